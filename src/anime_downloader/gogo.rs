@@ -1,6 +1,15 @@
 #![allow(dead_code)]
 
 use super::gogo_errors::*;
+use super::mirrors::{origin_of, Mirrors};
+use super::rate_limiter::{RateLimitConfig, RateLimiter};
+use super::streaming::{
+    find_playlist_url, find_subtitle_tracks, parse_streaming_servers, GogoCdnKeys, StreamingServer,
+    SubtitleTrack,
+};
+use crate::cache::{EntryKind, MetadataCache};
+use crate::credentials::Account;
+use crate::utils;
 use error_stack::{Report, ResultExt};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
@@ -9,17 +18,25 @@ use reqwest::header::HeaderValue;
 use reqwest::{self, Error};
 use scraper::selectable::Selectable;
 use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 use url::Url;
 use urlencoding::encode;
 
-#[derive(Debug)]
+/// Logins tried before giving up, each with a randomly picked account. A
+/// wrong password or a changed login form never succeeds.
+const LOGIN_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Anime {
     pub name: String,
     pub released: String,
     pub thumbnail: String,
     pub url: String,
+    pub language: Language,
 }
 
 impl fmt::Display for Anime {
@@ -35,21 +52,303 @@ impl Anime {
             released: released.to_string(),
             thumbnail: thumbnail.to_string(),
             url: url.to_string(),
+            language: Language::detect(name, url),
         }
     }
+
+    /// The name without the "(Dub)" suffix, shared by both variants of a show.
+    pub fn base_name(&self) -> &str {
+        strip_dub_suffix(&self.name)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub results: Vec<Anime>,
+    pub page: usize,
+    pub has_next_page: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimeType {
+    Tv,
+    Movie,
+    Ova,
+    Ona,
+    Special,
+}
+
+impl AnimeType {
+    fn query_value(&self) -> &'static str {
+        match self {
+            AnimeType::Tv => "1",
+            AnimeType::Special => "2",
+            AnimeType::Movie => "3",
+            AnimeType::Ova => "26",
+            AnimeType::Ona => "30",
+        }
+    }
+}
+
+impl FromStr for AnimeType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tv" => Ok(AnimeType::Tv),
+            "movie" => Ok(AnimeType::Movie),
+            "ova" => Ok(AnimeType::Ova),
+            "ona" => Ok(AnimeType::Ona),
+            "special" => Ok(AnimeType::Special),
+            _ => Err(format!(
+                "unknown anime type '{}' (expected tv, movie, ova, ona or special)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[serde(alias = "subbed")]
+    Sub,
+    #[serde(alias = "dubbed")]
+    Dub,
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Language::Sub => f.write_str("Sub"),
+            Language::Dub => f.write_str("Dub"),
+        }
+    }
+}
+
+impl Language {
+    // Gogo lists dubs as separate anime with a "(Dub)" suffix and a "-dub" slug.
+    fn detect(name: &str, url: &str) -> Self {
+        if strip_dub_suffix(name) != name.trim() || url.trim_end_matches('/').ends_with("-dub") {
+            Language::Dub
+        } else {
+            Language::Sub
+        }
+    }
+
+    fn query_value(&self) -> &'static str {
+        match self {
+            Language::Sub => "subbed",
+            Language::Dub => "dubbed",
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sub" | "subbed" => Ok(Language::Sub),
+            "dub" | "dubbed" => Ok(Language::Dub),
+            _ => Err(format!("unknown language '{}' (expected sub or dub)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiringStatus {
+    Upcoming,
+    Ongoing,
+    Completed,
+}
+
+impl AiringStatus {
+    fn query_value(&self) -> &'static str {
+        match self {
+            AiringStatus::Upcoming => "Upcoming",
+            AiringStatus::Ongoing => "Ongoing",
+            AiringStatus::Completed => "Completed",
+        }
+    }
+}
+
+impl FromStr for AiringStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "upcoming" => Ok(AiringStatus::Upcoming),
+            "ongoing" => Ok(AiringStatus::Ongoing),
+            "completed" => Ok(AiringStatus::Completed),
+            _ => Err(format!(
+                "unknown status '{}' (expected upcoming, ongoing or completed)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SearchSort {
+    #[default]
+    Relevance,
+    Name,
+    Year,
+}
+
+impl SearchSort {
+    fn query_value(&self) -> Option<&'static str> {
+        match self {
+            SearchSort::Relevance => None,
+            SearchSort::Name => Some("title_az"),
+            SearchSort::Year => Some("release_date"),
+        }
+    }
+
+    /// The site only sorts within a page, so the results of all the pages
+    /// fetched so far are sorted again together.
+    pub fn apply(&self, results: &mut [Anime]) {
+        match self {
+            SearchSort::Relevance => {}
+            SearchSort::Name => results.sort_by_key(|anime| anime.name.to_lowercase()),
+            SearchSort::Year => results
+                .sort_by_key(|anime| std::cmp::Reverse(anime.released.parse::<u32>().unwrap_or(0))),
+        }
+    }
+}
+
+impl FromStr for SearchSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "relevance" => Ok(SearchSort::Relevance),
+            "name" => Ok(SearchSort::Name),
+            "year" => Ok(SearchSort::Year),
+            _ => Err(format!(
+                "unknown sort order '{}' (expected relevance, name or year)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SearchFilters {
+    pub year: Option<u32>,
+    pub anime_type: Option<AnimeType>,
+    pub language: Option<Language>,
+    pub status: Option<AiringStatus>,
+    pub sort: SearchSort,
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        self.year.is_none()
+            && self.anime_type.is_none()
+            && self.language.is_none()
+            && self.status.is_none()
+            && self.sort == SearchSort::Relevance
+    }
+
+    fn to_query(&self) -> String {
+        let mut query = String::new();
+        if let Some(year) = self.year {
+            query.push_str(&format!("&year[]={}", year));
+        }
+        if let Some(anime_type) = self.anime_type {
+            query.push_str(&format!("&type[]={}", anime_type.query_value()));
+        }
+        if let Some(language) = self.language {
+            query.push_str(&format!("&language[]={}", language.query_value()));
+        }
+        if let Some(status) = self.status {
+            query.push_str(&format!("&status[]={}", status.query_value()));
+        }
+        if let Some(sort) = self.sort.query_value() {
+            query.push_str(&format!("&sort={}", sort));
+        }
+        query
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Season {
+    Winter,
+    Spring,
+    Summer,
+    Fall,
+}
+
+impl fmt::Display for Season {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let season = match self {
+            Season::Winter => "winter",
+            Season::Spring => "spring",
+            Season::Summer => "summer",
+            Season::Fall => "fall",
+        };
+        f.write_str(season)
+    }
+}
+
+impl FromStr for Season {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "winter" => Ok(Season::Winter),
+            "spring" => Ok(Season::Spring),
+            "summer" => Ok(Season::Summer),
+            "fall" | "autumn" => Ok(Season::Fall),
+            _ => Err(format!(
+                "unknown season '{}' (expected winter, spring, summer or fall)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AnimeDetailedInfo {
+    /// Gogo's ID of the show, it stays the same when its slug changes.
+    #[serde(default)]
+    pub anime_id: String,
     pub name: String,
+    pub language: Language,
     pub thumbnail: String,
     pub about: HashMap<String, String>,
     pub episode_links: Vec<String>,
 }
 
+impl AnimeDetailedInfo {
+    /// Whether new episodes are still coming out, going by the "Status" the
+    /// site lists.
+    pub fn is_airing(&self) -> bool {
+        is_airing(&self.about)
+    }
+}
+
+fn is_airing(about: &HashMap<String, String>) -> bool {
+    about.get("status").and_then(|status| status.parse().ok()) == Some(AiringStatus::Ongoing)
+}
+
+/// What an anime's page says, cached apart from its episode list so the list
+/// follows the shorter `episodes` TTL.
+#[derive(Debug, Serialize, Deserialize)]
+struct DetailsPage {
+    title: String,
+    thumbnail_url: String,
+    about: HashMap<String, String>,
+    end_ep: String,
+    anime_id: String,
+}
+
 impl fmt::Display for AnimeDetailedInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Name: {}", self.name)?;
+        writeln!(f, "Language: {}", self.language)?;
         writeln!(f, "Thumbnail: {}", self.thumbnail)?;
         writeln!(f, "About:")?;
         for (key, value) in &self.about {
@@ -61,81 +360,190 @@ impl fmt::Display for AnimeDetailedInfo {
 
 impl AnimeDetailedInfo {
     fn new(
+        anime_id: &str,
         name: &str,
+        language: Language,
         thumbnail: &str,
         about: &HashMap<String, String>,
         episode_links: &Vec<String>,
     ) -> Self {
         Self {
+            anime_id: anime_id.to_string(),
             name: name.to_string(),
+            language,
             thumbnail: thumbnail.to_string(),
             about: about.to_owned(),
             episode_links: episode_links.to_owned(),
         }
     }
+
+    /// Folder name that keeps the sub and dub of a show apart, e.g. "Naruto [Dub]".
+    pub fn folder_name(&self) -> String {
+        format!("{} [{}]", strip_dub_suffix(&self.name), self.language)
+    }
 }
 
 #[derive(Debug)]
 pub struct GogoAnime {
     client: reqwest::Client,
-    gogo_base_url: String,
+    mirrors: Mirrors,
+    login_lock: tokio::sync::Mutex<()>,
     fetch_ep_list_api: String,
-    password: String,
-    registered_account_emails: Vec<String>,
+    accounts: Vec<Account>,
     cookie_store: Arc<Jar>,
+    cache: Option<MetadataCache>,
+    rate_limiter: RateLimiter,
 }
 
 impl GogoAnime {
     pub fn new(
         gogo_base_url: &str,
         fetch_ep_list_api: &str,
-        password: &str,
-        registered_account_emails: Vec<String>,
+        accounts: Vec<Account>,
+        client_builder: reqwest::ClientBuilder,
     ) -> Self {
         let cookie_store = Arc::new(Jar::default());
-        let client = reqwest::Client::builder()
+        let client = client_builder
             .cookie_provider(cookie_store.clone())
             .build()
             .unwrap();
         Self {
             client,
-            gogo_base_url: gogo_base_url.to_string(),
+            mirrors: Mirrors::new(gogo_base_url, &[]),
+            login_lock: tokio::sync::Mutex::new(()),
             fetch_ep_list_api: fetch_ep_list_api.to_string(),
-            password: password.to_string(),
-            registered_account_emails,
+            accounts,
             cookie_store,
+            cache: None,
+            rate_limiter: RateLimiter::new(&RateLimitConfig::default()),
+        }
+    }
+
+    /// Domains to fall back to when the configured one is unreachable.
+    pub fn with_mirrors(mut self, mirrors: &[String]) -> Self {
+        self.mirrors = Mirrors::new(&self.mirrors.active(), mirrors);
+        self
+    }
+
+    /// The domain currently in use, e.g. "https://anitaku.bz".
+    pub fn base_url(&self) -> String {
+        self.mirrors.active()
+    }
+
+    pub fn with_rate_limit(mut self, config: &RateLimitConfig) -> Self {
+        self.rate_limiter = RateLimiter::new(config);
+        self
+    }
+
+    pub fn with_cache(mut self, cache: MetadataCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    fn cached<T: serde::de::DeserializeOwned>(&self, kind: EntryKind, url: &str) -> Option<T> {
+        self.cache.as_ref()?.get(kind, &self.mirrors.strip(url))
+    }
+
+    fn store<T: Serialize>(&self, kind: EntryKind, url: &str, value: &T) {
+        if let Some(cache) = &self.cache {
+            cache.insert(kind, &self.mirrors.strip(url), value);
         }
     }
+
+    /// Drops the cached download links of an episode, e.g. after they failed.
+    pub fn forget_ep_download_links(&self, ep_url: &str) {
+        if let Some(cache) = &self.cache {
+            cache.remove(EntryKind::DownloadLinks, &self.mirrors.strip(ep_url));
+        }
+    }
+
+    /// Finds a mirror that answers, starting with the active one, and follows
+    /// it if it redirects to a new domain.
+    pub async fn probe_mirrors(&self) -> Result<(), Report<GogoInitError>> {
+        for candidate in self.mirrors.candidates() {
+            let _permit = self.rate_limiter.acquire(&candidate).await;
+            let response = self
+                .client
+                .get(&candidate)
+                .timeout(Duration::from_secs(15))
+                .send()
+                .await;
+            match response {
+                Ok(response) if response.status().is_success() => {
+                    self.mirrors.activate(&origin_of(response.url().as_str()));
+                    return Ok(());
+                }
+                _ => continue,
+            }
+        }
+        Err(Report::new(GogoInitError).attach_printable(format!(
+            "None of the mirrors could be reached: {}",
+            self.mirrors.candidates().join(", ")
+        )))
+    }
+
+    fn is_logged_in(&self) -> bool {
+        Url::parse(&self.base_url())
+            .ok()
+            .and_then(|url| self.cookie_store.cookies(&url))
+            .is_some_and(|cookies| cookie_in(&cookies, "auth"))
+    }
+
     pub async fn init(&self) -> Result<(), Report<GogoInitError>> {
-        let login_url = format!("{}/login.html", self.gogo_base_url);
+        self.probe_mirrors().await?;
+        self.login().await
+    }
 
-        loop {
+    /// Logs in again, for long-running sessions the site may have expired
+    /// server-side while the cookie is still around.
+    pub async fn refresh_session(&self) -> Result<(), Report<GogoInitError>> {
+        let _guard = self.login_lock.lock().await;
+        // Otherwise the stale cookie passes for a successful login.
+        if let Ok(url) = Url::parse(&self.base_url()) {
+            self.cookie_store
+                .add_cookie_str("auth=; Max-Age=0; Path=/", &url);
+        }
+        self.login().await
+    }
+
+    async fn login(&self) -> Result<(), Report<GogoInitError>> {
+        let login_url = format!("{}/login.html", self.base_url());
+
+        for _ in 0..LOGIN_ATTEMPTS {
             let page_content = self
                 .fetch_content(&login_url)
                 .await
                 .change_context(GogoInitError)?;
 
-            let document = Html::parse_document(&page_content);
+            let csrf_token = {
+                let document = Html::parse_document(&page_content);
 
-            let selector =
-                Selector::parse(".form-login > form:nth-child(3) > input:nth-child(1)").unwrap();
+                let selector =
+                    Selector::parse(".form-login > form:nth-child(3) > input:nth-child(1)")
+                        .unwrap();
 
-            let csrf_token = document
-                .select(&selector)
-                .next()
-                .and_then(|node| node.attr("value"))
-                .ok_or_else(|| {
-                    Report::new(GogoInitError)
-                        .attach_printable(format!("CSRF token not found in {}", login_url))
-                })?;
+                document
+                    .select(&selector)
+                    .next()
+                    .and_then(|node| node.attr("value"))
+                    .ok_or_else(|| {
+                        Report::new(GogoInitError)
+                            .attach_printable(format!("CSRF token not found in {}", login_url))
+                    })?
+                    .to_string()
+            };
 
-            let mut rng = thread_rng();
-            let email = self.registered_account_emails.choose(&mut rng).unwrap();
+            let account = self.accounts.choose(&mut thread_rng()).ok_or_else(|| {
+                Report::new(GogoInitError).attach_printable("No accounts to log in with")
+            })?;
             let mut params = HashMap::new();
-            params.insert("email", email.as_str());
-            params.insert("password", self.password.as_str());
-            params.insert("_csrf", csrf_token);
+            params.insert("email", account.email.as_str());
+            params.insert("password", account.password.expose());
+            params.insert("_csrf", csrf_token.as_str());
 
+            // The login page may have failed over to another mirror.
+            let login_url = self.mirrors.rewrite(&login_url);
+            let _permit = self.rate_limiter.acquire(&login_url).await;
             self.client
                 .post(&login_url)
                 .form(&params)
@@ -143,32 +551,191 @@ impl GogoAnime {
                 .await
                 .change_context(GogoInitError)?;
 
-            let cookies = if let Some(c) = self
-                .cookie_store
-                .cookies(&Url::parse(&self.gogo_base_url).unwrap())
-            {
-                c
-            } else {
-                continue;
-            };
+            if self.is_logged_in() {
+                return Ok(());
+            }
+        }
 
-            if cookie_in(&cookies, "auth") {
+        Err(Report::new(GogoInitError).attach_printable(format!(
+            "Not logged in after {} attempts, check the accounts' passwords",
+            LOGIN_ATTEMPTS
+        )))
+    }
+
+    /// Follows the search pagination until `max_pages` pages were fetched or
+    /// the site runs out of results.
+    pub async fn search_anime_paginated(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        max_pages: usize,
+    ) -> Result<Vec<Anime>, Report<GogoSearchFailedError>> {
+        let mut search_results = Vec::new();
+        let mut page = 1;
+        while page <= max_pages {
+            let search_page = self.search_anime_page(query, filters, page).await?;
+            search_results.extend(search_page.results);
+            if !search_page.has_next_page {
                 break;
             }
+            page += 1;
         }
-
-        Ok(())
+        filters.sort.apply(&mut search_results);
+        Ok(search_results)
     }
 
-    pub async fn search_anime(
+    /// One page of results in the site's order, callers that show several
+    /// pages sort them together with [`SearchSort::apply`].
+    pub async fn search_anime_page(
         &self,
         query: &str,
-    ) -> Result<Vec<Anime>, Box<dyn std::error::Error>> {
-        let search_url = format!(
-            "{}/search.html?keyword={}",
-            self.gogo_base_url,
-            encode(query)
+        filters: &SearchFilters,
+        page: usize,
+    ) -> Result<SearchPage, Report<GogoSearchFailedError>> {
+        let search_url = if filters.is_empty() {
+            format!(
+                "{}/search.html?keyword={}&page={}",
+                self.base_url(),
+                encode(query),
+                page
+            )
+        } else {
+            format!(
+                "{}/filter.html?keyword={}{}&page={}",
+                self.base_url(),
+                encode(query),
+                filters.to_query(),
+                page
+            )
+        };
+        if let Some(search_page) = self.cached(EntryKind::Search, &search_url) {
+            return Ok(search_page);
+        }
+
+        let page_content = self
+            .fetch_content(&search_url)
+            .await
+            .change_context(GogoSearchFailedError)?;
+        let document = Html::parse_document(&page_content);
+
+        let results = self
+            .parse_anime_list(&document)
+            .change_context(GogoSearchFailedError)
+            .attach_printable_lazy(|| format!("Failed to parse results from {}", search_url))?;
+
+        let search_page = SearchPage {
+            results,
+            page,
+            has_next_page: has_next_page(&document, page),
+        };
+        self.store(EntryKind::Search, &search_url, &search_page);
+        Ok(search_page)
+    }
+
+    /// Looks up the sub or dub counterpart of `anime`. Returns `None` if the
+    /// site doesn't list that variant.
+    pub async fn find_variant(
+        &self,
+        anime: &Anime,
+        language: Language,
+    ) -> Result<Option<Anime>, Report<GogoSearchFailedError>> {
+        if anime.language == language {
+            return Ok(Some(anime.clone()));
+        }
+        let filters = SearchFilters {
+            language: Some(language),
+            ..Default::default()
+        };
+        let results = self
+            .search_anime_paginated(anime.base_name(), &filters, 1)
+            .await?;
+        Ok(results.into_iter().find(|candidate| {
+            candidate.language == language
+                && candidate
+                    .base_name()
+                    .eq_ignore_ascii_case(anime.base_name())
+        }))
+    }
+
+    /// Lists the most recently released episodes. The results point to the
+    /// anime pages, not to the episodes themselves.
+    pub async fn recent_releases(
+        &self,
+        page: usize,
+    ) -> Result<SearchPage, Report<GogoBrowseFailedError>> {
+        let url = format!("{}/home.html?page={}", self.base_url(), page);
+        let mut listing = self.fetch_anime_listing(&url, page).await?;
+        for anime in listing.results.iter_mut() {
+            anime.url = episode_url_to_anime_url(&anime.url);
+        }
+        Ok(listing)
+    }
+
+    pub async fn popular(&self, page: usize) -> Result<SearchPage, Report<GogoBrowseFailedError>> {
+        let url = format!("{}/popular.html?page={}", self.base_url(), page);
+        self.fetch_anime_listing(&url, page).await
+    }
+
+    pub async fn ongoing(&self, page: usize) -> Result<SearchPage, Report<GogoBrowseFailedError>> {
+        let url = format!("{}/ongoing-anime.html?page={}", self.base_url(), page);
+        self.fetch_anime_listing(&url, page).await
+    }
+
+    pub async fn genre(
+        &self,
+        genre: &str,
+        page: usize,
+    ) -> Result<SearchPage, Report<GogoBrowseFailedError>> {
+        let genre = genre.trim().to_lowercase().replace(' ', "-");
+        let url = format!("{}/genre/{}?page={}", self.base_url(), encode(&genre), page);
+        self.fetch_anime_listing(&url, page).await
+    }
+
+    pub async fn season(
+        &self,
+        season: Season,
+        year: u32,
+        page: usize,
+    ) -> Result<SearchPage, Report<GogoBrowseFailedError>> {
+        let url = format!(
+            "{}/sub-category/{}-{}-anime?page={}",
+            self.base_url(),
+            season,
+            year,
+            page
         );
+        self.fetch_anime_listing(&url, page).await
+    }
+
+    async fn fetch_anime_listing(
+        &self,
+        url: &str,
+        page: usize,
+    ) -> Result<SearchPage, Report<GogoBrowseFailedError>> {
+        if let Some(listing) = self.cached(EntryKind::Search, url) {
+            return Ok(listing);
+        }
+        let page_content = self
+            .fetch_content(url)
+            .await
+            .change_context(GogoBrowseFailedError)?;
+        let document = Html::parse_document(&page_content);
+
+        let results = self
+            .parse_anime_list(&document)
+            .change_context(GogoBrowseFailedError)
+            .attach_printable_lazy(|| format!("Failed to parse the listing from {}", url))?;
+
+        let listing = SearchPage {
+            results,
+            page,
+            has_next_page: has_next_page(&document, page),
+        };
+        self.store(EntryKind::Search, url, &listing);
+        Ok(listing)
+    }
+
+    fn parse_anime_list(&self, document: &Html) -> Result<Vec<Anime>, Report<GogoParseError>> {
         // Selectors
         let ul_selector_str = "ul.items";
         let li_selector_str = "li";
@@ -184,20 +751,17 @@ impl GogoAnime {
         let thumbnail_selector = Selector::parse(thumbnail_selector_str).unwrap();
         let url_selector = Selector::parse(url_selector_str).unwrap();
 
-        let page_content = self.fetch_content(&search_url).await?;
-        let document = Html::parse_document(&page_content);
-
         let ul_items = document.select(&ul_selector).next().ok_or_else(|| {
-            Report::new(GogoInitError)
-                .attach_printable(format!("{} not found in {}", ul_selector_str, search_url))
+            Report::new(GogoParseError)
+                .attach_printable(format!("Failed to locate {}", ul_selector_str))
         })?;
-        let mut search_results: Vec<Anime> = Vec::new();
+        let mut anime_list: Vec<Anime> = Vec::new();
         for li_element in ul_items.select(&li_selector) {
             let anime_name = li_element
                 .select(&name_selector)
                 .next()
                 .ok_or_else(|| {
-                    Report::new(GogoSearchFailedError).attach_printable(format!(
+                    Report::new(GogoParseError).attach_printable(format!(
                         "Failed to locate anime name with {}",
                         name_selector_str
                     ))
@@ -205,191 +769,258 @@ impl GogoAnime {
                 .text()
                 .collect::<Vec<_>>()
                 .concat();
+            // Release dates are missing on some listings (e.g. recent releases).
             let released = li_element
                 .select(&released_selector)
                 .next()
-                .ok_or_else(|| {
-                    Report::new(GogoSearchFailedError).attach_printable(format!(
-                        "Failed to locate anime release date with {}",
-                        released_selector_str
-                    ))
-                })?
-                .text()
-                .collect::<Vec<_>>()
-                .concat();
+                .map(|node| node.text().collect::<Vec<_>>().concat())
+                .unwrap_or_default();
             let released = released.trim().to_string();
             let thumbnail = li_element
                 .select(&thumbnail_selector)
                 .next()
                 .ok_or_else(|| {
-                    Report::new(GogoSearchFailedError).attach_printable(format!(
+                    Report::new(GogoParseError).attach_printable(format!(
                         "Failed to locate anime thumbnail with {}",
                         thumbnail_selector_str
                     ))
                 })?
                 .attr("src")
-                .unwrap();
+                .unwrap_or_default();
             let anime_url = li_element
                 .select(&url_selector)
                 .next()
                 .ok_or_else(|| {
-                    Report::new(GogoSearchFailedError).attach_printable(format!(
+                    Report::new(GogoParseError).attach_printable(format!(
                         "Failed to locate anime url with {}",
                         url_selector_str
                     ))
                 })?
                 .attr("href")
-                .unwrap();
+                .unwrap_or_default();
 
             let anime = Anime::new(
-                &anime_name,
+                anime_name.trim(),
                 released
                     .split_once(": ")
                     .unwrap_or(("", released.as_str()))
                     .1,
-                &thumbnail,
-                &format!("{}{}", self.gogo_base_url, anime_url),
+                thumbnail,
+                &format!("{}{}", self.base_url(), anime_url),
             );
-            search_results.push(anime);
+            anime_list.push(anime);
         }
-        Ok(search_results)
+        Ok(anime_list)
     }
 
     async fn fetch_content(&self, url: &str) -> Result<String, Report<Error>> {
-        let response = self.client.get(url).send().await?;
+        let mut attempts = 0;
+        loop {
+            let url = self.mirrors.rewrite(url);
+            let _permit = self.rate_limiter.acquire(&url).await;
+            let response = match self.client.get(&url).send().await {
+                Ok(response) => response,
+                Err(err)
+                    if (err.is_connect() || err.is_timeout())
+                        && self.mirrors.is_mirror(&url)
+                        && self.mirrors.fail_over(&url, attempts) =>
+                {
+                    attempts += 1;
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
 
-        Ok(response.text().await?)
+            // The site moved to a new domain and redirected us there.
+            let final_origin = origin_of(response.url().as_str());
+            if self.mirrors.is_mirror(&url) && final_origin != origin_of(&url) {
+                self.mirrors.activate(&final_origin);
+            }
+
+            return Ok(response.text().await?);
+        }
     }
 
     pub async fn fetch_detailed_anime_info(
         &self,
         anime_url: &str,
     ) -> Result<AnimeDetailedInfo, Report<GogoFetchingDetailsFailed>> {
-        let page_content = self
-            .fetch_content(anime_url)
-            .await
-            .change_context(GogoFetchingDetailsFailed)?;
-        let document = Html::parse_document(&page_content);
-
-        let anime_info_body_selector_str = "div.anime_info_body";
-        let anime_thumbnail_selector_str = "img";
-        let anime_name_selector_str = "h1";
-        let p_selector_str = "p.type";
-        let description_selector_str = ".description";
-        let total_eps_selector_str = ".active";
-        let movie_id_selector_str = "input#movie_id";
-
-        let anime_info_body_selector = Selector::parse(anime_info_body_selector_str).unwrap();
-        let anime_thumbnail_selector = Selector::parse(anime_thumbnail_selector_str).unwrap();
-        let anime_name_selector = Selector::parse(anime_name_selector_str).unwrap();
-        let p_selector = Selector::parse(p_selector_str).unwrap();
-        let description_selector = Selector::parse(description_selector_str).unwrap();
-        let end_ep_selector = Selector::parse(total_eps_selector_str).unwrap();
-        let movie_id_selector = Selector::parse(movie_id_selector_str).unwrap();
-
-        let failed_to_locate_msg = |target: &str, selector: &str| -> String {
-            format!(
-                "Failed to locate {} with {} from {}",
-                target, selector, anime_url
-            )
-        };
-        let anime_info_body = document
-            .select(&anime_info_body_selector)
-            .next()
-            .ok_or_else(|| {
-                Report::new(GogoFetchingDetailsFailed).attach_printable(failed_to_locate_msg(
-                    "anime_info_body",
-                    anime_info_body_selector_str,
-                ))
-            })?;
-
-        let thumbnail_url = anime_info_body
-            .select(&anime_thumbnail_selector)
-            .next()
-            .ok_or_else(|| {
-                Report::new(GogoFetchingDetailsFailed).attach_printable(failed_to_locate_msg(
-                    "thumbnail_url",
-                    anime_thumbnail_selector_str,
-                ))
-            })?
-            .value()
-            .attr("src")
-            .unwrap()
-            .to_string();
-        let title = anime_info_body
-            .select(&anime_name_selector)
-            .next()
-            .ok_or_else(|| {
-                Report::new(GogoFetchingDetailsFailed)
-                    .attach_printable(failed_to_locate_msg("anime title", anime_name_selector_str))
-            })?
-            .inner_html();
-        let mut about_anime: HashMap<String, String> = HashMap::new();
-
-        for p_tag in anime_info_body.select(&p_selector) {
-            let raw_text = p_tag.text().collect::<Vec<_>>().concat().to_string();
-
-            let (key, value) = raw_text.split_once(":").unwrap();
-            let (key, mut value) = (
-                key.to_lowercase().replace(" ", "_").to_string(),
-                value.trim().to_string(),
-            );
-
-            if key == "plot_summary" {
-                let raw_desc = anime_info_body
-                    .select(&description_selector)
-                    .next()
-                    .ok_or_else(|| {
-                        Report::new(GogoFetchingDetailsFailed).attach_printable(
-                            failed_to_locate_msg("description", &description_selector_str),
-                        )
-                    })?;
-                value = raw_desc
-                    .text()
-                    .collect::<Vec<_>>()
-                    .concat()
-                    .replace("\n\n ", "\n\n")
-                    .to_string();
+        let page = match self.cached::<DetailsPage>(EntryKind::Details, anime_url) {
+            // The page of an airing show has the number of the newest
+            // episode, its episode list is only as fresh as the page.
+            Some(page)
+                if !is_airing(&page.about)
+                    || self
+                        .cached::<Vec<String>>(EntryKind::Episodes, &self.ep_list_url(&page))
+                        .is_some() =>
+            {
+                page
             }
-
-            about_anime.insert(key, value);
-        }
-        let end_ep = document
-            .select(&end_ep_selector)
-            .next()
-            .ok_or_else(|| {
-                Report::new(GogoFetchingDetailsFailed)
-                    .attach_printable(failed_to_locate_msg("total eps", total_eps_selector_str))
-            })?
-            .attr("ep_end")
-            .unwrap();
-        let anime_id = document
-            .select(&movie_id_selector)
-            .next()
-            .unwrap()
-            .attr("value")
-            .unwrap();
+            _ => {
+                let page = self.fetch_details_page(anime_url).await?;
+                self.store(EntryKind::Details, anime_url, &page);
+                page
+            }
+        };
         let episode_links = self
-            .fetch_anime_ep_links(anime_id, end_ep)
+            .fetch_anime_ep_links(&page)
             .await
             .change_context(GogoFetchingDetailsFailed)?;
 
         Ok(AnimeDetailedInfo::new(
-            &title,
-            &thumbnail_url,
-            &about_anime,
+            &page.anime_id,
+            &page.title,
+            Language::detect(&page.title, anime_url),
+            &page.thumbnail_url,
+            &page.about,
             &episode_links,
         ))
     }
-    async fn fetch_anime_ep_links(
+
+    /// Drops the cached details of an anime, the next fetch sees new
+    /// episodes right away.
+    pub fn forget_anime_details(&self, anime_url: &str) {
+        if let Some(cache) = &self.cache {
+            cache.remove(EntryKind::Details, &self.mirrors.strip(anime_url));
+        }
+    }
+
+    async fn fetch_details_page(
         &self,
-        anime_id: &str,
-        end_ep: &str,
-    ) -> Result<Vec<String>, Report<Error>> {
-        let url = self
-            .fetch_ep_list_api
-            .replace("{END_EP}", end_ep)
-            .replace("{ANIME_ID}", anime_id);
+        anime_url: &str,
+    ) -> Result<DetailsPage, Report<GogoFetchingDetailsFailed>> {
+        let page_content = self
+            .fetch_content(anime_url)
+            .await
+            .change_context(GogoFetchingDetailsFailed)?;
+        // `Html` isn't `Send`, it's dropped before the next request.
+        let page = {
+            let document = Html::parse_document(&page_content);
+
+            let anime_info_body_selector_str = "div.anime_info_body";
+            let anime_thumbnail_selector_str = "img";
+            let anime_name_selector_str = "h1";
+            let p_selector_str = "p.type";
+            let description_selector_str = ".description";
+            let total_eps_selector_str = ".active";
+            let movie_id_selector_str = "input#movie_id";
+
+            let anime_info_body_selector = Selector::parse(anime_info_body_selector_str).unwrap();
+            let anime_thumbnail_selector = Selector::parse(anime_thumbnail_selector_str).unwrap();
+            let anime_name_selector = Selector::parse(anime_name_selector_str).unwrap();
+            let p_selector = Selector::parse(p_selector_str).unwrap();
+            let description_selector = Selector::parse(description_selector_str).unwrap();
+            let end_ep_selector = Selector::parse(total_eps_selector_str).unwrap();
+            let movie_id_selector = Selector::parse(movie_id_selector_str).unwrap();
+
+            let failed_to_locate_msg = |target: &str, selector: &str| -> String {
+                format!(
+                    "Failed to locate {} with {} from {}",
+                    target, selector, anime_url
+                )
+            };
+            let anime_info_body = document
+                .select(&anime_info_body_selector)
+                .next()
+                .ok_or_else(|| {
+                    Report::new(GogoFetchingDetailsFailed).attach_printable(failed_to_locate_msg(
+                        "anime_info_body",
+                        anime_info_body_selector_str,
+                    ))
+                })?;
+
+            let thumbnail_url = anime_info_body
+                .select(&anime_thumbnail_selector)
+                .next()
+                .ok_or_else(|| {
+                    Report::new(GogoFetchingDetailsFailed).attach_printable(failed_to_locate_msg(
+                        "thumbnail_url",
+                        anime_thumbnail_selector_str,
+                    ))
+                })?
+                .value()
+                .attr("src")
+                .unwrap()
+                .to_string();
+            let title = anime_info_body
+                .select(&anime_name_selector)
+                .next()
+                .ok_or_else(|| {
+                    Report::new(GogoFetchingDetailsFailed).attach_printable(failed_to_locate_msg(
+                        "anime title",
+                        anime_name_selector_str,
+                    ))
+                })?
+                .inner_html();
+            let mut about_anime: HashMap<String, String> = HashMap::new();
+
+            for p_tag in anime_info_body.select(&p_selector) {
+                let raw_text = p_tag.text().collect::<Vec<_>>().concat().to_string();
+
+                let (key, value) = raw_text.split_once(":").unwrap();
+                let (key, mut value) = (
+                    key.to_lowercase().replace(" ", "_").to_string(),
+                    value.trim().to_string(),
+                );
+
+                if key == "plot_summary" {
+                    let raw_desc = anime_info_body
+                        .select(&description_selector)
+                        .next()
+                        .ok_or_else(|| {
+                            Report::new(GogoFetchingDetailsFailed).attach_printable(
+                                failed_to_locate_msg("description", description_selector_str),
+                            )
+                        })?;
+                    value = raw_desc
+                        .text()
+                        .collect::<Vec<_>>()
+                        .concat()
+                        .replace("\n\n ", "\n\n")
+                        .to_string();
+                }
+
+                about_anime.insert(key, value);
+            }
+            let end_ep = document
+                .select(&end_ep_selector)
+                .next()
+                .ok_or_else(|| {
+                    Report::new(GogoFetchingDetailsFailed)
+                        .attach_printable(failed_to_locate_msg("total eps", total_eps_selector_str))
+                })?
+                .attr("ep_end")
+                .unwrap()
+                .to_string();
+            let anime_id = document
+                .select(&movie_id_selector)
+                .next()
+                .unwrap()
+                .attr("value")
+                .unwrap()
+                .to_string();
+            DetailsPage {
+                title,
+                thumbnail_url,
+                about: about_anime,
+                end_ep,
+                anime_id,
+            }
+        };
+        Ok(page)
+    }
+
+    fn ep_list_url(&self, page: &DetailsPage) -> String {
+        self.fetch_ep_list_api
+            .replace("{END_EP}", &page.end_ep)
+            .replace("{ANIME_ID}", &page.anime_id)
+    }
+
+    async fn fetch_anime_ep_links(&self, page: &DetailsPage) -> Result<Vec<String>, Report<Error>> {
+        let url = self.ep_list_url(page);
+        if let Some(episodes) = self.cached(EntryKind::Episodes, &url) {
+            return Ok(episodes);
+        }
         let page_content = self.fetch_content(&url).await?;
         let document = Html::parse_document(&page_content);
         let episodes_selector = Selector::parse("#episode_related").unwrap();
@@ -405,7 +1036,7 @@ impl GogoAnime {
         {
             let url = format!(
                 "{}{}",
-                self.gogo_base_url,
+                self.base_url(),
                 li.select(&Selector::parse("a").unwrap())
                     .next()
                     .unwrap()
@@ -417,43 +1048,166 @@ impl GogoAnime {
             episodes.push(url);
         }
 
+        self.store(EntryKind::Episodes, &url, &episodes);
         Ok(episodes)
     }
-    pub async fn fetch_ep_download_links(
+    /// Every way to get an episode, best first: the download links closest
+    /// to `pref_res` (the same resolution on other hosts before the next-best
+    /// one), then the streaming servers.
+    pub async fn episode_sources(
         &self,
-        anime_url: &str,
-    ) -> Result<HashMap<String, String>, Report<GogoFailedToFetchDownloadLinks>> {
+        ep_url: &str,
+        pref_res: &str,
+    ) -> Result<Vec<EpisodeSource>, Report<GogoFailedToFetchDownloadLinks>> {
+        let cached = (
+            self.cached::<Vec<DownloadLink>>(EntryKind::DownloadLinks, ep_url),
+            self.cached::<Vec<StreamingServer>>(EntryKind::DownloadLinks, &servers_key(ep_url)),
+        );
+        let (mut download_links, servers) = match cached {
+            (Some(download_links), Some(servers)) => (download_links, servers),
+            _ => self.fetch_episode_page(ep_url).await?,
+        };
+        utils::sort_by_resolution(&mut download_links, pref_res, |link| &link.resolution);
+        Ok(download_links
+            .into_iter()
+            .map(EpisodeSource::Direct)
+            .chain(servers.into_iter().map(EpisodeSource::Stream))
+            .collect())
+    }
+
+    /// The download links and the streaming servers of an episode, both from
+    /// one fetch of its page. Only the download links need a login, without
+    /// one the servers are still used.
+    async fn fetch_episode_page(
+        &self,
+        ep_url: &str,
+    ) -> Result<(Vec<DownloadLink>, Vec<StreamingServer>), Report<GogoFailedToFetchDownloadLinks>>
+    {
+        // The session is per domain, so log in again after a failover.
+        let mut logged_in = Ok(());
+        if !self.is_logged_in() {
+            let _guard = self.login_lock.lock().await;
+            if !self.is_logged_in() {
+                logged_in = self.login().await;
+            }
+        }
         let page_content = self
-            .fetch_content(anime_url)
+            .fetch_content(ep_url)
             .await
             .change_context(GogoFailedToFetchDownloadLinks)?;
         let document = Html::parse_document(&page_content);
-        let cf_download_selector_str = ".cf-download";
-        let cf_download_selector = Selector::parse(cf_download_selector_str).unwrap();
-        let cf_download = document
-            .select(&cf_download_selector)
-            .next()
-            .ok_or_else(|| {
-                Report::new(GogoFailedToFetchDownloadLinks)
-                    .attach_printable(format!(
-                        "Download links not find using {} from {}",
-                        cf_download_selector_str, anime_url
+
+        let servers = parse_streaming_servers(&document);
+        self.store(EntryKind::DownloadLinks, &servers_key(ep_url), &servers);
+        let download_links = logged_in
+            .change_context(GogoFailedToFetchDownloadLinks)
+            .and_then(|()| parse_download_links(&document, ep_url));
+        match download_links {
+            Ok(download_links) => {
+                self.store(EntryKind::DownloadLinks, ep_url, &download_links);
+                Ok((download_links, servers))
+            }
+            Err(report) if servers.is_empty() => {
+                Err(report.attach_printable(format!("No streaming servers found on {}", ep_url)))
+            }
+            Err(_) => Ok((Vec::new(), servers)),
+        }
+    }
+
+    /// Finds the HLS playlist `server` plays. Gogo's own player encrypts it,
+    /// for the others it's looked up in the embed page as is.
+    pub async fn resolve_stream(
+        &self,
+        server: &StreamingServer,
+    ) -> Result<ResolvedStream, Report<GogoStreamError>> {
+        let embed_page = self
+            .fetch_content(&server.embed_url)
+            .await
+            .change_context(GogoStreamError)
+            .attach_printable_lazy(|| format!("Failed to fetch {}", server.embed_url))?;
+        let referer = format!("{}/", origin_of(&server.embed_url));
+
+        let (playlist_url, subtitles) = match GogoCdnKeys::from_embed_page(&embed_page) {
+            Some(keys) => {
+                let ajax_url = keys.ajax_url(&server.embed_url).ok_or_else(|| {
+                    Report::new(GogoStreamError).attach_printable(format!(
+                        "Failed to build the source request for {}",
+                        server.embed_url
                     ))
-                    .attach_printable("Did you initalize gogoanime?")
-            })?;
+                })?;
+                let _permit = self.rate_limiter.acquire(&ajax_url).await;
+                let response = self
+                    .client
+                    .get(&ajax_url)
+                    .header("X-Requested-With", "XMLHttpRequest")
+                    .header("Referer", &server.embed_url)
+                    .send()
+                    .await
+                    .change_context(GogoStreamError)?
+                    .text()
+                    .await
+                    .change_context(GogoStreamError)?;
+                let (playlists, subtitles) = keys.sources(&response).unwrap_or_default();
+                (playlists.into_iter().next(), subtitles)
+            }
+            None => (
+                find_playlist_url(&embed_page),
+                find_subtitle_tracks(&embed_page),
+            ),
+        };
 
-        let mut download_links = HashMap::new();
-        for a in cf_download.select(&Selector::parse("a").unwrap()) {
-            let res = a.text().collect::<Vec<_>>().concat().trim().to_string();
-            let link = a.value().attr("href").unwrap().to_string();
+        let playlist_url = playlist_url.ok_or_else(|| {
+            Report::new(GogoStreamError).attach_printable(format!(
+                "No HLS playlist found for the {} server at {}",
+                server.name, server.embed_url
+            ))
+        })?;
+        Ok(ResolvedStream {
+            playlist_url,
+            referer,
+            subtitles,
+        })
+    }
+}
 
-            download_links.insert(res, link);
-        }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadLink {
+    /// As labeled on the page, usually WIDTHxHEIGHT.
+    pub resolution: String,
+    pub url: String,
+}
 
-        Ok(download_links)
+/// One place an episode can be downloaded from.
+#[derive(Debug, Clone)]
+pub enum EpisodeSource {
+    Direct(DownloadLink),
+    Stream(StreamingServer),
+}
+
+impl fmt::Display for EpisodeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpisodeSource::Direct(link) => {
+                let host = Url::parse(&link.url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_else(|| link.url.clone());
+                write!(f, "{} from {}", link.resolution, host)
+            }
+            EpisodeSource::Stream(server) => write!(f, "{} stream", server.name),
+        }
     }
 }
 
+/// An HLS playlist and the page its segments expect to be requested from.
+#[derive(Debug, Clone)]
+pub struct ResolvedStream {
+    pub playlist_url: String,
+    pub referer: String,
+    /// Tracks the player loads next to the stream, the playlist may list more.
+    pub subtitles: Vec<SubtitleTrack>,
+}
+
 fn cookie_in(header_value: &HeaderValue, cookie_name: &str) -> bool {
     let header_str = header_value.to_str().unwrap();
 
@@ -470,3 +1224,71 @@ fn cookie_in(header_value: &HeaderValue, cookie_name: &str) -> bool {
 
     false
 }
+
+fn strip_dub_suffix(name: &str) -> &str {
+    let name = name.trim();
+    match name.len().checked_sub("(Dub)".len()) {
+        Some(idx) if name.is_char_boundary(idx) && name[idx..].eq_ignore_ascii_case("(Dub)") => {
+            name[..idx].trim_end()
+        }
+        _ => name,
+    }
+}
+
+/// Where the streaming servers of an episode are cached, next to its
+/// download links.
+fn servers_key(ep_url: &str) -> String {
+    format!("{}#servers", ep_url)
+}
+
+fn parse_download_links(
+    document: &Html,
+    ep_url: &str,
+) -> Result<Vec<DownloadLink>, Report<GogoFailedToFetchDownloadLinks>> {
+    let cf_download_selector_str = ".cf-download";
+    let cf_download_selector = Selector::parse(cf_download_selector_str).unwrap();
+    let cf_download = document
+        .select(&cf_download_selector)
+        .next()
+        .ok_or_else(|| {
+            Report::new(GogoFailedToFetchDownloadLinks)
+                .attach_printable(format!(
+                    "Download links not find using {} from {}",
+                    cf_download_selector_str, ep_url
+                ))
+                .attach_printable("Did you initalize gogoanime?")
+        })?;
+
+    let mut download_links = Vec::new();
+    for a in cf_download.select(&Selector::parse("a").unwrap()) {
+        let resolution = a.text().collect::<Vec<_>>().concat().trim().to_string();
+        let Some(url) = a.value().attr("href") else {
+            continue;
+        };
+        download_links.push(DownloadLink {
+            resolution,
+            url: url.to_string(),
+        });
+    }
+    Ok(download_links)
+}
+
+fn has_next_page(document: &Html, current_page: usize) -> bool {
+    let page_selector = Selector::parse("ul.pagination-list li a").unwrap();
+    document.select(&page_selector).any(|a| {
+        a.attr("data-page")
+            .and_then(|page| page.parse::<usize>().ok())
+            .is_some_and(|page| page > current_page)
+    })
+}
+
+// "/one-piece-episode-1100" -> "/category/one-piece"
+fn episode_url_to_anime_url(episode_url: &str) -> String {
+    let Some((base, slug)) = episode_url.rsplit_once('/') else {
+        return episode_url.to_string();
+    };
+    match slug.rsplit_once("-episode-") {
+        Some((anime_slug, _)) => format!("{}/category/{}", base, anime_slug),
+        None => episode_url.to_string(),
+    }
+}
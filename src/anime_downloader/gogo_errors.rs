@@ -44,3 +44,36 @@ impl fmt::Display for GogoFailedToFetchDownloadLinks {
 }
 
 impl Context for GogoFailedToFetchDownloadLinks {}
+
+#[derive(Debug)]
+pub struct GogoParseError;
+
+impl fmt::Display for GogoParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Failed to parse the page returned by Gogo")
+    }
+}
+
+impl Context for GogoParseError {}
+
+#[derive(Debug)]
+pub struct GogoBrowseFailedError;
+
+impl fmt::Display for GogoBrowseFailedError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Failed to fetch the anime listing from Gogo")
+    }
+}
+
+impl Context for GogoBrowseFailedError {}
+
+#[derive(Debug)]
+pub struct GogoStreamError;
+
+impl fmt::Display for GogoStreamError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Failed to resolve a streaming source")
+    }
+}
+
+impl Context for GogoStreamError {}
@@ -0,0 +1,265 @@
+use std::sync::RwLock;
+use url::Url;
+
+/// The domains Gogo is reachable under, one of them being the active one.
+///
+/// Every URL built or stored by the tool carries a domain, so URLs are
+/// rewritten to the active mirror right before they're requested. That keeps
+/// cached entries and queued episodes valid after a domain change.
+#[derive(Debug)]
+pub struct Mirrors {
+    state: RwLock<MirrorState>,
+}
+
+#[derive(Debug)]
+struct MirrorState {
+    candidates: Vec<String>,
+    active: usize,
+}
+
+impl Mirrors {
+    pub fn new(primary: &str, mirrors: &[String]) -> Self {
+        let mut candidates = vec![origin_of(primary)];
+        for mirror in mirrors {
+            let mirror = origin_of(mirror);
+            if !candidates.contains(&mirror) {
+                candidates.push(mirror);
+            }
+        }
+        Self {
+            state: RwLock::new(MirrorState {
+                candidates,
+                active: 0,
+            }),
+        }
+    }
+
+    pub fn active(&self) -> String {
+        let state = self.state.read().unwrap();
+        state.candidates[state.active].clone()
+    }
+
+    /// Every candidate, starting with the active one.
+    pub fn candidates(&self) -> Vec<String> {
+        let state = self.state.read().unwrap();
+        let (before, after) = state.candidates.split_at(state.active);
+        after.iter().chain(before).cloned().collect()
+    }
+
+    /// Makes `origin` the active mirror, adding it if it's a new domain the
+    /// site redirected to.
+    pub fn activate(&self, origin: &str) {
+        let origin = origin_of(origin);
+        let mut state = self.state.write().unwrap();
+        match state.candidates.iter().position(|c| *c == origin) {
+            Some(idx) => state.active = idx,
+            None => {
+                state.candidates.insert(0, origin);
+                state.active = 0;
+            }
+        }
+    }
+
+    /// Moves on to the next candidate after `failed_url` couldn't be reached,
+    /// `attempts` being the number of failures before this one. Returns
+    /// `false` once every candidate was tried.
+    pub fn fail_over(&self, failed_url: &str, attempts: usize) -> bool {
+        let mut state = self.state.write().unwrap();
+        if attempts + 1 >= state.candidates.len() {
+            return false;
+        }
+        if origin_of(failed_url) == state.candidates[state.active] {
+            state.active = (state.active + 1) % state.candidates.len();
+        }
+        true
+    }
+
+    /// Points `url` at the active mirror if it belongs to any known one.
+    pub fn rewrite(&self, url: &str) -> String {
+        let state = self.state.read().unwrap();
+        let origin = origin_of(url);
+        if url.starts_with(&origin) && state.candidates.contains(&origin) {
+            format!("{}{}", state.candidates[state.active], &url[origin.len()..])
+        } else {
+            url.to_string()
+        }
+    }
+
+    /// `url` without the mirror's domain, so cache keys survive domain changes.
+    pub fn strip(&self, url: &str) -> String {
+        let state = self.state.read().unwrap();
+        let origin = origin_of(url);
+        if url.starts_with(&origin) && state.candidates.contains(&origin) {
+            url[origin.len()..].to_string()
+        } else {
+            url.to_string()
+        }
+    }
+
+    /// Whether `url` points at one of the mirrors.
+    pub fn is_mirror(&self, url: &str) -> bool {
+        self.state
+            .read()
+            .unwrap()
+            .candidates
+            .contains(&origin_of(url))
+    }
+}
+
+/// "https://anitaku.bz/category/naruto" -> "https://anitaku.bz"
+pub fn origin_of(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(parsed) if parsed.has_host() => parsed.origin().ascii_serialization(),
+        _ => url.trim_end_matches('/').to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirrors() -> Mirrors {
+        Mirrors::new(
+            "https://anitaku.bz/",
+            &[
+                "https://gogoanime3.co".to_string(),
+                "https://anitaku.bz".to_string(),
+                "http://gogotaku.info/home".to_string(),
+            ],
+        )
+    }
+
+    #[test]
+    fn dedups_candidates_by_origin() {
+        assert_eq!(
+            mirrors().candidates(),
+            [
+                "https://anitaku.bz",
+                "https://gogoanime3.co",
+                "http://gogotaku.info"
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_between_mirrors() {
+        let mirrors = mirrors();
+        let url = "https://gogoanime3.co/category/naruto?page=2";
+        assert_eq!(
+            mirrors.rewrite(url),
+            "https://anitaku.bz/category/naruto?page=2"
+        );
+        assert_eq!(mirrors.strip(url), "/category/naruto?page=2");
+
+        mirrors.activate("http://gogotaku.info/");
+        assert_eq!(
+            mirrors.rewrite(url),
+            "http://gogotaku.info/category/naruto?page=2"
+        );
+        assert_eq!(
+            mirrors.rewrite("https://anitaku.bz/naruto-episode-1"),
+            "http://gogotaku.info/naruto-episode-1"
+        );
+        assert_eq!(
+            mirrors.strip("https://anitaku.bz/naruto-episode-1"),
+            "/naruto-episode-1"
+        );
+    }
+
+    #[test]
+    fn leaves_other_hosts_alone() {
+        let mirrors = mirrors();
+        let embed = "https://embtaku.pro/streaming.php?id=MTIz";
+        assert_eq!(mirrors.rewrite(embed), embed);
+        assert_eq!(mirrors.strip(embed), embed);
+        assert!(!mirrors.is_mirror(embed));
+        // A different scheme or port is a different origin.
+        assert_eq!(
+            mirrors.rewrite("http://anitaku.bz/home"),
+            "http://anitaku.bz/home"
+        );
+        assert_eq!(
+            mirrors.rewrite("https://anitaku.bz:8443/home"),
+            "https://anitaku.bz:8443/home"
+        );
+    }
+
+    #[test]
+    fn fails_over_in_order_until_every_mirror_was_tried() {
+        let mirrors = mirrors();
+        assert!(mirrors.fail_over("https://anitaku.bz/home", 0));
+        assert_eq!(mirrors.active(), "https://gogoanime3.co");
+        assert!(mirrors.fail_over("https://gogoanime3.co/home", 1));
+        assert_eq!(mirrors.active(), "http://gogotaku.info");
+        assert!(!mirrors.fail_over("http://gogotaku.info/home", 2));
+        assert_eq!(mirrors.active(), "http://gogotaku.info");
+        assert_eq!(
+            mirrors.candidates(),
+            [
+                "http://gogotaku.info",
+                "https://anitaku.bz",
+                "https://gogoanime3.co"
+            ]
+        );
+    }
+
+    #[test]
+    fn fail_over_keeps_a_mirror_another_request_switched_to() {
+        let mirrors = mirrors();
+        assert!(mirrors.fail_over("https://anitaku.bz/home", 0));
+        // A request that started before the switch fails late.
+        assert!(mirrors.fail_over("https://anitaku.bz/search.html", 0));
+        assert_eq!(mirrors.active(), "https://gogoanime3.co");
+    }
+
+    #[test]
+    fn a_single_mirror_is_not_retried() {
+        let mirrors = Mirrors::new("https://anitaku.bz", &[]);
+        assert!(!mirrors.fail_over("https://anitaku.bz/home", 0));
+        assert_eq!(mirrors.active(), "https://anitaku.bz");
+    }
+
+    #[test]
+    fn activates_redirect_targets() {
+        let mirrors = mirrors();
+        mirrors.activate(&origin_of("https://gogoanime3.co/category/naruto"));
+        assert_eq!(mirrors.active(), "https://gogoanime3.co");
+        assert_eq!(mirrors.candidates().len(), 3);
+
+        // A domain the site moved to is added in front.
+        mirrors.activate(&origin_of("https://anitaku.to/category/naruto?page=2"));
+        assert_eq!(mirrors.active(), "https://anitaku.to");
+        assert!(mirrors.is_mirror("https://anitaku.to/home"));
+        assert_eq!(
+            mirrors.rewrite("https://anitaku.bz/category/naruto"),
+            "https://anitaku.to/category/naruto"
+        );
+        assert_eq!(
+            mirrors.candidates(),
+            [
+                "https://anitaku.to",
+                "https://anitaku.bz",
+                "https://gogoanime3.co",
+                "http://gogotaku.info"
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_of_redirect_targets() {
+        assert_eq!(
+            origin_of("https://anitaku.to/category/naruto?page=2#top"),
+            "https://anitaku.to"
+        );
+        assert_eq!(origin_of("https://ANITAKU.to/"), "https://anitaku.to");
+        assert_eq!(
+            origin_of("https://anitaku.to:443/home"),
+            "https://anitaku.to"
+        );
+        assert_eq!(
+            origin_of("http://anitaku.to:8080/home"),
+            "http://anitaku.to:8080"
+        );
+        assert_eq!(origin_of("anitaku.to/"), "anitaku.to");
+    }
+}
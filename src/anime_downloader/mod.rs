@@ -1,2 +1,5 @@
 pub mod gogo;
 pub mod gogo_errors;
+pub mod mirrors;
+pub mod rate_limiter;
+pub mod streaming;
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep_until, Instant};
+use url::Url;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Requests started per second against a single host, 0 disables the limit.
+    pub requests_per_second: f64,
+    /// Page requests allowed to be in flight against a single host at once.
+    pub max_in_flight: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 2.0,
+            max_in_flight: 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HostLimiter {
+    in_flight: Arc<Semaphore>,
+    next_slot: tokio::sync::Mutex<Instant>,
+}
+
+/// Spaces out page requests per host so the site doesn't start blocking us.
+/// Media downloads are not routed through this, they have their own limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    min_interval: Duration,
+    max_in_flight: usize,
+    hosts: Mutex<HashMap<String, Arc<HostLimiter>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let min_interval = if config.requests_per_second > 0.0 {
+            Duration::from_secs_f64(1.0 / config.requests_per_second)
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            min_interval,
+            max_in_flight: config.max_in_flight.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a free slot for `url`'s host. The request counts as in flight
+    /// until the returned permit is dropped.
+    pub async fn acquire(&self, url: &str) -> OwnedSemaphorePermit {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let limiter = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| {
+                Arc::new(HostLimiter {
+                    in_flight: Arc::new(Semaphore::new(self.max_in_flight)),
+                    next_slot: tokio::sync::Mutex::new(Instant::now()),
+                })
+            })
+            .clone();
+
+        let permit = limiter.in_flight.clone().acquire_owned().await.unwrap();
+        let start_at = {
+            let mut next_slot = limiter.next_slot.lock().await;
+            let start_at = (*next_slot).max(Instant::now());
+            *next_slot = start_at + self.min_interval;
+            start_at
+        };
+        sleep_until(start_at).await;
+        permit
+    }
+}
@@ -0,0 +1,480 @@
+//! The streaming servers embedded on episode pages, used when the download
+//! links are missing or broken.
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamingServer {
+    /// e.g. "vidcdn" or "streamwish", as the episode page names it.
+    pub name: String,
+    pub embed_url: String,
+}
+
+/// The player list below the video, `div.anime_muti_link`.
+pub fn parse_streaming_servers(document: &Html) -> Vec<StreamingServer> {
+    let server_selector = Selector::parse("div.anime_muti_link ul li").unwrap();
+    let link_selector = Selector::parse("a[data-video]").unwrap();
+
+    let mut servers = Vec::new();
+    for li in document.select(&server_selector) {
+        let Some(link) = li.select(&link_selector).next() else {
+            continue;
+        };
+        let embed_url = link.value().attr("data-video").unwrap().trim();
+        if embed_url.is_empty() {
+            continue;
+        }
+        // Some pages leave out the scheme.
+        let embed_url = if embed_url.starts_with("//") {
+            format!("https:{}", embed_url)
+        } else {
+            embed_url.to_string()
+        };
+        // `classes()` is unordered, the server's class is the first written.
+        let name = li
+            .value()
+            .attr("class")
+            .and_then(|class| class.split_whitespace().next())
+            .unwrap_or("unknown")
+            .to_string();
+        servers.push(StreamingServer { name, embed_url });
+    }
+    servers
+}
+
+/// The keys Gogo's own player (the `streaming.php` embed) hides in the class
+/// names of its page.
+#[derive(Debug)]
+pub struct GogoCdnKeys {
+    key: Vec<u8>,
+    iv: Vec<u8>,
+    second_key: Vec<u8>,
+    /// Encrypted query parameters the ajax endpoint wants along with the id.
+    episode_token: String,
+}
+
+impl GogoCdnKeys {
+    pub fn from_embed_page(page: &str) -> Option<Self> {
+        let document = Html::parse_document(page);
+        let class_suffix = |selector: &str, prefix: &str| -> Option<Vec<u8>> {
+            let selector = Selector::parse(selector).unwrap();
+            document
+                .select(&selector)
+                .next()?
+                .value()
+                .classes()
+                .find_map(|class| class.strip_prefix(prefix))
+                .map(|suffix| suffix.as_bytes().to_vec())
+        };
+        let episode_token = {
+            let selector = Selector::parse("script[data-name='episode']").unwrap();
+            document
+                .select(&selector)
+                .next()?
+                .value()
+                .attr("data-value")?
+                .to_string()
+        };
+        Some(Self {
+            key: class_suffix("body", "container-")?,
+            iv: class_suffix("div.wrapper", "container-")?,
+            second_key: class_suffix("div.videocontent", "videocontent-")?,
+            episode_token,
+        })
+    }
+
+    /// The `encrypt-ajax.php` URL that returns the sources of `embed_url`.
+    pub fn ajax_url(&self, embed_url: &str) -> Option<String> {
+        let embed_url = Url::parse(embed_url).ok()?;
+        let id = embed_url
+            .query_pairs()
+            .find(|(name, _)| name == "id")
+            .map(|(_, value)| value.to_string())?;
+        let token = decrypt(&self.key, &self.iv, &self.episode_token)?;
+        let encrypted_id = encrypt(&self.key, &self.iv, &id)?;
+        Some(format!(
+            "{}://{}/encrypt-ajax.php?id={}&alias={}&{}",
+            embed_url.scheme(),
+            embed_url.host_str()?,
+            urlencoding::encode(&encrypted_id),
+            id,
+            token
+        ))
+    }
+
+    /// The playlist URLs in the `encrypt-ajax.php` response, best first,
+    /// and its subtitle tracks.
+    pub fn sources(&self, ajax_response: &str) -> Option<(Vec<String>, Vec<SubtitleTrack>)> {
+        #[derive(Deserialize)]
+        struct Response {
+            data: String,
+        }
+        #[derive(Deserialize)]
+        struct Sources {
+            #[serde(default)]
+            source: Vec<Source>,
+            #[serde(default)]
+            source_bk: Vec<Source>,
+            /// Either `[]` or `{"tracks": [...]}`.
+            #[serde(default)]
+            track: serde_json::Value,
+        }
+        #[derive(Deserialize)]
+        struct Source {
+            file: String,
+        }
+        #[derive(Deserialize)]
+        struct Track {
+            file: String,
+            label: Option<String>,
+            kind: Option<String>,
+        }
+
+        let response: Response = serde_json::from_str(ajax_response).ok()?;
+        let sources = decrypt(&self.second_key, &self.iv, &response.data)?;
+        let sources: Sources = serde_json::from_str(&sources).ok()?;
+        let playlists = sources
+            .source
+            .into_iter()
+            .chain(sources.source_bk)
+            .map(|source| source.file)
+            .filter(|file| file.contains(".m3u8"))
+            .collect();
+        let tracks: Vec<Track> = sources
+            .track
+            .get("tracks")
+            .cloned()
+            .and_then(|tracks| serde_json::from_value(tracks).ok())
+            .unwrap_or_default();
+        let subtitles = tracks
+            .into_iter()
+            // The other kind is "thumbnails", the seek bar previews.
+            .filter(|track| matches!(track.kind.as_deref(), Some("captions" | "subtitles")))
+            .map(|track| SubtitleTrack::new(&track.file, track.label.as_deref()))
+            .collect();
+        Some((playlists, subtitles))
+    }
+}
+
+/// A subtitle file (`.vtt`/`.srt`) or HLS subtitle playlist served next to a
+/// stream.
+#[derive(Debug, Clone)]
+pub struct SubtitleTrack {
+    /// A language code like "en" when the label could be recognized, the
+    /// label otherwise.
+    pub language: String,
+    pub url: String,
+}
+
+impl SubtitleTrack {
+    pub fn new(url: &str, label: Option<&str>) -> Self {
+        let language = label
+            .map(language_code)
+            .unwrap_or_else(|| "und".to_string());
+        Self {
+            language,
+            url: url.to_string(),
+        }
+    }
+}
+
+/// "English" -> "en", "Español (Latinoamérica)" -> "es"
+pub fn language_code(label: &str) -> String {
+    const LANGUAGES: [(&str, &str); 14] = [
+        ("english", "en"),
+        ("spanish", "es"),
+        ("español", "es"),
+        ("portuguese", "pt"),
+        ("português", "pt"),
+        ("french", "fr"),
+        ("français", "fr"),
+        ("german", "de"),
+        ("deutsch", "de"),
+        ("italian", "it"),
+        ("arabic", "ar"),
+        ("russian", "ru"),
+        ("indonesian", "id"),
+        ("japanese", "ja"),
+    ];
+    let label = label.trim().to_lowercase();
+    if let Some((_, code)) = LANGUAGES.iter().find(|(name, _)| label.starts_with(name)) {
+        return code.to_string();
+    }
+    // Already a code, or something unknown that still makes a usable suffix.
+    let code: String = label
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    let code = code.trim_matches('-').to_string();
+    if code.is_empty() {
+        "und".to_string()
+    } else {
+        code
+    }
+}
+
+/// Subtitle files referenced in a player's setup script, e.g.
+/// `{file: "https://.../eng-2.vtt", label: "English", kind: "captions"}`.
+pub fn find_subtitle_tracks(page: &str) -> Vec<SubtitleTrack> {
+    let mut tracks: Vec<SubtitleTrack> = Vec::new();
+    for extension in [".vtt", ".srt"] {
+        let mut offset = 0;
+        while let Some(pos) = page[offset..].find(extension) {
+            let end = offset + pos + extension.len();
+            offset = end;
+            let Some(start) = page[..end].rfind("http") else {
+                continue;
+            };
+            let Some(url) = embedded_url(&page[start..end]) else {
+                continue;
+            };
+            // The label usually follows the file within the same object.
+            let object_end = page[end..].find('}').map_or(page.len(), |idx| end + idx);
+            let label = find_label(&page[end..object_end]);
+            if !tracks.iter().any(|track| track.url == url) {
+                tracks.push(SubtitleTrack::new(&url, label.as_deref()));
+            }
+        }
+    }
+    tracks
+}
+
+fn find_label(object: &str) -> Option<String> {
+    let rest = &object[object.find("label")? + "label".len()..];
+    let rest = rest.trim_start_matches(['"', '\'', ':', ' ']);
+    let end = rest.find(['"', '\''])?;
+    Some(rest[..end].to_string())
+}
+
+/// The first `.m3u8` URL appearing anywhere in `page`, for players that
+/// don't hide it.
+pub fn find_playlist_url(page: &str) -> Option<String> {
+    let mut offset = 0;
+    while let Some(pos) = page[offset..].find(".m3u8") {
+        let end = offset + pos + ".m3u8".len();
+        offset = end;
+        let Some(start) = page[..end].rfind("http") else {
+            continue;
+        };
+        let rest = &page[end..];
+        let query_len = rest
+            .find(|c: char| c == '"' || c == '\'' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        if let Some(url) = embedded_url(&page[start..end + query_len]) {
+            return Some(url);
+        }
+    }
+    None
+}
+
+/// A URL cut out of a script, unless the cut reaches past the string the URL
+/// is in, e.g. when a mention of "http" comes before a relative path.
+fn embedded_url(candidate: &str) -> Option<String> {
+    let url = candidate.replace("\\/", "/");
+    if url.contains(['"', '\'', ' ']) || Url::parse(&url).is_err() {
+        return None;
+    }
+    Some(url)
+}
+
+fn encrypt(key: &[u8], iv: &[u8], plain: &str) -> Option<String> {
+    let cipher = Aes256CbcEnc::new_from_slices(key, iv).ok()?;
+    Some(STANDARD.encode(cipher.encrypt_padded_vec_mut::<Pkcs7>(plain.as_bytes())))
+}
+
+fn decrypt(key: &[u8], iv: &[u8], encoded: &str) -> Option<String> {
+    let cipher = Aes256CbcDec::new_from_slices(key, iv).ok()?;
+    let data = STANDARD.decode(encoded.trim()).ok()?;
+    let plain = cipher.decrypt_padded_vec_mut::<Pkcs7>(&data).ok()?;
+    String::from_utf8(plain).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "37911490979715163134003223491201";
+    const IV: &str = "3134003223491201";
+    const SECOND_KEY: &str = "54674138327930866480207815084989";
+
+    fn keys() -> GogoCdnKeys {
+        GogoCdnKeys {
+            key: KEY.as_bytes().to_vec(),
+            iv: IV.as_bytes().to_vec(),
+            second_key: SECOND_KEY.as_bytes().to_vec(),
+            episode_token: encrypt(KEY.as_bytes(), IV.as_bytes(), "token=abc&expires=1700")
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_streaming_servers() {
+        let document = Html::parse_document(
+            r##"<div class="anime_muti_link"><ul>
+                <li class="anime"><a href="#" data-video="https://embtaku.pro/streaming.php?id=MTIz">Vidstreaming</a></li>
+                <li class="streamwish active"><a href="#" data-video="//awish.pro/e/abc">Streamwish</a></li>
+                <li class="doodstream"><a href="#" data-video=" ">Doodstream</a></li>
+                <li class="mp4upload"><a href="#">Mp4upload</a></li>
+            </ul></div>"##,
+        );
+        let servers = parse_streaming_servers(&document);
+        let servers: Vec<_> = servers
+            .iter()
+            .map(|server| (server.name.as_str(), server.embed_url.as_str()))
+            .collect();
+        assert_eq!(
+            servers,
+            [
+                ("anime", "https://embtaku.pro/streaming.php?id=MTIz"),
+                ("streamwish", "https://awish.pro/e/abc"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_playlist_url() {
+        let page = r#"jwplayer("player").setup({sources: [{file: "https:\/\/cdn.example\/ep1\/master.m3u8?t=1&e=2"}]});"#;
+        assert_eq!(
+            find_playlist_url(page).as_deref(),
+            Some("https://cdn.example/ep1/master.m3u8?t=1&e=2")
+        );
+        assert_eq!(find_playlist_url("no playlist here"), None);
+    }
+
+    #[test]
+    fn skips_playlist_urls_that_run_past_their_string() {
+        // The relative path would be cut from the earlier "http" across the
+        // quotes, the absolute one after it is the real match.
+        let page = r#"var home = "https://example.com"; var rel = "/ep1.m3u8"; var abs = "https://cdn.example/ep1.m3u8";"#;
+        assert_eq!(
+            find_playlist_url(page).as_deref(),
+            Some("https://cdn.example/ep1.m3u8")
+        );
+        assert_eq!(
+            find_playlist_url(r#"var home = "https://example.com"; var rel = "/ep1.m3u8";"#),
+            None
+        );
+    }
+
+    #[test]
+    fn finds_subtitle_tracks() {
+        let page = r#"tracks: [
+            {file: "https://cdn.example/subs/eng-2.vtt", label: "English", kind: "captions"},
+            {file: "https://cdn.example/subs/spa-3.vtt", label: 'Español (Latinoamérica)', kind: "captions"},
+            {file: "https://cdn.example/subs/eng-2.vtt", label: "English", kind: "captions"},
+            {file: "https:\/\/cdn.example\/subs\/unknown.srt"}
+        ]"#;
+        let tracks: Vec<_> = find_subtitle_tracks(page)
+            .into_iter()
+            .map(|track| (track.language, track.url))
+            .collect();
+        assert_eq!(
+            tracks,
+            [
+                (
+                    "en".to_string(),
+                    "https://cdn.example/subs/eng-2.vtt".to_string()
+                ),
+                (
+                    "es".to_string(),
+                    "https://cdn.example/subs/spa-3.vtt".to_string()
+                ),
+                (
+                    "und".to_string(),
+                    "https://cdn.example/subs/unknown.srt".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_gogo_cdn_keys_from_the_embed_page() {
+        let page = format!(
+            r#"<html><body class="container-{KEY}">
+                <div class="wrapper container-{IV}">
+                <div class="videocontent videocontent-{SECOND_KEY}"></div></div>
+                <script type="text/javascript" src="/js/crypto.js" data-name="episode" data-value="{}"></script>
+            </body></html>"#,
+            keys().episode_token
+        );
+        let keys = GogoCdnKeys::from_embed_page(&page).unwrap();
+        assert_eq!(keys.key, KEY.as_bytes());
+        assert_eq!(keys.iv, IV.as_bytes());
+        assert_eq!(keys.second_key, SECOND_KEY.as_bytes());
+        assert!(GogoCdnKeys::from_embed_page("<html><body></body></html>").is_none());
+    }
+
+    #[test]
+    fn builds_the_ajax_url() {
+        let keys = keys();
+        let url = keys
+            .ajax_url("https://embtaku.pro/streaming.php?id=MTIz&title=Naruto")
+            .unwrap();
+        let encrypted_id = encrypt(KEY.as_bytes(), IV.as_bytes(), "MTIz").unwrap();
+        assert_eq!(
+            url,
+            format!(
+                "https://embtaku.pro/encrypt-ajax.php?id={}&alias=MTIz&token=abc&expires=1700",
+                urlencoding::encode(&encrypted_id)
+            )
+        );
+        assert!(keys.ajax_url("https://embtaku.pro/streaming.php").is_none());
+    }
+
+    #[test]
+    fn decrypts_the_ajax_sources() {
+        let sources = r#"{
+            "source": [{"file": "https://cdn.example/ep1.m3u8", "type": "hls"}],
+            "source_bk": [{"file": "https://backup.example/ep1.m3u8"}, {"file": "https://backup.example/ep1.mp4"}],
+            "track": {"tracks": [
+                {"file": "https://cdn.example/ep1-thumbs.vtt", "kind": "thumbnails"},
+                {"file": "https://cdn.example/ep1-eng.vtt", "label": "English", "kind": "captions"}
+            ]}
+        }"#;
+        let data = encrypt(SECOND_KEY.as_bytes(), IV.as_bytes(), sources).unwrap();
+        let response = serde_json::json!({ "data": data }).to_string();
+        let (playlists, subtitles) = keys().sources(&response).unwrap();
+        assert_eq!(
+            playlists,
+            [
+                "https://cdn.example/ep1.m3u8",
+                "https://backup.example/ep1.m3u8"
+            ]
+        );
+        assert_eq!(subtitles.len(), 1);
+        assert_eq!(subtitles[0].language, "en");
+        assert_eq!(subtitles[0].url, "https://cdn.example/ep1-eng.vtt");
+
+        // `track` is an empty array when there are no subtitles.
+        let data = encrypt(
+            SECOND_KEY.as_bytes(),
+            IV.as_bytes(),
+            r#"{"source": [], "track": []}"#,
+        )
+        .unwrap();
+        let response = serde_json::json!({ "data": data }).to_string();
+        let (playlists, subtitles) = keys().sources(&response).unwrap();
+        assert!(playlists.is_empty() && subtitles.is_empty());
+    }
+
+    #[test]
+    fn decrypt_rejects_the_wrong_key() {
+        let encoded = encrypt(KEY.as_bytes(), IV.as_bytes(), "id=MTIz").unwrap();
+        assert_eq!(
+            decrypt(KEY.as_bytes(), IV.as_bytes(), &encoded).as_deref(),
+            Some("id=MTIz")
+        );
+        assert_ne!(
+            decrypt(SECOND_KEY.as_bytes(), IV.as_bytes(), &encoded).as_deref(),
+            Some("id=MTIz")
+        );
+        assert_eq!(decrypt(KEY.as_bytes(), IV.as_bytes(), "not base64!"), None);
+    }
+}
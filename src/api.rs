@@ -0,0 +1,295 @@
+//! The HTTP/JSON control API of the daemon. Every request needs the token,
+//! as `Authorization: Bearer <token>` or, for `EventSource` which can't set
+//! headers, a `token` query parameter.
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use error_stack::{Context, Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::anime_downloader::gogo::{AnimeDetailedInfo, GogoAnime, SearchFilters, SearchPage};
+use crate::credentials::Secret;
+use crate::daemon::{DownloadEvent, Downloads, JobEntry, SharedConfig};
+use crate::jobs::{JobId, JobState};
+use crate::utils;
+
+/// How often `/api/events` reports the progress of the running jobs.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct ApiError;
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while running the control API")
+    }
+}
+
+impl Context for ApiError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Address the daemon listens on. Anything but localhost should sit
+    /// behind a reverse proxy doing TLS.
+    pub bind: String,
+    /// Sent by every client, the API is off without it.
+    pub token: Option<Secret>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:7979".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiState {
+    config: SharedConfig,
+    gogo_anime: Arc<GogoAnime>,
+    downloads: Arc<Downloads>,
+}
+
+impl ApiState {
+    pub fn new(
+        config: SharedConfig,
+        gogo_anime: Arc<GogoAnime>,
+        downloads: Arc<Downloads>,
+    ) -> Self {
+        Self {
+            config,
+            gogo_anime,
+            downloads,
+        }
+    }
+}
+
+pub async fn serve(listener: TcpListener, state: ApiState) -> Result<(), Report<ApiError>> {
+    let router = Router::new()
+        .route("/api/search", get(search))
+        .route("/api/anime", get(anime))
+        .route("/api/jobs", get(list_jobs).post(queue_episodes))
+        .route("/api/jobs/:id", delete(cancel_job))
+        .route("/api/events", get(events))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state);
+    axum::serve(listener, router).await.change_context(ApiError)
+}
+
+/// A failed request, answered with `{"error": "..."}`.
+struct HttpError(StatusCode, String);
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct Body {
+            error: String,
+        }
+        (self.0, Json(Body { error: self.1 })).into_response()
+    }
+}
+
+/// The site couldn't be scraped.
+fn bad_gateway<C: Context>(report: Report<C>) -> HttpError {
+    HttpError(StatusCode::BAD_GATEWAY, utils::report_summary(&report))
+}
+
+async fn authenticate(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    // Read on every request, a reload may have changed it.
+    let config = state.config.get();
+    let expected = config
+        .api
+        .token
+        .as_ref()
+        .map(|token| token.expose().as_bytes());
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let query = request.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "token")
+            .map(|(_, value)| value.into_owned())
+    });
+    let authorized = [bearer, query]
+        .into_iter()
+        .flatten()
+        .any(|token| expected.is_some_and(|expected| same_token(token.as_bytes(), expected)));
+    if !authorized {
+        return HttpError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong token".to_string(),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Compares without returning early, so the time taken doesn't tell how
+/// much of the token was right.
+fn same_token(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(default = "first_page")]
+    page: usize,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+async fn search(
+    State(state): State<ApiState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchPage>, HttpError> {
+    let page = state
+        .gogo_anime
+        .search_anime_page(&query.q, &SearchFilters::default(), query.page)
+        .await
+        .map_err(bad_gateway)?;
+    Ok(Json(page))
+}
+
+#[derive(Deserialize)]
+struct AnimeQuery {
+    /// Of the anime's page, as returned by the search.
+    url: String,
+}
+
+async fn anime(
+    State(state): State<ApiState>,
+    Query(query): Query<AnimeQuery>,
+) -> Result<Json<AnimeDetailedInfo>, HttpError> {
+    let anime = state
+        .gogo_anime
+        .fetch_detailed_anime_info(&query.url)
+        .await
+        .map_err(bad_gateway)?;
+    Ok(Json(anime))
+}
+
+async fn list_jobs(State(state): State<ApiState>) -> Json<Vec<JobEntry>> {
+    Json(state.downloads.jobs().await)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueueRequest {
+    /// Of the anime's page.
+    url: String,
+    /// "5" or "3-12", every episode when left out.
+    #[serde(default)]
+    episodes: String,
+    /// Instead of the one from the queue config.
+    priority: Option<i32>,
+}
+
+/// Answers with the jobs that were queued, episodes that are queued already
+/// are left out.
+async fn queue_episodes(
+    State(state): State<ApiState>,
+    Json(request): Json<QueueRequest>,
+) -> Result<(StatusCode, Json<Vec<JobEntry>>), HttpError> {
+    let anime = state
+        .gogo_anime
+        .fetch_detailed_anime_info(&request.url)
+        .await
+        .map_err(bad_gateway)?;
+    let (start, end) = utils::parse_episode_range(&request.episodes, anime.episode_links.len())
+        .ok_or_else(|| {
+            HttpError(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Expected an episode or a range like 1-{}, got {:?}",
+                    anime.episode_links.len(),
+                    request.episodes
+                ),
+            )
+        })?;
+    let queued = state
+        .downloads
+        .queue(
+            &state.config.get(),
+            &state.gogo_anime,
+            &anime,
+            &anime.episode_links[start - 1..end],
+            request.priority,
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(queued)))
+}
+
+async fn cancel_job(
+    State(state): State<ApiState>,
+    Path(id): Path<JobId>,
+) -> Result<StatusCode, HttpError> {
+    let job = state
+        .downloads
+        .job(id)
+        .await
+        .ok_or_else(|| HttpError(StatusCode::NOT_FOUND, format!("No job {}", id)))?;
+    job.cancel();
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// `queued` and `finished` events with the job, and every second a
+/// `progress` event with the jobs that aren't finished.
+async fn events(
+    State(state): State<ApiState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let changes = BroadcastStream::new(state.downloads.subscribe()).filter_map(|event| {
+        // A client too slow to keep up misses some, the progress events
+        // still show where the jobs are.
+        let (name, entry) = match event.ok()? {
+            DownloadEvent::Queued(entry) => ("queued", entry),
+            DownloadEvent::Finished(entry) => ("finished", entry),
+        };
+        Some(Ok(Event::default()
+            .event(name)
+            .json_data(entry)
+            .expect("jobs serialize")))
+    });
+    let downloads = state.downloads.clone();
+    let progress = IntervalStream::new(tokio::time::interval(PROGRESS_INTERVAL)).then(move |_| {
+        let downloads = downloads.clone();
+        async move {
+            let jobs: Vec<JobEntry> = downloads
+                .jobs()
+                .await
+                .into_iter()
+                .filter(|entry| entry.job.state != JobState::Finished)
+                .collect();
+            Ok(Event::default()
+                .event("progress")
+                .json_data(jobs)
+                .expect("jobs serialize"))
+        }
+    });
+    Sse::new(changes.merge(progress)).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,267 @@
+use error_stack::{Context, Report, ResultExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub struct CacheError;
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while accessing the metadata cache")
+    }
+}
+
+impl Context for CacheError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Search,
+    Details,
+    Episodes,
+    DownloadLinks,
+}
+
+impl EntryKind {
+    const ALL: [EntryKind; 4] = [
+        EntryKind::Search,
+        EntryKind::Details,
+        EntryKind::Episodes,
+        EntryKind::DownloadLinks,
+    ];
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| key.split_once(':').map(|(prefix, _)| prefix) == Some(kind.prefix()))
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            EntryKind::Search => "search",
+            EntryKind::Details => "details",
+            EntryKind::Episodes => "episodes",
+            EntryKind::DownloadLinks => "download_links",
+        }
+    }
+}
+
+/// How long each kind of entry stays valid, in seconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheTtls {
+    pub search: u64,
+    pub details: u64,
+    pub episodes: u64,
+    pub download_links: u64,
+}
+
+impl Default for CacheTtls {
+    fn default() -> Self {
+        Self {
+            search: 60 * 60,
+            details: 6 * 60 * 60,
+            episodes: 60 * 60,
+            // The download links are signed and expire after a while.
+            download_links: 20 * 60,
+        }
+    }
+}
+
+impl CacheTtls {
+    fn get(&self, kind: EntryKind) -> Duration {
+        let secs = match kind {
+            EntryKind::Search => self.search,
+            EntryKind::Details => self.details,
+            EntryKind::Episodes => self.episodes,
+            EntryKind::DownloadLinks => self.download_links,
+        };
+        Duration::from_secs(secs)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    stored_at: u64,
+    value: serde_json::Value,
+}
+
+/// Changes are written to disk after this delay, together with whatever
+/// else changed meanwhile.
+const PERSIST_DELAY: Duration = Duration::from_secs(2);
+
+/// JSON file backed cache for everything scraped from Gogo.
+///
+/// The cache is best effort: an unreadable cache file is treated as empty and
+/// failures to persist it are ignored.
+#[derive(Debug)]
+pub struct MetadataCache {
+    refresh: bool,
+    store: Arc<Store>,
+}
+
+/// The entries and the file they're persisted to, shared with the task that
+/// writes them.
+#[derive(Debug)]
+struct Store {
+    path: PathBuf,
+    ttls: CacheTtls,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    /// Set while a write is scheduled.
+    dirty: AtomicBool,
+    writing: Mutex<()>,
+}
+
+impl MetadataCache {
+    /// Opens the cache at `path`. With `refresh` set every lookup misses, but
+    /// fresh results are still written back.
+    pub fn open(path: PathBuf, ttls: CacheTtls, refresh: bool) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            refresh,
+            store: Arc::new(Store {
+                path,
+                ttls,
+                entries: Mutex::new(entries),
+                dirty: AtomicBool::new(false),
+                writing: Mutex::new(()),
+            }),
+        }
+    }
+
+    pub fn default_path() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("rusty-gogo-dl")
+            .join("metadata.json")
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, kind: EntryKind, key: &str) -> Option<T> {
+        if self.refresh {
+            return None;
+        }
+        let entries = self.store.entries.lock().unwrap();
+        let entry = entries.get(&entry_key(kind, key))?;
+        if now().saturating_sub(entry.stored_at) > self.store.ttls.get(kind).as_secs() {
+            return None;
+        }
+        serde_json::from_value(entry.value.clone()).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, kind: EntryKind, key: &str, value: &T) {
+        let Ok(value) = serde_json::to_value(value) else {
+            return;
+        };
+        self.store.entries.lock().unwrap().insert(
+            entry_key(kind, key),
+            CacheEntry {
+                stored_at: now(),
+                value,
+            },
+        );
+        self.schedule_persist();
+    }
+
+    pub fn remove(&self, kind: EntryKind, key: &str) {
+        let removed = self
+            .store
+            .entries
+            .lock()
+            .unwrap()
+            .remove(&entry_key(kind, key))
+            .is_some();
+        if removed {
+            self.schedule_persist();
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), Report<CacheError>> {
+        let _writing = self.store.writing.lock().unwrap();
+        self.store.entries.lock().unwrap().clear();
+        let path = &self.store.path;
+        if path.exists() {
+            std::fs::remove_file(path)
+                .change_context(CacheError)
+                .attach_printable_lazy(|| {
+                    format!("failed to remove the cache file {}", path.display())
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Writes the entries off the async threads, a burst of inserts (e.g.
+    /// the download links of a whole season) is written once.
+    fn schedule_persist(&self) {
+        if self.store.dirty.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let store = self.store.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    tokio::time::sleep(PERSIST_DELAY).await;
+                    let _ = tokio::task::spawn_blocking(move || store.persist()).await;
+                });
+            }
+            Err(_) => {
+                let _ = store.persist();
+            }
+        }
+    }
+}
+
+impl Drop for MetadataCache {
+    /// Writes what a scheduled write didn't get to before the program exits.
+    fn drop(&mut self) {
+        if self.store.dirty.load(Ordering::SeqCst) {
+            let _ = self.store.persist();
+        }
+    }
+}
+
+impl Store {
+    fn persist(&self) -> Result<(), Report<CacheError>> {
+        let _writing = self.writing.lock().unwrap();
+        // Changes from here on schedule another write.
+        self.dirty.store(false, Ordering::SeqCst);
+        let contents = {
+            // Drop whatever already expired so the file doesn't grow forever.
+            let entries = self.entries.lock().unwrap();
+            let now = now();
+            let live: HashMap<&String, &CacheEntry> = entries
+                .iter()
+                .filter(|(key, entry)| {
+                    EntryKind::from_key(key).is_some_and(|kind| {
+                        now.saturating_sub(entry.stored_at) <= self.ttls.get(kind).as_secs()
+                    })
+                })
+                .collect();
+            serde_json::to_string(&live).change_context(CacheError)?
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).change_context(CacheError)?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, contents).change_context(CacheError)?;
+        std::fs::rename(&tmp_path, &self.path).change_context(CacheError)?;
+        Ok(())
+    }
+}
+
+fn entry_key(kind: EntryKind, key: &str) -> String {
+    format!("{}:{}", kind.prefix(), key)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,170 @@
+use crate::anime_downloader::gogo::{
+    AiringStatus, AnimeType, Language, SearchFilters, SearchSort, Season,
+};
+use crate::config::CliOverrides;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Only list anime released in this year
+    #[arg(long)]
+    pub year: Option<u32>,
+    /// Only list anime of this type (tv, movie, ova, ona, special)
+    #[arg(long = "type")]
+    pub anime_type: Option<AnimeType>,
+    /// Only list subbed or dubbed anime (sub, dub)
+    #[arg(long)]
+    pub language: Option<Language>,
+    /// Only list anime with this airing status (upcoming, ongoing, completed)
+    #[arg(long)]
+    pub status: Option<AiringStatus>,
+    /// Order of the search results (relevance, name, year)
+    #[arg(long, default_value = "relevance")]
+    pub sort: SearchSort,
+    /// Ignore cached search results, anime details and download links
+    #[arg(long, global = true)]
+    pub refresh: bool,
+    /// Use this config file instead of looking one up
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Override the download folder from the config
+    #[arg(long, global = true, value_name = "PATH")]
+    pub download_folder: Option<PathBuf>,
+    /// Override the preferred resolution from the config, e.g. 1920x1080
+    #[arg(long, global = true, value_name = "RES")]
+    pub preferred_res: Option<String>,
+    /// Override the number of concurrent downloads from the config
+    #[arg(long, global = true, value_name = "N")]
+    pub concurrent_downloads: Option<usize>,
+    /// Override the number of retries from the config
+    #[arg(long, global = true, value_name = "N")]
+    pub retries: Option<usize>,
+    /// Send every request through this proxy, e.g. socks5://127.0.0.1:1080
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<String>,
+    /// Override any config value, e.g. --set rate_limit.max_in_flight=2
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub set: Vec<(String, String)>,
+}
+
+impl Cli {
+    pub fn config_overrides(&self) -> CliOverrides {
+        let mut overrides = CliOverrides {
+            config_path: self.config.clone(),
+            ..Default::default()
+        };
+        if let Some(download_folder) = &self.download_folder {
+            overrides.set(
+                "--download-folder",
+                "download_folder",
+                download_folder.display(),
+            );
+        }
+        if let Some(preferred_res) = &self.preferred_res {
+            overrides.set("--preferred-res", "preferred_res", preferred_res);
+        }
+        if let Some(concurrent_downloads) = self.concurrent_downloads {
+            overrides.set(
+                "--concurrent-downloads",
+                "concurrent_downloads",
+                concurrent_downloads,
+            );
+        }
+        if let Some(retries) = self.retries {
+            overrides.set("--retries", "retries", retries);
+        }
+        if let Some(proxy) = &self.proxy {
+            overrides.set("--proxy", "http.proxy", proxy);
+        }
+        for (key, value) in &self.set {
+            overrides.set("--set", key, value);
+        }
+        overrides
+    }
+
+    pub fn search_filters(&self) -> SearchFilters {
+        SearchFilters {
+            year: self.year,
+            anime_type: self.anime_type,
+            language: self.language,
+            status: self.status,
+            sort: self.sort,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Browse the most recently released episodes
+    Recent,
+    /// Browse the most popular anime
+    Popular,
+    /// Browse currently airing anime
+    Ongoing,
+    /// Browse anime of a genre, e.g. "slice of life"
+    Genre { genre: String },
+    /// Browse the anime of a season, e.g. "fall 2026"
+    Season { season: Season, year: u32 },
+    /// Open a full-screen dashboard to search and queue episodes while others download
+    Tui,
+    /// Run in the background and take downloads through the HTTP API instead of prompts
+    Daemon,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Manage the local metadata cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+    /// Keep track of the episodes that were already downloaded
+    Library {
+        #[command(subcommand)]
+        action: LibraryCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective config and where each value came from
+    Show,
+    /// Write a commented config file, asking for the values when run interactively
+    Init {
+        /// Overwrite an existing config file without asking
+        #[arg(long)]
+        force: bool,
+    },
+    /// Update the config file from the one published in the repository
+    Fetch {
+        /// Overwrite the config file without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Delete every cached entry
+    Clear,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LibraryCommand {
+    /// Index the episodes in a folder so they aren't downloaded again
+    Scan {
+        /// Defaults to the download folder
+        folder: Option<PathBuf>,
+    },
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", s))
+}
@@ -0,0 +1,1016 @@
+use crate::anime_downloader::gogo::Language;
+use crate::anime_downloader::rate_limiter::RateLimitConfig;
+use crate::api::ApiConfig;
+use crate::cache::CacheTtls;
+use crate::credentials::Secret;
+use crate::daemon::DaemonConfig;
+use crate::disk_space::DiskSpaceConfig;
+use crate::hooks::HooksConfig;
+use crate::http::HttpConfig;
+use crate::jobs::QueueConfig;
+use crate::post_process::PostProcessConfig;
+use crate::subtitles::SubtitlesConfig;
+use crate::utils;
+use error_stack::{Context, Report, ResultExt};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const LINK_TO_CONFIG: &str =
+    "https://raw.githubusercontent.com/Zai-Kun/rusty-gogo-dl/master/config.json";
+
+const ENV_PREFIX: &str = "RUSTY_GOGO_";
+
+/// Kept out of the fetched config, the accounts live in the credentials file.
+const CREDENTIAL_KEYS: [&str; 4] = [
+    "registered_account_emails",
+    "password",
+    "password_cmd",
+    "credentials_file",
+];
+
+#[derive(Debug)]
+pub struct ParseConfigError;
+
+impl fmt::Display for ParseConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Could not parse configuration file")
+    }
+}
+
+impl Context for ParseConfigError {}
+
+#[derive(Debug)]
+pub struct ConfigDownloadError;
+
+impl fmt::Display for ConfigDownloadError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Failed to download the config file")
+    }
+}
+
+impl Context for ConfigDownloadError {}
+
+#[derive(Debug)]
+pub struct InvalidConfigError;
+
+impl fmt::Display for InvalidConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("The configuration contains invalid values")
+    }
+}
+
+impl Context for InvalidConfigError {}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub gogo_base_url: String,
+    /// Other domains of the site, tried in order when `gogo_base_url` is down.
+    pub gogo_mirrors: Vec<String>,
+    pub fetch_ep_list_api: String,
+    /// Prefer the credentials file, see [`crate::credentials`].
+    pub registered_account_emails: Vec<String>,
+    pub password: Option<Secret>,
+    /// Command printing the shared password, e.g. `pass show gogo`.
+    pub password_cmd: Option<String>,
+    /// Defaults to `credentials.toml` next to the user config.
+    pub credentials_file: Option<PathBuf>,
+    pub preferred_res: String,
+    pub concurrent_downloads: usize,
+    pub download_folder: PathBuf,
+    pub retries: usize,
+    /// Variant to switch to when a show is listed both subbed and dubbed.
+    pub preferred_language: Option<Language>,
+    pub cache_ttl: CacheTtls,
+    pub rate_limit: RateLimitConfig,
+    pub http: HttpConfig,
+    pub queue: QueueConfig,
+    pub disk_space: DiskSpaceConfig,
+    pub hooks: HooksConfig,
+    pub post_process: PostProcessConfig,
+    pub subtitles: SubtitlesConfig,
+    pub api: ApiConfig,
+    pub daemon: DaemonConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gogo_base_url: "https://anitaku.bz".to_string(),
+            gogo_mirrors: vec![
+                "https://anitaku.pe".to_string(),
+                "https://anitaku.so".to_string(),
+                "https://gogoanime3.co".to_string(),
+            ],
+            fetch_ep_list_api: "https://ajax.gogocdn.net/ajax/load-list-episode?ep_start=0&ep_end={END_EP}&id={ANIME_ID}".to_string(),
+            registered_account_emails: Vec::new(),
+            password: None,
+            password_cmd: None,
+            credentials_file: None,
+            preferred_res: "1280x720".to_string(),
+            concurrent_downloads: 2,
+            download_folder: PathBuf::from("downloaded_anime"),
+            retries: 3,
+            preferred_language: None,
+            cache_ttl: CacheTtls::default(),
+            rate_limit: RateLimitConfig::default(),
+            http: HttpConfig::default(),
+            queue: QueueConfig::default(),
+            disk_space: DiskSpaceConfig::default(),
+            hooks: HooksConfig::default(),
+            post_process: PostProcessConfig::default(),
+            subtitles: SubtitlesConfig::default(),
+            api: ApiConfig::default(),
+            daemon: DaemonConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Checks the values serde can't, reporting every problem at once as
+    /// `(key, message)` pairs.
+    fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+
+        if Url::parse(&self.gogo_base_url).is_err() {
+            problems.push((
+                "gogo_base_url",
+                format!("expected a URL, got {:?}", self.gogo_base_url),
+            ));
+        }
+        for mirror in &self.gogo_mirrors {
+            if Url::parse(mirror).is_err() {
+                problems.push((
+                    "gogo_mirrors",
+                    format!("expected a list of URLs, got {:?}", mirror),
+                ));
+            }
+        }
+        for placeholder in ["{END_EP}", "{ANIME_ID}"] {
+            if !self.fetch_ep_list_api.contains(placeholder) {
+                problems.push((
+                    "fetch_ep_list_api",
+                    format!("is missing the {} placeholder", placeholder),
+                ));
+            }
+        }
+        if utils::parse_resolution(&self.preferred_res).is_none() {
+            problems.push((
+                "preferred_res",
+                format!(
+                    "expected WIDTHxHEIGHT such as 1280x720, got {:?}",
+                    self.preferred_res
+                ),
+            ));
+        }
+        if self.concurrent_downloads == 0 {
+            problems.push(("concurrent_downloads", "must be at least 1".to_string()));
+        }
+        if !self.rate_limit.requests_per_second.is_finite()
+            || self.rate_limit.requests_per_second < 0.0
+        {
+            problems.push((
+                "rate_limit.requests_per_second",
+                "must be 0 (unlimited) or a positive number".to_string(),
+            ));
+        }
+        if self.rate_limit.max_in_flight == 0 {
+            problems.push(("rate_limit.max_in_flight", "must be at least 1".to_string()));
+        }
+        if self.subtitles.embed && !self.post_process.enabled {
+            problems.push((
+                "subtitles.embed",
+                "needs post_process.enabled, subtitles are muxed in by ffmpeg".to_string(),
+            ));
+        }
+        if self.api.bind.parse::<std::net::SocketAddr>().is_err() {
+            problems.push((
+                "api.bind",
+                format!(
+                    "expected an address like 127.0.0.1:7979, got {:?}",
+                    self.api.bind
+                ),
+            ));
+        }
+        if self.api.token.as_ref().is_some_and(Secret::is_empty) {
+            problems.push(("api.token", "must not be empty".to_string()));
+        }
+        if self.daemon.sync_interval_minutes == 0 {
+            problems.push((
+                "daemon.sync_interval_minutes",
+                "must be at least 1".to_string(),
+            ));
+        }
+        for watched in &self.daemon.watch {
+            if Url::parse(&watched.url).is_err() {
+                problems.push((
+                    "daemon.watch.url",
+                    format!("expected the URL of an anime's page, got {:?}", watched.url),
+                ));
+            }
+            if watched.from_episode == 0 {
+                problems.push((
+                    "daemon.watch.from_episode",
+                    "must be at least 1, episodes are counted from 1".to_string(),
+                ));
+            }
+        }
+        if let Some(proxy) = &self.http.proxy {
+            match Url::parse(proxy) {
+                Ok(url) if ["http", "https", "socks5", "socks5h"].contains(&url.scheme()) => {}
+                _ => problems.push((
+                    "http.proxy",
+                    format!(
+                        "expected an http://, https:// or socks5:// URL, got {:?}",
+                        proxy
+                    ),
+                )),
+            }
+        }
+        for path in &self.http.ca_certificates {
+            if !path.is_file() {
+                problems.push((
+                    "http.ca_certificates",
+                    format!("{} does not exist", path.display()),
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Checks what only matters once downloading starts: that the download
+    /// folder is writable and ffmpeg runs. Kept out of [`Config::validate`] as
+    /// it touches the disk and spawns ffmpeg.
+    fn check_environment(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+
+        if let Err(err) = check_writable(&self.download_folder) {
+            problems.push((
+                "download_folder",
+                format!(
+                    "{} is not writable: {}",
+                    self.download_folder.display(),
+                    err
+                ),
+            ));
+        }
+        if self.post_process.enabled {
+            if let Err(err) = self.post_process.check_ffmpeg() {
+                problems.push((
+                    "post_process.ffmpeg",
+                    format!(
+                        "could not run {}: {}",
+                        self.post_process.ffmpeg.display(),
+                        err
+                    ),
+                ));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Where a config value came from. Later layers win over earlier ones.
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => f.write_str("default"),
+            ConfigSource::File(path) => write!(f, "file {}", path.display()),
+            ConfigSource::Env(var) => write!(f, "env {}", var),
+            ConfigSource::Cli(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// Overrides given on the command line, as `(flag, key, value)`. Nested keys
+/// are separated with dots, e.g. `rate_limit.max_in_flight`.
+#[derive(Debug, Default)]
+pub struct CliOverrides {
+    pub config_path: Option<PathBuf>,
+    pub values: Vec<(String, String, String)>,
+}
+
+impl CliOverrides {
+    pub fn set(&mut self, flag: &str, key: &str, value: impl ToString) {
+        self.values
+            .push((flag.to_string(), key.to_string(), value.to_string()));
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadedConfig {
+    pub config: Config,
+    /// The source of every value that wasn't left at its default, keyed by
+    /// its dotted path.
+    pub sources: BTreeMap<String, ConfigSource>,
+}
+
+impl LoadedConfig {
+    pub fn is_from_file(&self, key: &str) -> bool {
+        matches!(self.sources.get(key), Some(ConfigSource::File(_)))
+    }
+
+    /// Run before downloading: checks the download folder and ffmpeg, which
+    /// loading the config leaves alone.
+    pub fn check_environment(&self) -> Result<(), Report<InvalidConfigError>> {
+        let problems = self.config.check_environment();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let mut report = Report::new(InvalidConfigError);
+        for (key, problem) in problems {
+            report = report.attach_printable(format!(
+                "{}: {}",
+                describe_key(&self.sources, key),
+                problem
+            ));
+        }
+        Err(report)
+    }
+
+    /// Every value of the effective config together with where it came from.
+    pub fn entries(&self) -> Vec<(String, Value, ConfigSource)> {
+        let mut leaves = Vec::new();
+        let value = serde_json::to_value(&self.config).unwrap_or(Value::Null);
+        flatten("", &value, &mut leaves);
+        leaves
+            .into_iter()
+            .map(|(key, value)| {
+                let source = self
+                    .sources
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or(ConfigSource::Default);
+                (key, value, source)
+            })
+            .collect()
+    }
+}
+
+/// Candidate config files, most specific first: `./config.json`, then the
+/// user config dir (`~/.config/rusty-gogo-dl/config.{toml,json}` on Linux).
+pub fn config_file_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from("config.json")];
+    if let Some(config_dir) = dirs::config_dir() {
+        let dir = config_dir.join("rusty-gogo-dl");
+        candidates.push(dir.join("config.toml"));
+        candidates.push(dir.join("config.json"));
+    }
+    candidates
+}
+
+/// The config file that will be used: the `--config` path if given, else the
+/// first candidate that exists.
+pub fn find_config_file(overrides: &CliOverrides) -> Option<PathBuf> {
+    match &overrides.config_path {
+        Some(path) => Some(path.clone()),
+        None => config_file_candidates().into_iter().find(|p| p.exists()),
+    }
+}
+
+/// Where `config init` writes to when no `--config` path is given.
+pub fn default_config_path() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("rusty-gogo-dl"))
+        .unwrap_or_default()
+        .join("config.toml")
+}
+
+/// Values asked for by `config init`, everything else keeps its default.
+#[derive(Debug, Default)]
+pub struct InitValues {
+    pub registered_account_emails: Vec<String>,
+    pub password: Secret,
+    pub download_folder: Option<PathBuf>,
+    pub preferred_res: Option<String>,
+    pub concurrent_downloads: Option<usize>,
+    /// Where the credentials were written, when it's not the default path.
+    pub credentials_file: Option<PathBuf>,
+}
+
+/// Renders a commented TOML config with the defaults filled in.
+pub fn render_config_template(values: &InitValues) -> String {
+    let defaults = Config::default();
+    let string = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let download_folder = values
+        .download_folder
+        .as_ref()
+        .unwrap_or(&defaults.download_folder);
+    let credentials_file = match &values.credentials_file {
+        Some(path) => format!("credentials_file = {}", string(&path.to_string_lossy())),
+        None => "# credentials_file = \"/path/to/credentials.toml\"".to_string(),
+    };
+
+    format!(
+        r#"# rusty-gogo-dl configuration
+#
+# Every key can also be set with a RUSTY_GOGO_<KEY> environment variable or
+# overridden on the command line, see `rusty-gogo-dl config show`.
+
+# The accounts used to log in are kept in credentials.toml next to this file.
+{credentials_file}
+
+# Where the episodes are saved to, one folder per show.
+download_folder = {download_folder}
+# Resolution to download, the closest available one is used (WIDTHxHEIGHT).
+preferred_res = {preferred_res}
+# Number of episodes downloaded at the same time.
+concurrent_downloads = {concurrent_downloads}
+# How often a failed episode is retried.
+retries = {retries}
+# Switch to the "sub" or "dub" variant of a show when both exist.
+# preferred_language = "sub"
+
+# Site the anime are scraped from and its episode list API. When the site
+# can't be reached the mirrors are tried in order.
+gogo_base_url = {gogo_base_url}
+gogo_mirrors = {gogo_mirrors}
+fetch_ep_list_api = {fetch_ep_list_api}
+
+# Seconds search results, details, episode lists and links stay cached.
+[cache_ttl]
+search = {ttl_search}
+details = {ttl_details}
+episodes = {ttl_episodes}
+download_links = {ttl_download_links}
+
+# Politeness towards the site, per host. 0 requests_per_second disables it.
+[rate_limit]
+requests_per_second = {requests_per_second:?}
+max_in_flight = {max_in_flight}
+
+# Used for both scraping and downloading. Timeouts are in seconds, 0 disables.
+[http]
+# proxy = "socks5://127.0.0.1:1080"
+connect_timeout = {connect_timeout}
+read_timeout = {read_timeout}
+# user_agent = "Mozilla/5.0 ..."
+# ca_certificates = ["/etc/ssl/corporate-ca.pem"]
+# insecure_tls = false
+
+# [http.headers]
+# Referer = "https://anitaku.bz/"
+
+# Episodes of airing shows are downloaded before others, higher priorities go
+# first. max_per_show limits the downloads of one show at a time, 0 disables.
+[queue]
+max_per_show = {max_per_show}
+airing_priority = {airing_priority}
+
+# Before downloading, the size of the episodes is compared with the free space.
+# preflight is "warn" (ask), "abort" or "off". Downloads pause while less than
+# reserve_mb is free, 0 disables that.
+[disk_space]
+preflight = "warn"
+reserve_mb = {reserve_mb}
+
+# Shell commands run when downloads finish. They get the event as JSON on
+# stdin and RUSTY_GOGO_EVENT, RUSTY_GOGO_ANIME, RUSTY_GOGO_EPISODE,
+# RUSTY_GOGO_PATH, ... in the environment. timeout is in seconds.
+[hooks]
+# on_episode_complete = "notify-send \"Downloaded $RUSTY_GOGO_ANIME episode $RUSTY_GOGO_EPISODE\""
+# on_episode_failed = "echo \"$RUSTY_GOGO_PATH: $RUSTY_GOGO_ERROR\" >> ~/gogo-failures.log"
+# on_batch_complete = "curl -X POST -H \"X-Emby-Token: ...\" http://localhost:8096/Library/Refresh"
+timeout = {hook_timeout}
+
+# Remux every episode with ffmpeg, tag it with the show and episode and embed
+# the poster. Set video_codec/audio_codec (and bitrates) to transcode too.
+[post_process]
+enabled = false
+ffmpeg = "ffmpeg"
+container = "mkv"
+# video_codec = "libx265"
+# video_bitrate = "1500k"
+# audio_codec = "libopus"
+# audio_bitrate = "128k"
+embed_cover = true
+keep_original = false
+
+# Subtitle tracks of streamed episodes are saved next to the video, e.g.
+# "Episode 1.en.srt". format is "srt" (converted from WebVTT) or "original",
+# languages limits them to some language codes, e.g. ["en"]. embed muxes them
+# into the video during post-processing instead.
+[subtitles]
+download = true
+format = "srt"
+languages = []
+embed = false
+
+# The HTTP API of `rusty-gogo-dl daemon`, off without a token. Clients send it
+# as "Authorization: Bearer <token>", it can also be set in
+# RUSTY_GOGO_API__TOKEN.
+[api]
+bind = {api_bind}
+# token = "a long random string"
+
+# `rusty-gogo-dl daemon` checks the followed anime every sync_interval_minutes
+# and queues their new episodes. It logs in again every session_refresh_hours,
+# 0 disables that. Send it SIGHUP to reload this file.
+[daemon]
+sync_interval_minutes = {sync_interval_minutes}
+session_refresh_hours = {session_refresh_hours}
+# log_file = "/var/log/rusty-gogo-dl.log"
+
+# One section per followed anime, from_episode skips the earlier episodes.
+# [[daemon.watch]]
+# url = "https://anitaku.bz/category/one-piece"
+# from_episode = 1100
+"#,
+        download_folder = string(&download_folder.to_string_lossy()),
+        credentials_file = credentials_file,
+        preferred_res = string(
+            values
+                .preferred_res
+                .as_deref()
+                .unwrap_or(&defaults.preferred_res)
+        ),
+        concurrent_downloads = values
+            .concurrent_downloads
+            .unwrap_or(defaults.concurrent_downloads),
+        retries = defaults.retries,
+        gogo_base_url = string(&defaults.gogo_base_url),
+        gogo_mirrors = toml::Value::Array(
+            defaults
+                .gogo_mirrors
+                .iter()
+                .map(|mirror| toml::Value::String(mirror.clone()))
+                .collect()
+        ),
+        fetch_ep_list_api = string(&defaults.fetch_ep_list_api),
+        ttl_search = defaults.cache_ttl.search,
+        ttl_details = defaults.cache_ttl.details,
+        ttl_episodes = defaults.cache_ttl.episodes,
+        ttl_download_links = defaults.cache_ttl.download_links,
+        requests_per_second = defaults.rate_limit.requests_per_second,
+        max_in_flight = defaults.rate_limit.max_in_flight,
+        connect_timeout = defaults.http.connect_timeout,
+        read_timeout = defaults.http.read_timeout,
+        max_per_show = defaults.queue.max_per_show,
+        airing_priority = defaults.queue.airing_priority,
+        reserve_mb = defaults.disk_space.reserve_mb,
+        hook_timeout = defaults.hooks.timeout,
+        api_bind = string(&defaults.api.bind),
+        sync_interval_minutes = defaults.daemon.sync_interval_minutes,
+        session_refresh_hours = defaults.daemon.session_refresh_hours,
+    )
+}
+
+/// Renders the config written by `config init` in the format `path` is read
+/// in. JSON has no comments, so only the TOML file is commented.
+pub fn render_config_file(values: &InitValues, path: &Path) -> String {
+    let template = render_config_template(values);
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        return template;
+    }
+    let value: toml::Value = toml::from_str(&template).expect("the template is valid toml");
+    serde_json::to_string_pretty(&value).expect("toml values serialize") + "\n"
+}
+
+/// Renders the credentials file written next to the config by `config init`.
+pub fn render_credentials_template(values: &InitValues) -> String {
+    let string = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let mut template = String::from(
+        r#"# rusty-gogo-dl credentials, keep this file private (chmod 600).
+#
+# Instead of storing the password here you can set
+#   password_cmd = "pass show gogo"
+# or give each account its own `password`/`password_cmd`.
+
+"#,
+    );
+    if values.password.is_empty() {
+        template.push_str("# password = \"\"\n");
+    } else {
+        template.push_str(&format!(
+            "password = {}\n",
+            string(values.password.expose())
+        ));
+    }
+    if values.registered_account_emails.is_empty() {
+        template.push_str("\n# [[accounts]]\n# email = \"you@example.com\"\n");
+    }
+    for email in &values.registered_account_emails {
+        template.push_str(&format!("\n[[accounts]]\nemail = {}\n", string(email)));
+    }
+    template
+}
+
+/// Downloads the config published in the repository and renders it in the
+/// format of `path`, keeping the account settings of `path`. Nothing is
+/// written, see [`config_diff`].
+pub async fn fetch_remote_config(path: &Path) -> Result<String, Report<ConfigDownloadError>> {
+    let response = reqwest::get(LINK_TO_CONFIG)
+        .await
+        .change_context(ConfigDownloadError)?
+        .error_for_status()
+        .change_context(ConfigDownloadError)?;
+    let contents = response.text().await.change_context(ConfigDownloadError)?;
+
+    let mut value: Value = serde_json::from_str(&contents)
+        .change_context(ConfigDownloadError)
+        .attach_printable_lazy(|| format!("{} did not return valid json", LINK_TO_CONFIG))?;
+    if let Value::Object(remote) = &mut value {
+        // The remote's placeholders would replace the accounts set up here.
+        let current = read_config_file(path).ok();
+        for key in CREDENTIAL_KEYS {
+            remote.remove(key);
+            if let Some(kept) = current.as_ref().and_then(|current| current.get(key)) {
+                remote.insert(key.to_string(), kept.clone());
+            }
+        }
+    }
+    serde_path_to_error::deserialize::<_, Config>(value.clone())
+        .map_err(|err| {
+            Report::new(ConfigDownloadError).attach_printable(format!(
+                "`{}`: {}",
+                err.path(),
+                err.inner()
+            ))
+        })
+        .attach_printable_lazy(|| format!("{} did not return a valid config", LINK_TO_CONFIG))?;
+
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::to_string_pretty(&value).change_context(ConfigDownloadError)
+    } else {
+        serde_json::to_string_pretty(&value)
+            .map(|json| json + "\n")
+            .change_context(ConfigDownloadError)
+    }
+}
+
+/// Unified line diff from `old` to `new`.
+pub fn config_diff(old: &str, new: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .header("current", "remote")
+        .to_string()
+}
+
+/// Builds the effective config from, in increasing priority: the config
+/// file, `RUSTY_GOGO_*` environment variables and command line overrides.
+pub fn parse_and_load_config(
+    overrides: &CliOverrides,
+) -> Result<LoadedConfig, Report<ParseConfigError>> {
+    let config_path = find_config_file(overrides).ok_or_else(|| {
+        Report::new(ParseConfigError)
+            .attach_printable(format!(
+                "no config file found, looked in: {}",
+                config_file_candidates()
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .attach_printable("run `rusty-gogo-dl config init` to create one")
+    })?;
+
+    let file_value = read_config_file(&config_path)?;
+    build_config(&config_path, &file_value, std::env::vars(), overrides)
+}
+
+/// Layers `env_vars` (the `RUSTY_GOGO_*` ones among them) and the command
+/// line overrides over the contents of the config file, then deserializes and
+/// checks the result.
+fn build_config(
+    config_path: &Path,
+    file_value: &Value,
+    env_vars: impl IntoIterator<Item = (String, String)>,
+    overrides: &CliOverrides,
+) -> Result<LoadedConfig, Report<ParseConfigError>> {
+    let mut merged = Map::new();
+    let mut sources = BTreeMap::new();
+    // Only used to tell which overrides are meant as strings.
+    let defaults = match serde_json::to_value(Config::default()) {
+        Ok(Value::Object(defaults)) => defaults,
+        _ => Map::new(),
+    };
+
+    let mut leaves = Vec::new();
+    flatten("", file_value, &mut leaves);
+    for (key, value) in leaves {
+        set_path(&mut merged, &key, value);
+        sources.insert(key, ConfigSource::File(config_path.to_path_buf()));
+    }
+
+    let mut env_vars: Vec<(String, String)> = env_vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    env_vars.sort();
+    for (name, raw) in env_vars {
+        // RUSTY_GOGO_RATE_LIMIT__MAX_IN_FLIGHT -> rate_limit.max_in_flight
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+        // Others share the prefix, e.g. the variables hooks get when they
+        // run this program again. A typo below a known section still fails.
+        let section = key.split('.').next().unwrap_or_default();
+        if !defaults.contains_key(section) {
+            continue;
+        }
+        let value = coerce(get_path(&merged, &key).or(get_path(&defaults, &key)), &raw);
+        set_path(&mut merged, &key, value);
+        sources.insert(key, ConfigSource::Env(name));
+    }
+
+    for (flag, key, raw) in &overrides.values {
+        let value = coerce(get_path(&merged, key).or(get_path(&defaults, key)), raw);
+        set_path(&mut merged, key, value);
+        sources.insert(key.clone(), ConfigSource::Cli(flag.clone()));
+    }
+
+    let describe = |key: &str| describe_key(&sources, key);
+
+    let config: Config =
+        serde_path_to_error::deserialize(Value::Object(merged)).map_err(|err| {
+            let key = err.path().to_string();
+            Report::new(ParseConfigError)
+                .attach_printable(format!("{}: {}", describe(&key), err.inner()))
+                .attach_printable(format!(
+                    "failed to build the config from {} and its overrides",
+                    config_path.display()
+                ))
+        })?;
+
+    let problems = config.validate();
+    if !problems.is_empty() {
+        let mut report = Report::new(InvalidConfigError);
+        for (key, problem) in problems {
+            report = report.attach_printable(format!("{}: {}", describe(key), problem));
+        }
+        return Err(report.change_context(ParseConfigError));
+    }
+
+    Ok(LoadedConfig { config, sources })
+}
+
+fn read_config_file(config_path: &Path) -> Result<Value, Report<ParseConfigError>> {
+    let contents = std::fs::read_to_string(config_path)
+        .change_context(ParseConfigError)
+        .attach_printable_lazy(|| {
+            format!("failed to read the config file {}", config_path.display())
+        })?;
+
+    if config_path.extension().is_some_and(|ext| ext == "toml") {
+        let value: toml::Value = toml::from_str(&contents)
+            .change_context(ParseConfigError)
+            .attach_printable(format!(
+                "failed to parse the config file as toml {}",
+                config_path.display()
+            ))?;
+        serde_json::to_value(value).change_context(ParseConfigError)
+    } else {
+        serde_json::from_str(&contents)
+            .change_context(ParseConfigError)
+            .attach_printable(format!(
+                "failed to parse the config file as json {}",
+                config_path.display()
+            ))
+    }
+}
+
+// Overrides are plain strings, so they're read as JSON where that makes sense
+// ("3", "true", "[...]"), except when the value they replace is a string.
+fn coerce(current: Option<&Value>, raw: &str) -> Value {
+    match current {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, leaves: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, leaves);
+            }
+        }
+        _ => leaves.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// A config key for error messages, with where its value came from.
+fn describe_key(sources: &BTreeMap<String, ConfigSource>, key: &str) -> String {
+    match sources.get(key) {
+        Some(source) => format!("`{}` (from {})", key, source),
+        None => format!("`{}`", key),
+    }
+}
+
+/// Walks up to the closest existing ancestor, since the download folder is
+/// created on demand, and tries to create a file there.
+fn check_writable(path: &Path) -> std::io::Result<()> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.as_os_str().is_empty() || ancestor.exists())
+        .unwrap_or(path);
+    let existing = if existing.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing
+    };
+    if !existing.is_dir() {
+        return Err(std::io::Error::other(format!(
+            "{} is not a directory",
+            existing.display()
+        )));
+    }
+    let probe = existing.join(".rusty-gogo-dl-write-test");
+    File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+fn get_path<'a>(map: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    match key.split_once('.') {
+        Some((head, rest)) => get_path(map.get(head)?.as_object()?, rest),
+        None => map.get(key),
+    }
+}
+
+fn set_path(map: &mut Map<String, Value>, key: &str, value: Value) {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let child = map.entry(head).or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            set_path(child.as_object_mut().unwrap(), rest, value);
+        }
+        None => {
+            map.insert(key.to_string(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn cli(values: &[(&str, &str, &str)]) -> CliOverrides {
+        CliOverrides {
+            config_path: None,
+            values: values
+                .iter()
+                .map(|(flag, key, value)| (flag.to_string(), key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn build(
+        file: Value,
+        vars: &[(&str, &str)],
+        overrides: &CliOverrides,
+    ) -> Result<LoadedConfig, Report<ParseConfigError>> {
+        build_config(Path::new("config.json"), &file, env(vars), overrides)
+    }
+
+    fn source(loaded: &LoadedConfig, key: &str) -> String {
+        match loaded.sources.get(key) {
+            Some(source) => source.to_string(),
+            None => ConfigSource::Default.to_string(),
+        }
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let file = json!({ "concurrent_downloads": 3, "retries": 4, "preferred_res": "640x360" });
+        let vars = [
+            ("RUSTY_GOGO_CONCURRENT_DOWNLOADS", "5"),
+            ("RUSTY_GOGO_RETRIES", "6"),
+        ];
+        let overrides = cli(&[("--concurrency", "concurrent_downloads", "7")]);
+        let loaded = build(file, &vars, &overrides).unwrap();
+
+        assert_eq!(loaded.config.concurrent_downloads, 7);
+        assert_eq!(
+            source(&loaded, "concurrent_downloads"),
+            "flag --concurrency"
+        );
+        assert_eq!(loaded.config.retries, 6);
+        assert_eq!(source(&loaded, "retries"), "env RUSTY_GOGO_RETRIES");
+        assert_eq!(loaded.config.preferred_res, "640x360");
+        assert_eq!(source(&loaded, "preferred_res"), "file config.json");
+        assert!(loaded.is_from_file("preferred_res"));
+        assert_eq!(loaded.config.gogo_base_url, Config::default().gogo_base_url);
+        assert_eq!(source(&loaded, "gogo_base_url"), "default");
+    }
+
+    #[test]
+    fn nested_keys_keep_their_siblings() {
+        let file = json!({ "rate_limit": { "requests_per_second": 0.5 } });
+        let vars = [("RUSTY_GOGO_RATE_LIMIT__MAX_IN_FLIGHT", "7")];
+        let overrides = cli(&[("--set", "daemon.sync_interval_minutes", "15")]);
+        let loaded = build(file, &vars, &overrides).unwrap();
+
+        assert_eq!(loaded.config.rate_limit.requests_per_second, 0.5);
+        assert_eq!(loaded.config.rate_limit.max_in_flight, 7);
+        assert_eq!(
+            source(&loaded, "rate_limit.max_in_flight"),
+            "env RUSTY_GOGO_RATE_LIMIT__MAX_IN_FLIGHT"
+        );
+        assert_eq!(loaded.config.daemon.sync_interval_minutes, 15);
+        assert_eq!(
+            loaded.config.daemon.watch.len(),
+            DaemonConfig::default().watch.len()
+        );
+    }
+
+    #[test]
+    fn overrides_take_the_type_of_the_value_they_replace() {
+        let vars = [
+            ("RUSTY_GOGO_PREFERRED_RES", "1920x1080"),
+            ("RUSTY_GOGO_GOGO_MIRRORS", r#"["https://example.com"]"#),
+            ("RUSTY_GOGO_API__BIND", "127.0.0.1:8080"),
+        ];
+        let loaded = build(json!({}), &vars, &cli(&[])).unwrap();
+        assert_eq!(loaded.config.preferred_res, "1920x1080");
+        assert_eq!(loaded.config.gogo_mirrors, vec!["https://example.com"]);
+        assert_eq!(loaded.config.api.bind, "127.0.0.1:8080");
+
+        let string = Value::String("old".to_string());
+        let number = json!(1);
+        assert_eq!(coerce(Some(&string), "42"), json!("42"));
+        assert_eq!(coerce(Some(&number), "42"), json!(42));
+        assert_eq!(coerce(None, "true"), json!(true));
+        assert_eq!(coerce(None, "not json"), json!("not json"));
+    }
+
+    #[test]
+    fn skips_variables_outside_the_config() {
+        let vars = [
+            ("RUSTY_GOGO_EVENT", "episode_finished"),
+            ("RUSTY_GOGO_PATH", "/tmp/naruto-episode-1.mp4"),
+            ("OTHER_RETRIES", "9"),
+        ];
+        let loaded = build(json!({}), &vars, &cli(&[])).unwrap();
+        assert_eq!(loaded.config.retries, Config::default().retries);
+        assert!(loaded.sources.is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_key_and_its_source() {
+        let vars = [("RUSTY_GOGO_RATE_LIMIT__MAX_INFLIGHT", "3")];
+        let report = build(json!({}), &vars, &cli(&[])).unwrap_err();
+        assert!(format!("{:?}", report)
+            .contains("`rate_limit.max_inflight` (from env RUSTY_GOGO_RATE_LIMIT__MAX_INFLIGHT)"));
+
+        let overrides = cli(&[("--retries", "retries", "many")]);
+        let report = build(json!({}), &[], &overrides).unwrap_err();
+        assert!(format!("{:?}", report).contains("`retries` (from flag --retries)"));
+
+        let vars = [("RUSTY_GOGO_CONCURRENT_DOWNLOADS", "0")];
+        let report = build(json!({ "preferred_res": "hd" }), &vars, &cli(&[])).unwrap_err();
+        let message = format!("{:?}", report);
+        assert!(message.contains(
+            "`concurrent_downloads` (from env RUSTY_GOGO_CONCURRENT_DOWNLOADS): must be at least 1"
+        ));
+        assert!(message.contains("`preferred_res` (from file config.json)"));
+    }
+
+    #[test]
+    fn describes_keys_with_their_source() {
+        let mut sources = BTreeMap::new();
+        sources.insert(
+            "api.bind".to_string(),
+            ConfigSource::Env("RUSTY_GOGO_API__BIND".to_string()),
+        );
+        sources.insert(
+            "retries".to_string(),
+            ConfigSource::File(PathBuf::from("/etc/config.toml")),
+        );
+        assert_eq!(
+            describe_key(&sources, "api.bind"),
+            "`api.bind` (from env RUSTY_GOGO_API__BIND)"
+        );
+        assert_eq!(
+            describe_key(&sources, "retries"),
+            "`retries` (from file /etc/config.toml)"
+        );
+        assert_eq!(describe_key(&sources, "preferred_res"), "`preferred_res`");
+    }
+}
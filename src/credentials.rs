@@ -0,0 +1,250 @@
+use crate::config::Config;
+use error_stack::{Context, Report, ResultExt};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug)]
+pub struct CredentialsError;
+
+impl fmt::Display for CredentialsError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Could not load the account credentials")
+    }
+}
+
+impl Context for CredentialsError {}
+
+/// A string that never shows up in `Debug`/`Display` output or when
+/// serialized, e.g. by `config show`.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"********\"")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("********")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("********")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub email: String,
+    pub password: Secret,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct CredentialsFile {
+    /// Used for every account without a password of its own.
+    password: Option<Secret>,
+    password_cmd: Option<String>,
+    #[serde(default)]
+    accounts: Vec<AccountEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct AccountEntry {
+    email: String,
+    password: Option<Secret>,
+    password_cmd: Option<String>,
+}
+
+pub fn default_credentials_path() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("rusty-gogo-dl"))
+        .unwrap_or_default()
+        .join("credentials.toml")
+}
+
+/// Resolves the accounts to log in with.
+///
+/// The accounts are read from the credentials file if it exists, falling back
+/// to `registered_account_emails` from the config (or `RUSTY_GOGO_*`
+/// variables). A password is taken from, in order: the account entry, the
+/// credentials file, `password`/`password_cmd` from the config.
+pub fn load_credentials(config: &Config) -> Result<Vec<Account>, Report<CredentialsError>> {
+    let path = config
+        .credentials_file
+        .clone()
+        .unwrap_or_else(default_credentials_path);
+    let mut file = if path.exists() {
+        read_credentials_file(&path)?
+    } else {
+        CredentialsFile::default()
+    };
+
+    let entries: Vec<AccountEntry> = if file.accounts.is_empty() {
+        config
+            .registered_account_emails
+            .iter()
+            .map(|email| AccountEntry {
+                email: email.clone(),
+                password: None,
+                password_cmd: None,
+            })
+            .collect()
+    } else {
+        std::mem::take(&mut file.accounts)
+    };
+    if entries.is_empty() {
+        return Err(Report::new(CredentialsError).attach_printable(format!(
+            "no accounts configured, add them to {} or set `registered_account_emails`",
+            path.display()
+        )));
+    }
+
+    // The shared password is only looked up (and its command only run) when
+    // an account actually needs it.
+    let mut shared_password: Option<Secret> = None;
+    let mut accounts = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let password = match (entry.password, entry.password_cmd) {
+            (Some(password), _) => password,
+            (None, Some(cmd)) => run_password_cmd(&cmd)?,
+            (None, None) => match &shared_password {
+                Some(password) => password.clone(),
+                None => {
+                    let password = shared_password_for(&file, config)?.ok_or_else(|| {
+                        Report::new(CredentialsError).attach_printable(format!(
+                            "no password for {}, set one in {} or use `password_cmd`",
+                            entry.email,
+                            path.display()
+                        ))
+                    })?;
+                    shared_password.insert(password).clone()
+                }
+            },
+        };
+        if password.is_empty() {
+            return Err(Report::new(CredentialsError)
+                .attach_printable(format!("the password for {} is empty", entry.email)));
+        }
+        accounts.push(Account {
+            email: entry.email,
+            password,
+        });
+    }
+    Ok(accounts)
+}
+
+fn shared_password_for(
+    file: &CredentialsFile,
+    config: &Config,
+) -> Result<Option<Secret>, Report<CredentialsError>> {
+    if let Some(password) = &file.password {
+        return Ok(Some(password.clone()));
+    }
+    if let Some(cmd) = &file.password_cmd {
+        return run_password_cmd(cmd).map(Some);
+    }
+    if let Some(password) = &config.password {
+        return Ok(Some(password.clone()));
+    }
+    if let Some(cmd) = &config.password_cmd {
+        return run_password_cmd(cmd).map(Some);
+    }
+    Ok(None)
+}
+
+fn read_credentials_file(path: &Path) -> Result<CredentialsFile, Report<CredentialsError>> {
+    check_permissions(path)?;
+    let contents = std::fs::read_to_string(path)
+        .change_context(CredentialsError)
+        .attach_printable_lazy(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&contents)
+        .change_context(CredentialsError)
+        .attach_printable_lazy(|| format!("failed to parse {} as toml", path.display()))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Report<CredentialsError>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .change_context(CredentialsError)
+        .attach_printable_lazy(|| format!("failed to stat {}", path.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(Report::new(CredentialsError)
+            .attach_printable(format!(
+                "{} can be read by other users (mode {:o})",
+                path.display(),
+                mode & 0o777
+            ))
+            .attach_printable(format!("run `chmod 600 {}`", path.display())));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Report<CredentialsError>> {
+    Ok(())
+}
+
+/// Writes `contents` to a file only the current user can read.
+pub fn write_private_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files.
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Runs `cmd` through the shell and uses the first line it prints.
+fn run_password_cmd(cmd: &str) -> Result<Secret, Report<CredentialsError>> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", cmd]).output()
+    } else {
+        Command::new("sh").args(["-c", cmd]).output()
+    }
+    .change_context(CredentialsError)
+    .attach_printable_lazy(|| format!("failed to run password_cmd `{}`", cmd))?;
+
+    if !output.status.success() {
+        return Err(Report::new(CredentialsError).attach_printable(format!(
+            "password_cmd `{}` exited with {}",
+            cmd, output.status
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(Secret::new(stdout.lines().next().unwrap_or("").trim()))
+}
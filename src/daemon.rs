@@ -0,0 +1,591 @@
+//! Runs as a service without a terminal: downloads are queued through the
+//! control API and from the watchlist instead of the prompts.
+
+use error_stack::{Context, Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, interval_at, Instant, Interval, MissedTickBehavior};
+
+use crate::anime_downloader::gogo::{AnimeDetailedInfo, GogoAnime};
+use crate::api::{self, ApiError};
+use crate::config::{parse_and_load_config, CliOverrides, Config, ParseConfigError};
+use crate::download_manager::{
+    ConcurrentDownloadManager, DownloadError, DownloadStatus, JobResults,
+};
+use crate::jobs::{JobHandle, JobId, JobInfo};
+use crate::ledger::{EpisodeId, Ledger};
+use crate::shutdown::{InterruptedDownloads, Shutdown};
+use crate::utils;
+
+/// How often finished downloads are collected.
+const POLL: Duration = Duration::from_secs(1);
+/// How many finished jobs the API keeps listing, older ones are forgotten.
+const FINISHED_HISTORY: usize = 100;
+
+#[derive(Debug)]
+pub struct DaemonError;
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while running the daemon")
+    }
+}
+
+impl Context for DaemonError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Minutes between two checks of the watchlist for new episodes.
+    pub sync_interval_minutes: u64,
+    /// Hours after which the daemon logs in to the site again, 0 never does.
+    pub session_refresh_hours: u64,
+    /// Defaults to `daemon.log` in the data directory.
+    pub log_file: Option<PathBuf>,
+    pub watch: Vec<WatchedAnime>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            sync_interval_minutes: 60,
+            session_refresh_hours: 12,
+            log_file: None,
+            watch: Vec::new(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn log_file(&self) -> PathBuf {
+        self.log_file.clone().unwrap_or_else(|| {
+            dirs::data_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join("rusty-gogo-dl")
+                .join("daemon.log")
+        })
+    }
+}
+
+/// An anime whose new episodes are queued as they come out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WatchedAnime {
+    /// Of the anime's page.
+    pub url: String,
+    /// Episodes before this one (1-based, in the site's list) are left
+    /// alone, so following a long show doesn't download all of it.
+    #[serde(default = "first_episode")]
+    pub from_episode: usize,
+}
+
+fn first_episode() -> usize {
+    1
+}
+
+/// The config the daemon runs with, replaced when it's reloaded.
+#[derive(Debug, Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, config: Config) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
+
+/// How a finished job ended.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum JobOutcome {
+    Downloaded { path: String, source: String },
+    Skipped { path: String },
+    Cancelled,
+    Failed { error: String },
+}
+
+impl From<&Result<DownloadStatus, Report<DownloadError>>> for JobOutcome {
+    fn from(result: &Result<DownloadStatus, Report<DownloadError>>) -> Self {
+        match result {
+            Ok(DownloadStatus::Downloaded { path, source }) => JobOutcome::Downloaded {
+                path: path.display().to_string(),
+                source: source.to_string(),
+            },
+            Ok(DownloadStatus::Skipped(path)) => JobOutcome::Skipped {
+                path: path.display().to_string(),
+            },
+            Ok(DownloadStatus::Cancelled(_)) => JobOutcome::Cancelled,
+            Err(report) => JobOutcome::Failed {
+                error: utils::report_summary(report),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JobEntry {
+    #[serde(flatten)]
+    pub job: JobInfo,
+    /// Set once the job finished.
+    pub outcome: Option<JobOutcome>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    Queued(JobEntry),
+    Finished(JobEntry),
+}
+
+/// The download manager of the daemon, shared by everything that queues or
+/// watches downloads.
+#[derive(Debug)]
+pub struct Downloads {
+    manager: Mutex<ConcurrentDownloadManager>,
+    /// By job, like the results of the manager.
+    outcomes: std::sync::Mutex<HashMap<JobId, JobOutcome>>,
+    events: broadcast::Sender<DownloadEvent>,
+}
+
+impl Downloads {
+    fn new(manager: ConcurrentDownloadManager) -> Self {
+        Self {
+            manager: Mutex::new(manager),
+            outcomes: std::sync::Mutex::new(HashMap::new()),
+            events: broadcast::channel(256).0,
+        }
+    }
+
+    pub async fn jobs(&self) -> Vec<JobEntry> {
+        let manager = self.manager.lock().await;
+        manager.jobs().iter().map(|job| self.entry(job)).collect()
+    }
+
+    pub async fn job(&self, id: JobId) -> Option<JobHandle> {
+        let manager = self.manager.lock().await;
+        manager.jobs().iter().find(|job| job.id() == id).cloned()
+    }
+
+    /// Events about queued and finished jobs from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<DownloadEvent> {
+        self.events.subscribe()
+    }
+
+    /// Queues the episodes of `anime` at `links`, leaving out the ones that
+    /// are queued already.
+    pub async fn queue(
+        &self,
+        config: &Config,
+        gogo_anime: &Arc<GogoAnime>,
+        anime: &AnimeDetailedInfo,
+        links: &[String],
+        priority: Option<i32>,
+    ) -> Vec<JobEntry> {
+        let mut manager = self.manager.lock().await;
+        let mut queued = Vec::new();
+        for link in links {
+            let ep_path = utils::episode_path(&config.download_folder, anime, link);
+            let Some(job) = manager.add_gogo_download(
+                gogo_anime.clone(),
+                anime,
+                &config.preferred_res,
+                &ep_path,
+                link,
+            ) else {
+                continue;
+            };
+            if let Some(priority) = priority {
+                job.set_priority(priority);
+            }
+            let entry = self.entry(&job);
+            let _ = self.events.send(DownloadEvent::Queued(entry.clone()));
+            queued.push(entry);
+        }
+        if !queued.is_empty() {
+            log(format!(
+                "Queued {} episode(s) of {}",
+                queued.len(),
+                anime.name
+            ));
+        }
+        queued
+    }
+
+    fn entry(&self, job: &JobHandle) -> JobEntry {
+        JobEntry {
+            job: job.info(),
+            outcome: self.outcomes.lock().unwrap().get(&job.id()).cloned(),
+        }
+    }
+
+    /// Records the results of the jobs that finished since the last call,
+    /// then forgets all but the last [`FINISHED_HISTORY`] finished jobs.
+    async fn collect_finished(&self) {
+        let mut manager = self.manager.lock().await;
+        let results = manager.take_finished().await;
+        if results.is_empty() {
+            return;
+        }
+        self.record(&manager, results);
+        manager.prune_finished(FINISHED_HISTORY);
+        self.outcomes
+            .lock()
+            .unwrap()
+            .retain(|id, _| manager.jobs().iter().any(|job| job.id() == *id));
+    }
+
+    fn record(&self, manager: &ConcurrentDownloadManager, results: JobResults) {
+        for (id, (path, result)) in results {
+            let outcome = JobOutcome::from(&result);
+            match &outcome {
+                JobOutcome::Downloaded { path, source } => {
+                    log(format!("Downloaded {} ({})", path, source))
+                }
+                JobOutcome::Skipped { path: existing } => {
+                    log(format!("Skipped {}, already at {}", path, existing))
+                }
+                JobOutcome::Cancelled => log(format!("Cancelled {}", path)),
+                JobOutcome::Failed { error } => log(format!("Failed {}: {}", path, error)),
+            }
+            self.outcomes.lock().unwrap().insert(id, outcome);
+            if let Some(job) = manager.jobs().iter().find(|job| job.id() == id) {
+                let _ = self.events.send(DownloadEvent::Finished(self.entry(job)));
+            }
+        }
+    }
+}
+
+/// Serves the control API and syncs the watchlist until SIGINT or SIGTERM,
+/// then waits for the running downloads to save their progress. Episodes
+/// that didn't finish are picked up again on the next start. SIGHUP reloads
+/// the config.
+pub async fn run(
+    overrides: &CliOverrides,
+    config: Config,
+    gogo_anime: Arc<GogoAnime>,
+    ledger: Arc<Ledger>,
+    download_manager: ConcurrentDownloadManager,
+    shutdown: &Shutdown,
+    interrupted: &InterruptedDownloads,
+) -> Result<(), Box<dyn Error>> {
+    init_log(&config.daemon.log_file())?;
+    // Right away, until it's listened to SIGHUP kills the daemon.
+    let mut reload = ReloadSignal::listen();
+    if config.api.token.is_none() && config.daemon.watch.is_empty() {
+        return Err(Report::new(DaemonError)
+            .attach_printable("Set api.token or follow some anime in daemon.watch")
+            .into());
+    }
+    let config = SharedConfig::new(config);
+    let mut download_manager = download_manager.with_hidden_progress();
+    shutdown.downloads_started(download_manager.cancellation_token());
+
+    match interrupted.load() {
+        Ok(episodes) if !episodes.is_empty() => {
+            interrupted.clear()?;
+            log(format!(
+                "Resuming {} episode(s) interrupted last time",
+                episodes.len()
+            ));
+            for episode in episodes {
+                download_manager.add_episode(
+                    gogo_anime.clone(),
+                    &config.get().preferred_res,
+                    episode,
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(report) => log(utils::report_summary(&report)),
+    }
+
+    let downloads = Arc::new(Downloads::new(download_manager));
+    let server = match &config.get().api.token {
+        Some(_) => {
+            let bind = config.get().api.bind.clone();
+            let listener = TcpListener::bind(&bind).await.map_err(|err| {
+                Report::new(err)
+                    .change_context(ApiError)
+                    .attach_printable(format!("Failed to listen on {}", bind))
+            })?;
+            log(format!("Listening on http://{}", listener.local_addr()?));
+            Some(tokio::spawn(api::serve(
+                listener,
+                api::ApiState::new(config.clone(), gogo_anime.clone(), downloads.clone()),
+            )))
+        }
+        None => {
+            log("api.token isn't set, the HTTP API is off");
+            None
+        }
+    };
+
+    notify_systemd(Notification::Ready);
+    let mut poll = interval(POLL);
+    let mut sync = sync_interval(&config.get());
+    let mut refresh = refresh_interval(&config.get());
+    let mut syncing: Option<JoinHandle<()>> = None;
+    let mut refreshing: Option<JoinHandle<()>> = None;
+    while !shutdown.is_requested() {
+        tokio::select! {
+            _ = poll.tick() => {
+                downloads.collect_finished().await;
+                notify_systemd(Notification::Watchdog);
+                if server.as_ref().is_some_and(JoinHandle::is_finished) {
+                    break;
+                }
+            }
+            _ = sync.tick() => {
+                // A slow site may take longer than the interval.
+                if syncing.as_ref().is_none_or(JoinHandle::is_finished) {
+                    syncing = Some(tokio::spawn(sync_watchlist(
+                        config.get(),
+                        gogo_anime.clone(),
+                        ledger.clone(),
+                        downloads.clone(),
+                    )));
+                }
+            }
+            _ = refresh.tick() => {
+                // Logging in takes a few requests, polling goes on meanwhile.
+                if refreshing.as_ref().is_none_or(JoinHandle::is_finished) {
+                    let gogo_anime = gogo_anime.clone();
+                    refreshing = Some(tokio::spawn(async move {
+                        match gogo_anime.refresh_session().await {
+                            Ok(()) => log("Renewed the session"),
+                            Err(report) => log(utils::report_summary(&report)),
+                        }
+                    }));
+                }
+            }
+            _ = reload.recv() => {
+                notify_systemd(Notification::Reloading);
+                let loaded = parse_and_load_config(overrides).and_then(|loaded| {
+                    loaded
+                        .check_environment()
+                        .change_context(ParseConfigError)?;
+                    Ok(loaded)
+                });
+                match loaded {
+                    Ok(loaded) => {
+                        let reloaded = loaded.config;
+                        {
+                            let mut manager = downloads.manager.lock().await;
+                            manager.set_concurrency(reloaded.concurrent_downloads);
+                            manager.set_queue(&reloaded.queue);
+                        }
+                        // The next sync runs right away, for newly followed anime.
+                        sync = sync_interval(&reloaded);
+                        refresh = refresh_interval(&reloaded);
+                        config.replace(reloaded);
+                        log("Reloaded the config");
+                    }
+                    Err(report) => log(format!(
+                        "Kept the old config: {}",
+                        utils::report_summary(&report)
+                    )),
+                }
+                notify_systemd(Notification::Ready);
+            }
+        }
+    }
+
+    notify_systemd(Notification::Stopping);
+    log("Shutting down, waiting for the downloads to save their progress");
+    for task in syncing.into_iter().chain(refreshing) {
+        task.abort();
+    }
+    let mut manager = downloads.manager.lock().await;
+    // A signal cancelled them already, but not when the server failed.
+    manager.cancel_all();
+    let results = manager.await_results().await;
+    shutdown.downloads_finished();
+    let mut unfinished = manager.cancelled_episodes(&results);
+    downloads.record(&manager, results);
+    unfinished.extend(manager.unfinished_episodes());
+    if !unfinished.is_empty() {
+        interrupted.save(&unfinished)?;
+        log(format!(
+            "{} interrupted episode(s) will be resumed next time",
+            unfinished.len()
+        ));
+    }
+    let Some(server) = server else {
+        return Ok(());
+    };
+    server.abort();
+    match server.await {
+        Ok(Err(report)) => Err(report.into()),
+        _ => Ok(()),
+    }
+}
+
+fn sync_interval(config: &Config) -> Interval {
+    let mut sync = interval(Duration::from_secs(
+        config.daemon.sync_interval_minutes.max(1) * 60,
+    ));
+    sync.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    sync
+}
+
+/// Ticks for the first time after one period, the session is fresh at the
+/// start.
+fn refresh_interval(config: &Config) -> Interval {
+    // Practically never when it's turned off.
+    let hours = match config.daemon.session_refresh_hours {
+        0 => 24 * 365 * 100,
+        hours => hours,
+    };
+    let period = Duration::from_secs(hours * 60 * 60);
+    interval_at(Instant::now() + period, period)
+}
+
+/// Queues the episodes of the watched anime that were never downloaded.
+async fn sync_watchlist(
+    config: Arc<Config>,
+    gogo_anime: Arc<GogoAnime>,
+    ledger: Arc<Ledger>,
+    downloads: Arc<Downloads>,
+) {
+    for watched in &config.daemon.watch {
+        // Cached details would hide episodes that came out since.
+        gogo_anime.forget_anime_details(&watched.url);
+        let anime = match gogo_anime.fetch_detailed_anime_info(&watched.url).await {
+            Ok(anime) => anime,
+            Err(report) => {
+                log(format!(
+                    "Failed to check {}: {}",
+                    watched.url,
+                    utils::report_summary(&report)
+                ));
+                continue;
+            }
+        };
+        let new_episodes: Vec<String> = anime
+            .episode_links
+            .iter()
+            .skip(watched.from_episode.saturating_sub(1))
+            .filter(|link| {
+                // Watched episodes that were deleted stay deleted.
+                EpisodeId::parse(link).is_none_or(|episode| !ledger.contains(&episode))
+            })
+            .cloned()
+            .collect();
+        if !new_episodes.is_empty() {
+            downloads
+                .queue(&config, &gogo_anime, &anime, &new_episodes, None)
+                .await;
+        }
+    }
+}
+
+/// SIGHUP, listened to for the whole run so one that arrives while the loop
+/// is busy is still seen on its next turn.
+struct ReloadSignal {
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl ReloadSignal {
+    #[cfg(unix)]
+    fn listen() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let hangup = signal(SignalKind::hangup())
+            .inspect_err(|err| log(format!("Can't reload on SIGHUP: {}", err)))
+            .ok();
+        Self { hangup }
+    }
+
+    #[cfg(not(unix))]
+    fn listen() -> Self {
+        Self {}
+    }
+
+    /// Waits for the next SIGHUP, or forever where there's no such signal.
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(hangup) = &mut self.hangup {
+            hangup.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+enum Notification {
+    Ready,
+    Reloading,
+    Stopping,
+    Watchdog,
+}
+
+/// Tells systemd about the daemon's state when it runs as a `Type=notify`
+/// (or `notify-reload`) service, does nothing otherwise.
+#[cfg(unix)]
+fn notify_systemd(notification: Notification) {
+    use sd_notify::NotifyState;
+
+    let states = match notification {
+        Notification::Ready => vec![NotifyState::Ready],
+        Notification::Reloading => {
+            let mut states = vec![NotifyState::Reloading];
+            // notify-reload services expect to be told when the reload began.
+            states.extend(NotifyState::monotonic_usec_now().ok());
+            states
+        }
+        Notification::Stopping => vec![NotifyState::Stopping],
+        Notification::Watchdog => vec![NotifyState::Watchdog],
+    };
+    let _ = sd_notify::notify(false, &states);
+}
+
+#[cfg(not(unix))]
+fn notify_systemd(_notification: Notification) {}
+
+static LOG_FILE: OnceLock<std::sync::Mutex<File>> = OnceLock::new();
+
+fn init_log(path: &std::path::Path) -> Result<(), Report<DaemonError>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .change_context(DaemonError)
+            .attach_printable_lazy(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .change_context(DaemonError)
+        .attach_printable_lazy(|| format!("Failed to open the log file {}", path.display()))?;
+    let _ = LOG_FILE.set(std::sync::Mutex::new(file));
+    Ok(())
+}
+
+/// Prints `line` and appends it to the log file with the time.
+fn log(line: impl AsRef<str>) {
+    println!("{}", line.as_ref());
+    if let Some(file) = LOG_FILE.get() {
+        let _ = writeln!(
+            file.lock().unwrap(),
+            "{} {}",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            line.as_ref()
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightMode {
+    /// Don't estimate the size before downloading.
+    Off,
+    /// Ask whether to continue if the episodes might not fit.
+    Warn,
+    /// Refuse to start if the episodes might not fit.
+    Abort,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiskSpaceConfig {
+    pub preflight: PreflightMode,
+    /// MiB that are kept free on the download filesystem. Downloads pause
+    /// while less than this is available, 0 disables the guard.
+    pub reserve_mb: u64,
+}
+
+impl Default for DiskSpaceConfig {
+    fn default() -> Self {
+        Self {
+            preflight: PreflightMode::Warn,
+            reserve_mb: 1024,
+        }
+    }
+}
+
+impl DiskSpaceConfig {
+    pub fn reserve_bytes(&self) -> u64 {
+        self.reserve_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// Free bytes on the filesystem `path` is (or will be) created on.
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."));
+    fs2::available_space(existing)
+}
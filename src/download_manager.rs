@@ -1,26 +1,42 @@
 #![allow(dead_code)]
 
 use error_stack::{Context, Report, ResultExt};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fmt;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::{
     fs::{create_dir_all, File, OpenOptions},
     io::AsyncWriteExt,
 };
 use tokio::{
     sync::Semaphore,
-    task::{self, JoinHandle},
+    task::{self, JoinHandle, JoinSet},
+    time::sleep,
 };
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 
-use super::utils;
-use crate::anime_downloader::gogo::GogoAnime;
+use super::{hls, utils};
+use crate::anime_downloader::gogo::{AnimeDetailedInfo, EpisodeSource, GogoAnime};
+use crate::anime_downloader::streaming::StreamingServer;
+use crate::disk_space::{self, DiskSpaceConfig};
+use crate::hooks::{BatchEvent, EpisodeEvent, EpisodeOutcome, HooksConfig};
+use crate::jobs::{Cancelled, JobHandle, JobId, JobState, ProgressUnit, QueueConfig, Scheduler};
+use crate::ledger::{EpisodeId, Ledger};
+use crate::post_process::{post_process, EpisodeMetadata, PostProcessConfig};
+use crate::subtitles::{self, SavedSubtitle, SubtitlesConfig};
+
+/// Free space is checked again after this many bytes were written.
+const FREE_SPACE_CHECK_INTERVAL: u64 = 8 * 1024 * 1024;
+const FREE_SPACE_POLL: Duration = Duration::from_secs(15);
 
 #[derive(Debug)]
 pub struct DownloadError;
@@ -33,117 +49,735 @@ impl fmt::Display for DownloadError {
 
 impl Context for DownloadError {}
 
+#[derive(Debug)]
+pub enum DownloadStatus {
+    /// The episode was downloaded (and post-processed) to `path`.
+    Downloaded {
+        path: PathBuf,
+        source: EpisodeSource,
+    },
+    /// The episode already exists at this path.
+    Skipped(PathBuf),
+    /// The job was cancelled, the partial file at this path is picked up
+    /// again the next time the episode is downloaded.
+    Cancelled(PathBuf),
+}
+
+impl DownloadStatus {
+    pub fn path(&self) -> &Path {
+        match self {
+            DownloadStatus::Downloaded { path, .. }
+            | DownloadStatus::Skipped(path)
+            | DownloadStatus::Cancelled(path) => path,
+        }
+    }
+}
+
+/// An episode as it was queued, everything needed to queue it again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedEpisode {
+    pub metadata: EpisodeMetadata,
+    pub ep_path: String,
+    pub ep_url: String,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+type DownloadTask = JoinHandle<Result<DownloadStatus, Report<DownloadError>>>;
+
+/// How the jobs that were taken ended, with the path they downloaded to.
+pub type JobResults = HashMap<JobId, (String, Result<DownloadStatus, Report<DownloadError>>)>;
+
 #[derive(Debug)]
 pub struct ConcurrentDownloadManager {
-    sem: Arc<Semaphore>,
+    scheduler: Arc<Scheduler>,
+    cancel: CancellationToken,
+    jobs: Vec<JobHandle>,
+    episodes: HashMap<JobId, QueuedEpisode>,
     client: Client,
     multi_progress: MultiProgress,
     retries: usize,
-    pub tasks_results: HashMap<String, JoinHandle<Result<(), Report<DownloadError>>>>,
+    airing_priority: i32,
+    free_space_reserve: u64,
+    ledger: Option<Arc<Ledger>>,
+    hooks: Arc<HooksConfig>,
+    post_process: Option<Arc<PostProcessConfig>>,
+    subtitles: Option<Arc<SubtitlesConfig>>,
+    finished: Arc<Mutex<Vec<EpisodeEvent>>>,
+    /// Jobs that haven't finished yet, the batch hook runs when it drops to 0.
+    pending: Arc<AtomicUsize>,
+    /// By job and not by path, an episode can be queued again before the
+    /// result of its last job was taken.
+    pub tasks_results: HashMap<JobId, (String, DownloadTask)>,
 }
 
 impl ConcurrentDownloadManager {
-    pub fn new(concurrent_downloads: usize, retries: usize) -> Self {
-        let client = reqwest::Client::builder().build().unwrap();
-        let sem = Arc::new(Semaphore::new(concurrent_downloads));
+    pub fn new(
+        concurrent_downloads: usize,
+        retries: usize,
+        client_builder: reqwest::ClientBuilder,
+    ) -> Self {
+        let client = client_builder.build().unwrap();
+        let scheduler = Scheduler::new(concurrent_downloads);
         let multi_progress = MultiProgress::new();
-        let tasks_results: HashMap<String, JoinHandle<Result<(), Report<DownloadError>>>> =
-            HashMap::new();
+        let tasks_results = HashMap::new();
 
         Self {
-            sem,
+            scheduler,
+            cancel: CancellationToken::new(),
+            jobs: Vec::new(),
+            episodes: HashMap::new(),
             client,
             multi_progress,
             retries,
+            airing_priority: QueueConfig::default().airing_priority,
+            free_space_reserve: DiskSpaceConfig::default().reserve_bytes(),
+            ledger: None,
+            hooks: Arc::new(HooksConfig::default()),
+            post_process: None,
+            subtitles: Some(Arc::new(SubtitlesConfig::default())),
+            finished: Arc::new(Mutex::new(Vec::new())),
+            pending: Arc::new(AtomicUsize::new(0)),
             tasks_results,
         }
     }
 
+    pub fn with_queue(mut self, config: &QueueConfig) -> Self {
+        self.set_queue(config);
+        self
+    }
+
+    /// Like `set_concurrency`, the queued jobs follow the new cap right away.
+    pub fn set_queue(&mut self, config: &QueueConfig) {
+        self.airing_priority = config.airing_priority;
+        self.scheduler
+            .set_group_limit(Some(config.max_per_show).filter(|&limit| limit > 0));
+    }
+
+    pub fn with_disk_space(mut self, config: &DiskSpaceConfig) -> Self {
+        self.free_space_reserve = config.reserve_bytes();
+        self
+    }
+
+    /// Skips episodes the ledger knows about and records finished ones in it.
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: &HooksConfig) -> Self {
+        self.hooks = Arc::new(hooks.clone());
+        self
+    }
+
+    pub fn with_post_process(mut self, config: &PostProcessConfig) -> Self {
+        self.post_process = config.enabled.then(|| Arc::new(config.clone()));
+        self
+    }
+
+    /// Saves the subtitles of streamed episodes, or embeds them when
+    /// post-processing is on too.
+    pub fn with_subtitles(mut self, config: &SubtitlesConfig) -> Self {
+        self.subtitles = config.download.then(|| Arc::new(config.clone()));
+        self
+    }
+
+    /// Sums up what's left to download of each `(ep_path, ep_url)` by asking
+    /// the file hosts for the size of the links that would be picked.
+    pub async fn estimate_size(
+        &self,
+        gogo_anime: &Arc<GogoAnime>,
+        pref_res: &str,
+        episodes: &[(String, String)],
+    ) -> SizeEstimate {
+        let sem = Arc::new(Semaphore::new(self.scheduler.limit()));
+        let mut set = JoinSet::new();
+        for (ep_path, ep_url) in episodes {
+            let gogo_anime = gogo_anime.clone();
+            let client = self.client.clone();
+            let sem = sem.clone();
+            let pref_res = pref_res.to_string();
+            let ep_path = ep_path.clone();
+            let ep_url = ep_url.clone();
+            let in_ledger = self.ledger.as_ref().is_some_and(|ledger| {
+                EpisodeId::parse(&ep_url).is_some_and(|episode| ledger.find(&episode).is_some())
+            });
+            set.spawn(async move {
+                if in_ledger {
+                    return Some(0);
+                }
+                let _permit = sem.acquire().await.unwrap();
+                remaining_bytes(&gogo_anime, &client, &pref_res, &ep_path, &ep_url).await
+            });
+        }
+
+        let mut estimate = SizeEstimate::default();
+        while let Some(result) = set.join_next().await {
+            match result.ok().flatten() {
+                Some(bytes) => estimate.remaining_bytes += bytes,
+                None => estimate.unknown += 1,
+            }
+        }
+        estimate
+    }
+
+    /// Queues an episode, the returned handle pauses, resumes, cancels or
+    /// reprioritizes it. Episodes of airing shows start with the airing
+    /// priority. Returns `None` if the episode is queued already.
     pub fn add_gogo_download(
         &mut self,
         gogo_anime: Arc<GogoAnime>,
+        anime: &AnimeDetailedInfo,
         pref_res: &str,
         ep_path: &str,
         ep_url: &str,
-    ) {
+    ) -> Option<JobHandle> {
+        if let (Some(ledger), Some(episode)) = (&self.ledger, EpisodeId::parse(ep_url)) {
+            // Only writes the ledger the first time a show is queued.
+            let _ = ledger.learn_anime_id(&episode.anime, &anime.anime_id);
+        }
+        let episode = QueuedEpisode {
+            metadata: EpisodeMetadata {
+                show: anime.name.clone(),
+                episode: EpisodeId::parse(ep_url).map(|episode| episode.episode),
+                cover_url: Some(anime.thumbnail.clone()).filter(|url| !url.is_empty()),
+            },
+            ep_path: ep_path.to_string(),
+            ep_url: ep_url.to_string(),
+            priority: if anime.is_airing() {
+                self.airing_priority
+            } else {
+                0
+            },
+        };
+        self.add_episode(gogo_anime, pref_res, episode)
+    }
+
+    /// Queues an episode that was queued before, e.g. one an earlier run was
+    /// interrupted in. Returns `None` if the episode is queued already, two
+    /// jobs would write to the same file.
+    pub fn add_episode(
+        &mut self,
+        gogo_anime: Arc<GogoAnime>,
+        pref_res: &str,
+        episode: QueuedEpisode,
+    ) -> Option<JobHandle> {
+        if self.is_queued(&episode.ep_path) {
+            return None;
+        }
+        let metadata = episode.metadata.clone();
+        let ep_path = episode.ep_path.clone();
+        let ep_url = episode.ep_url.clone();
+        let priority = episode.priority;
         let pb = self.multi_progress.add(ProgressBar::new(0));
-        pb.set_style(ProgressStyle::with_template("{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-            .unwrap()
-            .progress_chars("#>-"));
-        let task = task::spawn(gogo_downloader_task(
+        pb.set_style(bytes_style());
+        let job =
+            self.scheduler
+                .new_job(&ep_path, &metadata.show, priority, &self.cancel, pb.clone());
+        self.episodes.insert(job.id(), episode);
+        self.jobs.push(job.clone());
+        let download = gogo_downloader_task(
             gogo_anime,
             pref_res.to_string(),
             self.retries,
+            self.free_space_reserve,
+            self.ledger.clone(),
+            self.post_process.clone(),
+            self.subtitles.clone(),
+            metadata.clone(),
             self.client.clone(),
             ep_path.to_string(),
             ep_url.to_string(),
-            self.sem.clone(),
-            pb,
-        ));
-        self.tasks_results.insert(ep_path.to_string(), task);
+            job.clone(),
+            pb.clone(),
+        );
+        let mut event = EpisodeEvent {
+            anime: metadata.show,
+            episode: metadata.episode,
+            url: ep_url.to_string(),
+            path: ep_path.to_string(),
+            outcome: EpisodeOutcome::Failed,
+            source: None,
+            error: None,
+        };
+        let hooks = self.hooks.clone();
+        let finished = self.finished.clone();
+        let pending = self.pending.clone();
+        pending.fetch_add(1, Ordering::SeqCst);
+        let handle = job.clone();
+        let task = task::spawn(async move {
+            let result = download.await;
+            handle.finish().await;
+            match &result {
+                Ok(DownloadStatus::Downloaded { path, source }) => {
+                    event.outcome = EpisodeOutcome::Downloaded;
+                    event.path = path.to_string_lossy().to_string();
+                    event.source = Some(source.to_string());
+                }
+                Ok(DownloadStatus::Skipped(existing)) => {
+                    event.outcome = EpisodeOutcome::Skipped;
+                    event.path = existing.to_string_lossy().to_string();
+                }
+                Ok(DownloadStatus::Cancelled(_)) => event.outcome = EpisodeOutcome::Cancelled,
+                Err(report) => event.error = Some(utils::report_summary(report)),
+            }
+            if let Err(report) = hooks.episode_finished(&event).await {
+                pb.println(utils::report_summary(&report));
+            }
+            finished.lock().unwrap().push(event);
+            // The queue ran empty, whatever is queued from now on is the
+            // next batch.
+            if pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                let episodes = std::mem::take(&mut *finished.lock().unwrap());
+                if !episodes.is_empty() {
+                    if let Err(report) = hooks.batch_finished(&BatchEvent::new(episodes)).await {
+                        pb.println(utils::report_summary(&report));
+                    }
+                }
+            }
+            result
+        });
+        self.tasks_results
+            .insert(job.id(), (ep_path.to_string(), task));
+        Some(job)
     }
 
-    pub async fn await_results(&mut self) -> HashMap<String, Result<(), Report<DownloadError>>> {
+    /// Whether a job that hasn't finished downloads to `ep_path`.
+    pub fn is_queued(&self, ep_path: &str) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| job.name() == ep_path && job.state() != JobState::Finished)
+    }
+
+    /// The episodes in `results` that were cancelled, so they can be queued
+    /// again later.
+    pub fn cancelled_episodes(&self, results: &JobResults) -> Vec<QueuedEpisode> {
+        self.jobs
+            .iter()
+            .filter(|job| {
+                matches!(
+                    results.get(&job.id()),
+                    Some((_, Ok(DownloadStatus::Cancelled(_))))
+                )
+            })
+            .filter_map(|job| self.queued_episode(job))
+            .collect()
+    }
+
+    /// The episode of `job` with the priority it has now.
+    fn queued_episode(&self, job: &JobHandle) -> Option<QueuedEpisode> {
+        let mut episode = self.episodes.get(&job.id())?.clone();
+        episode.priority = job.priority();
+        Some(episode)
+    }
+
+    /// Hides the progress bars, for a UI that draws the jobs' progress itself.
+    pub fn with_hidden_progress(self) -> Self {
+        self.set_progress_visible(false);
+        self
+    }
+
+    /// Hides the progress bars while something else uses the terminal, e.g.
+    /// a prompt, and draws them again afterwards.
+    pub fn set_progress_visible(&self, visible: bool) {
+        self.multi_progress.set_draw_target(if visible {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        });
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.scheduler.limit()
+    }
+
+    /// Takes effect right away for the queued jobs, running ones are left
+    /// alone.
+    pub fn set_concurrency(&self, concurrent_downloads: usize) {
+        self.scheduler.set_limit(concurrent_downloads);
+    }
+
+    /// Removes the jobs that are done from the ones `await_results` waits
+    /// for and returns their results.
+    pub async fn take_finished(&mut self) -> JobResults {
+        let finished: Vec<JobId> = self
+            .tasks_results
+            .iter()
+            .filter(|(_, (_, task))| task.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        let mut results = HashMap::new();
+        for id in finished {
+            let (path, task) = self.tasks_results.remove(&id).unwrap();
+            results.insert(id, (path, task.await.unwrap()));
+        }
+        results
+    }
+
+    /// Forgets the finished jobs whose results were taken, all but the last
+    /// `keep` of them, together with their progress bars.
+    pub fn prune_finished(&mut self, keep: usize) {
+        let done = |job: &JobHandle| {
+            job.state() == JobState::Finished && !self.tasks_results.contains_key(&job.id())
+        };
+        let mut excess = self
+            .jobs
+            .iter()
+            .filter(|job| done(job))
+            .count()
+            .saturating_sub(keep);
+        let mut pruned = Vec::new();
+        for job in &self.jobs {
+            if excess > 0 && done(job) {
+                excess -= 1;
+                pruned.push(job.id());
+            }
+        }
+        if pruned.is_empty() {
+            return;
+        }
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.jobs)
+            .into_iter()
+            .partition(|job| pruned.contains(&job.id()));
+        self.jobs = kept;
+        for job in removed {
+            self.multi_progress.remove(job.progress());
+            self.episodes.remove(&job.id());
+        }
+    }
+
+    /// The episodes whose jobs haven't finished yet.
+    pub fn unfinished_episodes(&self) -> Vec<QueuedEpisode> {
+        self.jobs
+            .iter()
+            .filter(|job| job.state() != JobState::Finished)
+            .filter_map(|job| self.queued_episode(job))
+            .collect()
+    }
+
+    /// The handles of every queued episode, in the order they were added.
+    pub fn jobs(&self) -> &[JobHandle] {
+        &self.jobs
+    }
+
+    /// Cancels every job, running and queued. `await_results` then returns
+    /// once the running ones saved what they have.
+    pub fn cancel_all(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Cancelling it is the same as `cancel_all`, for a signal handler that
+    /// can't borrow the manager.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    pub async fn await_results(&mut self) -> JobResults {
         let mut results = HashMap::new();
-        for (path, task) in self.tasks_results.drain() {
+        for (id, (path, task)) in self.tasks_results.drain() {
             let result = task.await.unwrap();
-            results.insert(path, result);
+            results.insert(id, (path, result));
         }
         results
     }
 }
 
+/// What the queued episodes are expected to take up on disk.
+#[derive(Debug, Default)]
+pub struct SizeEstimate {
+    /// Bytes still to download, for the episodes whose size is known.
+    pub remaining_bytes: u64,
+    /// Episodes whose size couldn't be determined.
+    pub unknown: usize,
+}
+
+async fn remaining_bytes(
+    gogo_anime: &GogoAnime,
+    client: &Client,
+    pref_res: &str,
+    ep_path: &str,
+    ep_url: &str,
+) -> Option<u64> {
+    let sources = gogo_anime.episode_sources(ep_url, pref_res).await.ok()?;
+    // Streams don't announce their size up front.
+    let EpisodeSource::Direct(link) = sources.first()? else {
+        return None;
+    };
+    let content_length = fetch_content_length(client, &link.url).await.ok()?;
+    let existing = tokio::fs::metadata(ep_path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    Some(content_length.saturating_sub(existing))
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn gogo_downloader_task(
     gogo_anime: Arc<GogoAnime>,
     pref_res: String,
     mut retries: usize,
+    free_space_reserve: u64,
+    ledger: Option<Arc<Ledger>>,
+    post_process_config: Option<Arc<PostProcessConfig>>,
+    subtitles_config: Option<Arc<SubtitlesConfig>>,
+    metadata: EpisodeMetadata,
     client: Client,
     ep_path: String,
     ep_url: String,
-    sem: Arc<Semaphore>,
+    job: JobHandle,
     pb: ProgressBar,
-) -> Result<(), Report<DownloadError>> {
-    let _permit = sem.acquire().await.unwrap();
-    loop {
-        let download_links = match gogo_anime
-            .fetch_ep_download_links(&ep_url)
-            .await
-            .change_context(DownloadError)
-        {
-            Ok(ok) => ok,
-            Err(_) if retries > 0 => {
-                retries -= 1;
-                continue;
+) -> Result<DownloadStatus, Report<DownloadError>> {
+    let episode = EpisodeId::parse(&ep_url);
+    if let (Some(ledger), Some(episode)) = (&ledger, &episode) {
+        if let Some(existing) = ledger.find(episode) {
+            pb.finish_and_clear();
+            return Ok(DownloadStatus::Skipped(existing));
+        }
+    }
+
+    if job.start().await.is_err() {
+        pb.finish_and_clear();
+        return Ok(DownloadStatus::Cancelled(PathBuf::from(&ep_path)));
+    }
+    let mut sources: Vec<EpisodeSource> = Vec::new();
+    let mut subtitles: Vec<SavedSubtitle> = Vec::new();
+    let mut current = 0;
+    let status = loop {
+        // Also waits out a pause between attempts.
+        if job.checkpoint().await.is_err() {
+            break DownloadStatus::Cancelled(PathBuf::from(&ep_path));
+        }
+        if sources.is_empty() {
+            sources = match gogo_anime
+                .episode_sources(&ep_url, &pref_res)
+                .await
+                .change_context(DownloadError)
+            {
+                Ok(sources) if !sources.is_empty() => sources,
+                Ok(_) => {
+                    return Err(Report::new(DownloadError)
+                        .attach_printable(format!("No sources found for {}", ep_url)))
+                }
+                Err(_) if retries > 0 => {
+                    retries -= 1;
+                    job.retried();
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            current = 0;
+        }
+
+        let source = &sources[current];
+        let result = match source {
+            EpisodeSource::Direct(link) => {
+                file_downloader_task(&client, &link.url, &ep_path, free_space_reserve, &job, &pb)
+                    .await
+            }
+            EpisodeSource::Stream(server) => {
+                download_stream(
+                    &gogo_anime,
+                    &client,
+                    server,
+                    &pref_res,
+                    &ep_path,
+                    free_space_reserve,
+                    subtitles_config.as_deref(),
+                    &mut subtitles,
+                    &job,
+                    &pb,
+                )
+                .await
             }
-            Err(err) => return Err(err),
         };
-        let resolutions: Vec<&String> = download_links.keys().collect();
-        let closest_res = utils::closest_resolution(&resolutions[..], &pref_res);
-        match file_downloader_task(
-            &client,
-            &download_links.get(&closest_res).unwrap().to_string(),
-            &ep_path,
-            &pb,
-        )
-        .await
-        {
-            Ok(_) => break,
+        match result {
+            Ok(Fetched::New(path)) => {
+                break DownloadStatus::Downloaded {
+                    path,
+                    source: source.clone(),
+                }
+            }
+            Ok(Fetched::Existing(path)) => break DownloadStatus::Skipped(path),
+            Err(_) if is_cancelled(&result) => {
+                break DownloadStatus::Cancelled(PathBuf::from(&ep_path))
+            }
+            // The partial file is kept, the next direct source only resumes
+            // it if it's the same file.
+            Err(_) if is_permanent(&result) && current + 1 < sources.len() => current += 1,
             Err(_) if retries > 0 => {
                 retries -= 1;
-                continue;
+                job.retried();
+                // Every source failed, start over with fresh links.
+                if is_permanent(&result) {
+                    gogo_anime.forget_ep_download_links(&ep_url);
+                    sources.clear();
+                }
+            }
+            Err(err) => {
+                return Err(err.attach_printable(format!(
+                    "Tried {} of {} sources, the last one was {}",
+                    current + 1,
+                    sources.len(),
+                    source
+                )))
             }
-            Err(err) => return Err(err),
         };
+    };
+
+    // Only the subtitles to embed are handed to ffmpeg, the rest stay as files.
+    if !subtitles_config.is_some_and(|config| config.embed) {
+        subtitles.clear();
+    }
+    // Files that were already complete on disk but never processed count too,
+    // the ones the ledger knew about were skipped above. Cancelling kills
+    // ffmpeg, the downloaded file is processed the next time.
+    if post_process_config.is_some() {
+        job.release_slot().await;
+        job.set_unit(ProgressUnit::Processing);
+    }
+    let status = match (status, post_process_config) {
+        (DownloadStatus::Downloaded { path, source }, Some(config)) => tokio::select! {
+            processed = post_process(&config, &client, &path, &metadata, &subtitles, &pb) => {
+                DownloadStatus::Downloaded {
+                    path: processed.change_context(DownloadError)?,
+                    source,
+                }
+            }
+            _ = job.cancelled() => DownloadStatus::Cancelled(path.clone()),
+        },
+        // Not the finished stream next to it or a file the ledger found
+        // elsewhere, those were processed already.
+        (DownloadStatus::Skipped(path), Some(config)) if path == Path::new(&ep_path) => {
+            tokio::select! {
+                processed = post_process(&config, &client, &path, &metadata, &subtitles, &pb) => {
+                    DownloadStatus::Skipped(processed.change_context(DownloadError)?)
+                }
+                _ = job.cancelled() => DownloadStatus::Cancelled(path.clone()),
+            }
+        }
+        (status, _) => status,
+    };
+    if let DownloadStatus::Cancelled(_) = status {
+        pb.abandon_with_message(format!("Cancelled {}", metadata.title()));
+        return Ok(status);
+    }
+
+    if let (Some(ledger), Some(episode)) = (ledger, episode) {
+        let path = status.path().to_path_buf();
+        // The ledger only saves work later on, the episode is there either way.
+        let _ = task::spawn_blocking(move || ledger.record(&episode, &path)).await;
     }
-    Ok(())
+    Ok(status)
+}
+
+/// Marks failures that retrying the same source won't fix, e.g. because its
+/// host doesn't have the file.
+#[derive(Debug)]
+pub struct PermanentFailure;
+
+/// Connection failures and server errors are usually over soon, they're
+/// retried with the same source.
+fn is_permanent<T>(result: &Result<T, Report<DownloadError>>) -> bool {
+    let Err(report) = result else {
+        return false;
+    };
+    report.contains::<PermanentFailure>()
+        || report.frames().any(|frame| {
+            frame.downcast_ref::<reqwest::Error>().is_some_and(|err| {
+                err.is_builder() || err.status().is_some_and(|status| status.is_client_error())
+            })
+        })
+}
+
+/// Next to a partial download, the file it's a part of.
+pub(crate) fn partial_source_path(path: &Path) -> PathBuf {
+    let mut source_path = path.as_os_str().to_owned();
+    source_path.push(".source");
+    PathBuf::from(source_path)
+}
+
+/// The file behind a download link: links are signed for each visit, only
+/// the host and path stay the same.
+fn file_identity(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(url) => format!("{}{}", url.host_str().unwrap_or_default(), url.path()),
+        Err(_) => url.to_string(),
+    }
+}
+
+fn is_cancelled<T>(result: &Result<T, Report<DownloadError>>) -> bool {
+    matches!(result, Err(report) if report.contains::<Cancelled>())
+}
+
+/// Where a source's file ended up.
+pub enum Fetched {
+    New(PathBuf),
+    /// It was already complete before.
+    Existing(PathBuf),
+}
+
+/// Downloads the HLS stream a streaming server plays and, with a
+/// `subtitles_config`, the subtitle tracks that come with it.
+#[allow(clippy::too_many_arguments)]
+async fn download_stream(
+    gogo_anime: &GogoAnime,
+    client: &Client,
+    server: &StreamingServer,
+    pref_res: &str,
+    ep_path: &str,
+    free_space_reserve: u64,
+    subtitles_config: Option<&SubtitlesConfig>,
+    subtitles: &mut Vec<SavedSubtitle>,
+    job: &JobHandle,
+    pb: &ProgressBar,
+) -> Result<Fetched, Report<DownloadError>> {
+    let stream = gogo_anime
+        .resolve_stream(server)
+        .await
+        .change_context(DownloadError)
+        .attach(PermanentFailure)?;
+    // Segments are already retried on their own.
+    let fetched = hls::download(
+        client,
+        &stream,
+        pref_res,
+        Path::new(ep_path),
+        free_space_reserve,
+        job,
+        pb,
+    )
+    .await
+    .change_context(DownloadError)
+    .attach(PermanentFailure)?;
+
+    if let Some(config) = subtitles_config {
+        let video = match &fetched {
+            Fetched::New(path) | Fetched::Existing(path) => path,
+        };
+        let (saved, errors) = subtitles::save_subtitles(config, client, &stream, video).await;
+        // The episode is there either way, a missing subtitle isn't worth a retry.
+        for report in errors {
+            pb.println(utils::report_summary(&report));
+        }
+        *subtitles = saved;
+    }
+    Ok(fetched)
+}
+
+fn bytes_style() -> ProgressStyle {
+    ProgressStyle::with_template("{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+        .unwrap()
+        .progress_chars("#>-")
 }
 
 async fn file_downloader_task(
     client: &Client,
     url: &str,
     path: &str,
+    free_space_reserve: u64,
+    job: &JobHandle,
     pb: &ProgressBar,
-) -> Result<(), Report<DownloadError>> {
+) -> Result<Fetched, Report<DownloadError>> {
     let file_path = Path::new(&path);
     if let Some(parent) = file_path.parent() {
         create_dir_all(parent)
@@ -152,7 +786,13 @@ async fn file_downloader_task(
             .attach_printable(format!("Error while directoire(s) {}", parent.display()))?;
     }
 
-    let mut file = if file_path.exists() && file_path.is_file() {
+    // A partial file from another host or file may not hold the same bytes.
+    let source_path = partial_source_path(file_path);
+    let identity = file_identity(url);
+    let other_source = tokio::fs::read_to_string(&source_path)
+        .await
+        .is_ok_and(|partial_of| partial_of != identity);
+    let mut file = if file_path.is_file() && !other_source {
         OpenOptions::new()
             .append(true)
             .open(&file_path)
@@ -176,67 +816,151 @@ async fn file_downloader_task(
         ))?
         .len();
 
-    let head_response = client
-        .head(url)
-        .send()
-        .await
-        .change_context(DownloadError)
-        .attach_printable(format!("Error while sendiing a head request to {}", url))?;
-    let content_length = head_response
-        .headers()
-        .get("Content-Length")
-        .ok_or_else(|| Report::new(DownloadError))
-        .attach_printable(format!("Content-Length header not found in {}", url))?
-        .to_str()
-        .map_err(|_| Report::new(DownloadError))?
-        .parse::<u64>()
-        .map_err(|_| Report::new(DownloadError))?;
+    let content_length = fetch_content_length(client, url).await?;
+    // A streaming attempt before this one may have changed the style.
+    pb.set_style(bytes_style());
+    job.set_unit(ProgressUnit::Bytes);
     pb.set_length(content_length);
 
     if file_size >= content_length {
-        return Ok(());
+        let _ = tokio::fs::remove_file(&source_path).await;
+        pb.finish_and_clear();
+        return Ok(Fetched::Existing(file_path.to_path_buf()));
     }
-
-    let request = client
-        .get(url)
-        .header("Range", format!("bytes={}-", file_size));
-    let mut stream = request
-        .send()
+    tokio::fs::write(&source_path, &identity)
         .await
         .change_context(DownloadError)
-        .attach_printable(format!("Error while sending a get request to {}", url))?
-        .bytes_stream();
+        .attach_printable(format!("Failed to write {}", source_path.display()))?;
 
     let mut total_bytes = file_size;
     let file_name = OsStr::to_str(file_path.file_name().unwrap())
         .unwrap_or("Unkown")
         .to_string();
 
-    pb.set_message(file_name.clone());
     pb.set_message(file_name.clone());
     pb.set_position(total_bytes);
     pb.reset_eta();
 
-    while let Some(chunk_result) = stream.next().await {
-        let chunk = chunk_result
-            .change_context(DownloadError)
-            .attach_printable("Error occurred while processing a chunk of data")?;
-        let new = min(total_bytes + chunk.len() as u64, content_length);
-        total_bytes = new;
-        pb.set_position(total_bytes);
-        file.write_all(&chunk)
+    if free_space_reserve > 0 {
+        wait_for_free_space(file_path, free_space_reserve, &file_name, job, pb).await;
+    }
+    'request: loop {
+        let request = client
+            .get(url)
+            .header("Range", format!("bytes={}-", total_bytes));
+        let mut stream = request
+            .send()
             .await
             .change_context(DownloadError)
-            .attach_printable(format!(
-                "Failed to write buffer to file {}",
-                file_path.display()
-            ))?;
+            .attach_printable(format!("Error while sending a get request to {}", url))?
+            .error_for_status()
+            .change_context(DownloadError)?
+            .bytes_stream();
+
+        let mut unchecked_bytes = 0;
+        loop {
+            let chunk_result = tokio::select! {
+                chunk_result = stream.next() => chunk_result,
+                _ = job.interrupted() => {
+                    // What's written so far is kept, the rest is requested
+                    // again once the job is resumed.
+                    file.flush()
+                        .await
+                        .change_context(DownloadError)
+                        .attach_printable(format!("Failed to flush file {}", file_path.display()))?;
+                    pb.set_message(format!("{} paused", file_name));
+                    job.checkpoint()
+                        .await
+                        .map_err(|cancelled| Report::new(DownloadError).attach(cancelled))?;
+                    pb.set_message(file_name.clone());
+                    continue 'request;
+                }
+            };
+            let Some(chunk_result) = chunk_result else {
+                break;
+            };
+            let chunk = chunk_result
+                .change_context(DownloadError)
+                .attach_printable("Error occurred while processing a chunk of data")?;
+            unchecked_bytes += chunk.len() as u64;
+            if free_space_reserve > 0 && unchecked_bytes >= FREE_SPACE_CHECK_INTERVAL {
+                unchecked_bytes = 0;
+                // The connection is dropped while waiting and the rest of the
+                // file requested again afterwards, an idle one would time out.
+                if wait_for_free_space(file_path, free_space_reserve, &file_name, job, pb).await {
+                    continue 'request;
+                }
+            }
+            let new = min(total_bytes + chunk.len() as u64, content_length);
+            total_bytes = new;
+            pb.set_position(total_bytes);
+            file.write_all(&chunk)
+                .await
+                .change_context(DownloadError)
+                .attach_printable(format!(
+                    "Failed to write buffer to file {}",
+                    file_path.display()
+                ))?;
+        }
+        break;
     }
 
     file.flush()
         .await
         .change_context(DownloadError)
         .attach_printable(format!("Failed to flush file {}", file_path.display()))?;
+    let _ = tokio::fs::remove_file(&source_path).await;
     pb.finish_with_message(format!("Downloaded {}", file_name));
-    Ok(())
+    Ok(Fetched::New(file_path.to_path_buf()))
+}
+
+async fn fetch_content_length(client: &Client, url: &str) -> Result<u64, Report<DownloadError>> {
+    let head_response = client
+        .head(url)
+        .send()
+        .await
+        .change_context(DownloadError)
+        .attach_printable(format!("Error while sendiing a head request to {}", url))?
+        .error_for_status()
+        .change_context(DownloadError)?;
+    head_response
+        .headers()
+        .get("Content-Length")
+        .ok_or_else(|| Report::new(DownloadError).attach(PermanentFailure))
+        .attach_printable(format!("Content-Length header not found in {}", url))?
+        .to_str()
+        .map_err(|_| Report::new(DownloadError))?
+        .parse::<u64>()
+        .map_err(|_| Report::new(DownloadError))
+}
+
+/// Holds the download back while less than `reserve` bytes are free on the
+/// filesystem `path` is on, or until `job` is cancelled. Returns whether it
+/// had to wait.
+pub(crate) async fn wait_for_free_space(
+    path: &Path,
+    reserve: u64,
+    file_name: &str,
+    job: &JobHandle,
+    pb: &ProgressBar,
+) -> bool {
+    let mut paused = false;
+    while matches!(disk_space::available_space(path), Ok(free) if free < reserve) {
+        if !paused {
+            pb.set_message(format!(
+                "{} paused, less than {} free",
+                file_name,
+                HumanBytes(reserve)
+            ));
+            paused = true;
+        }
+        tokio::select! {
+            _ = sleep(FREE_SPACE_POLL) => {}
+            _ = job.cancelled() => break,
+        }
+    }
+    if paused {
+        pb.set_message(file_name.to_string());
+    }
+    paused
 }
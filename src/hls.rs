@@ -0,0 +1,684 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use error_stack::{Context, Report, ResultExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use url::Url;
+
+use crate::anime_downloader::gogo::ResolvedStream;
+use crate::anime_downloader::streaming::SubtitleTrack;
+use crate::download_manager::{wait_for_free_space, Fetched};
+use crate::jobs::{Cancelled, JobHandle, ProgressUnit};
+use crate::utils;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// Segments of one episode downloaded at the same time.
+const SEGMENT_CONCURRENCY: usize = 4;
+const SEGMENT_ATTEMPTS: usize = 3;
+
+#[derive(Debug)]
+pub struct HlsError;
+
+impl fmt::Display for HlsError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while downloading an HLS stream")
+    }
+}
+
+impl Context for HlsError {}
+
+#[derive(Debug)]
+struct Variant {
+    resolution: Option<String>,
+    bandwidth: u64,
+    uri: String,
+}
+
+#[derive(Debug, Clone)]
+struct SegmentKey {
+    uri: String,
+    iv: Option<[u8; 16]>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    uri: String,
+    sequence: u64,
+    key: Option<SegmentKey>,
+}
+
+#[derive(Debug, Default)]
+struct MediaPlaylist {
+    /// The `EXT-X-MAP` init section of fragmented MP4 streams.
+    init: Option<String>,
+    segments: Vec<Segment>,
+}
+
+/// Downloads the stream into a single file next to `ep_path`: `.ts` for
+/// MPEG-TS segments, `ep_path` itself for fragmented MP4. Finished segments
+/// are kept in a `.parts` folder until everything is there, so an interrupted
+/// download picks up where it left off.
+pub async fn download(
+    client: &Client,
+    stream: &ResolvedStream,
+    pref_res: &str,
+    ep_path: &Path,
+    free_space_reserve: u64,
+    job: &JobHandle,
+    pb: &ProgressBar,
+) -> Result<Fetched, Report<HlsError>> {
+    let mut playlist_url = stream.playlist_url.clone();
+    let mut playlist = fetch_text(client, stream, &playlist_url).await?;
+    if playlist.contains("#EXT-X-STREAM-INF") {
+        let variants = parse_master_playlist(&playlist, &playlist_url)?;
+        playlist_url = pick_variant(&variants, pref_res)
+            .ok_or_else(|| {
+                Report::new(HlsError)
+                    .attach_printable(format!("No variants in {}", stream.playlist_url))
+            })?
+            .uri
+            .clone();
+        playlist = fetch_text(client, stream, &playlist_url).await?;
+    }
+    let media = parse_media_playlist(&playlist, &playlist_url)?;
+    if media.segments.is_empty() {
+        return Err(
+            Report::new(HlsError).attach_printable(format!("No segments in {}", playlist_url))
+        );
+    }
+
+    let output = if media.init.is_some() {
+        ep_path.to_path_buf()
+    } else {
+        ep_path.with_extension("ts")
+    };
+    let parts_dir = output.with_extension("parts");
+    if output.exists() && !parts_dir.exists() {
+        pb.finish_and_clear();
+        return Ok(Fetched::Existing(output));
+    }
+    tokio::fs::create_dir_all(&parts_dir)
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to create {}", parts_dir.display()))?;
+
+    let keys = fetch_keys(client, stream, &media).await?;
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} segments ({eta})",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+    pb.set_message(file_name.clone());
+    pb.set_length(media.segments.len() as u64);
+    job.set_unit(ProgressUnit::Segments);
+    pb.set_position(0);
+    pb.reset_eta();
+
+    let sem = Arc::new(Semaphore::new(SEGMENT_CONCURRENCY));
+    let mut set = JoinSet::new();
+    for (idx, segment) in media.segments.iter().enumerate() {
+        let part_path = part_path(&parts_dir, idx);
+        if part_path.exists() {
+            pb.inc(1);
+            continue;
+        }
+        let key = segment
+            .key
+            .as_ref()
+            .map(|key| (keys[&key.uri].clone(), key_iv(key, segment.sequence)));
+        let client = client.clone();
+        let stream = stream.clone();
+        let uri = segment.uri.clone();
+        let sem = sem.clone();
+        let job = job.clone();
+        let pb = pb.clone();
+        let file_name = file_name.clone();
+        set.spawn(async move {
+            let _permit = sem.acquire().await.unwrap();
+            if free_space_reserve > 0 {
+                wait_for_free_space(&part_path, free_space_reserve, &file_name, &job, &pb).await;
+            }
+            job.checkpoint()
+                .await
+                .map_err(|cancelled| Report::new(HlsError).attach(cancelled))?;
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                match download_segment(&client, &stream, &uri, key.as_ref(), &part_path).await {
+                    Ok(()) => {
+                        pb.inc(1);
+                        return Ok(());
+                    }
+                    Err(_) if attempt < SEGMENT_ATTEMPTS => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+        });
+    }
+    while let Some(result) = set.join_next().await {
+        match result.change_context(HlsError)? {
+            // Segments that are already downloading are finished and kept.
+            Err(report) if report.contains::<Cancelled>() => {}
+            result => result?,
+        }
+    }
+    if job.is_cancelled() {
+        return Err(Report::new(HlsError).attach(Cancelled));
+    }
+
+    // Joining the segments takes as much space again.
+    if free_space_reserve > 0 {
+        wait_for_free_space(&parts_dir, free_space_reserve, &file_name, job, pb).await;
+    }
+    concatenate(client, stream, &media, &parts_dir, &output).await?;
+    tokio::fs::remove_dir_all(&parts_dir)
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to remove {}", parts_dir.display()))?;
+    pb.finish_with_message(format!("Downloaded {}", file_name));
+    Ok(Fetched::New(output))
+}
+
+fn part_path(parts_dir: &Path, idx: usize) -> PathBuf {
+    parts_dir.join(format!("{:05}.seg", idx))
+}
+
+async fn fetch(
+    client: &Client,
+    stream: &ResolvedStream,
+    url: &str,
+) -> Result<Vec<u8>, Report<HlsError>> {
+    let bytes = client
+        .get(url)
+        .header("Referer", &stream.referer)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to request {}", url))?
+        .bytes()
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to read {}", url))?;
+    Ok(bytes.to_vec())
+}
+
+async fn fetch_text(
+    client: &Client,
+    stream: &ResolvedStream,
+    url: &str,
+) -> Result<String, Report<HlsError>> {
+    let bytes = fetch(client, stream, url).await?;
+    String::from_utf8(bytes)
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("{} is not a playlist", url))
+}
+
+/// The AES-128 keys of every segment, by key URI.
+async fn fetch_keys(
+    client: &Client,
+    stream: &ResolvedStream,
+    media: &MediaPlaylist,
+) -> Result<HashMap<String, Vec<u8>>, Report<HlsError>> {
+    let mut keys = HashMap::new();
+    for segment in &media.segments {
+        let Some(key) = &segment.key else {
+            continue;
+        };
+        if keys.contains_key(&key.uri) {
+            continue;
+        }
+        let bytes = fetch(client, stream, &key.uri).await?;
+        if bytes.len() != 16 {
+            return Err(Report::new(HlsError).attach_printable(format!(
+                "The key at {} is {} bytes long, expected 16",
+                key.uri,
+                bytes.len()
+            )));
+        }
+        keys.insert(key.uri.clone(), bytes);
+    }
+    Ok(keys)
+}
+
+/// Without an explicit IV the segment's media sequence number is used.
+fn key_iv(key: &SegmentKey, sequence: u64) -> [u8; 16] {
+    key.iv.unwrap_or_else(|| (sequence as u128).to_be_bytes())
+}
+
+async fn download_segment(
+    client: &Client,
+    stream: &ResolvedStream,
+    uri: &str,
+    key: Option<&(Vec<u8>, [u8; 16])>,
+    part_path: &Path,
+) -> Result<(), Report<HlsError>> {
+    let mut data = fetch(client, stream, uri).await?;
+    if let Some((key, iv)) = key {
+        let cipher = Aes128CbcDec::new_from_slices(key, iv).map_err(|_| Report::new(HlsError))?;
+        data = cipher
+            .decrypt_padded_vec_mut::<Pkcs7>(&data)
+            .map_err(|_| Report::new(HlsError))
+            .attach_printable_lazy(|| format!("Failed to decrypt {}", uri))?;
+    }
+    // Written under another name first so only complete segments count.
+    let tmp_path = part_path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, &data)
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to write {}", tmp_path.display()))?;
+    tokio::fs::rename(&tmp_path, part_path)
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to write {}", part_path.display()))?;
+    Ok(())
+}
+
+async fn concatenate(
+    client: &Client,
+    stream: &ResolvedStream,
+    media: &MediaPlaylist,
+    parts_dir: &Path,
+    output: &Path,
+) -> Result<(), Report<HlsError>> {
+    let tmp_output = output.with_extension("part");
+    let mut file = tokio::fs::File::create(&tmp_output)
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to create {}", tmp_output.display()))?;
+    if let Some(init) = &media.init {
+        file.write_all(&fetch(client, stream, init).await?)
+            .await
+            .change_context(HlsError)?;
+    }
+    for idx in 0..media.segments.len() {
+        let part = tokio::fs::read(part_path(parts_dir, idx))
+            .await
+            .change_context(HlsError)
+            .attach_printable_lazy(|| format!("Segment {} is missing", idx))?;
+        file.write_all(&part)
+            .await
+            .change_context(HlsError)
+            .attach_printable_lazy(|| format!("Failed to write {}", tmp_output.display()))?;
+    }
+    file.flush().await.change_context(HlsError)?;
+    tokio::fs::rename(&tmp_output, output)
+        .await
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Failed to move the stream to {}", output.display()))
+}
+
+/// The subtitle playlists a master playlist lists next to its variants.
+pub async fn subtitle_tracks(client: &Client, stream: &ResolvedStream) -> Vec<SubtitleTrack> {
+    let Ok(playlist) = fetch_text(client, stream, &stream.playlist_url).await else {
+        return Vec::new();
+    };
+    let Ok(base) = Url::parse(&stream.playlist_url) else {
+        return Vec::new();
+    };
+    playlist
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#EXT-X-MEDIA:"))
+        .map(parse_attributes)
+        .filter(|attributes| attributes.get("TYPE").map(String::as_str) == Some("SUBTITLES"))
+        .filter_map(|attributes| {
+            let url = resolve(&base, attributes.get("URI")?).ok()?;
+            let label = attributes.get("LANGUAGE").or(attributes.get("NAME"));
+            Some(SubtitleTrack::new(&url, label.map(String::as_str)))
+        })
+        .collect()
+}
+
+/// Joins the WebVTT segments of a subtitle playlist into a single file.
+pub async fn fetch_subtitle_playlist(
+    client: &Client,
+    stream: &ResolvedStream,
+    url: &str,
+) -> Result<String, Report<HlsError>> {
+    let playlist = fetch_text(client, stream, url).await?;
+    let media = parse_media_playlist(&playlist, url)?;
+    let mut vtt = String::from("WEBVTT\n\n");
+    for segment in &media.segments {
+        let text = fetch_text(client, stream, &segment.uri).await?;
+        let text = text.replace("\r\n", "\n");
+        // Every segment repeats the header, only the cues are kept.
+        let cues = match text.trim_start().strip_prefix("WEBVTT") {
+            Some(rest) => rest.split_once("\n\n").map_or("", |(_, cues)| cues),
+            None => &text,
+        };
+        vtt.push_str(cues.trim());
+        vtt.push_str("\n\n");
+    }
+    Ok(vtt)
+}
+
+/// The variant closest to `pref_res`, or the one with the highest bandwidth
+/// if the playlist doesn't list resolutions.
+fn pick_variant<'a>(variants: &'a [Variant], pref_res: &str) -> Option<&'a Variant> {
+    let resolutions: Vec<&String> = variants
+        .iter()
+        .filter_map(|variant| variant.resolution.as_ref())
+        .collect();
+    if resolutions.is_empty() {
+        return variants.iter().max_by_key(|variant| variant.bandwidth);
+    }
+    let closest = utils::closest_resolution(&resolutions, pref_res);
+    variants
+        .iter()
+        .filter(|variant| variant.resolution.as_ref() == Some(&closest))
+        .max_by_key(|variant| variant.bandwidth)
+}
+
+fn parse_master_playlist(playlist: &str, base: &str) -> Result<Vec<Variant>, Report<HlsError>> {
+    let base = Url::parse(base)
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Invalid playlist URL {}", base))?;
+    let mut variants = Vec::new();
+    let mut lines = playlist.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") else {
+            continue;
+        };
+        let attributes = parse_attributes(attributes);
+        let Some(uri) = lines.find(|line| !line.is_empty() && !line.starts_with('#')) else {
+            break;
+        };
+        variants.push(Variant {
+            resolution: attributes.get("RESOLUTION").cloned(),
+            bandwidth: attributes
+                .get("BANDWIDTH")
+                .and_then(|bandwidth| bandwidth.parse().ok())
+                .unwrap_or(0),
+            uri: resolve(&base, uri)?,
+        });
+    }
+    Ok(variants)
+}
+
+fn parse_media_playlist(playlist: &str, base: &str) -> Result<MediaPlaylist, Report<HlsError>> {
+    let base = Url::parse(base)
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Invalid playlist URL {}", base))?;
+    let mut media = MediaPlaylist::default();
+    let mut sequence = 0;
+    let mut key: Option<SegmentKey> = None;
+    for line in playlist.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = value.parse().unwrap_or(0);
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-KEY:") {
+            let attributes = parse_attributes(attributes);
+            key = match attributes.get("METHOD").map(String::as_str) {
+                Some("NONE") | None => None,
+                Some("AES-128") => {
+                    let uri = attributes.get("URI").ok_or_else(|| {
+                        Report::new(HlsError).attach_printable("AES-128 key without a URI")
+                    })?;
+                    Some(SegmentKey {
+                        uri: resolve(&base, uri)?,
+                        iv: attributes.get("IV").and_then(|iv| parse_iv(iv)),
+                    })
+                }
+                Some(method) => {
+                    return Err(Report::new(HlsError)
+                        .attach_printable(format!("Unsupported encryption {}", method)))
+                }
+            };
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+            if let Some(uri) = parse_attributes(attributes).get("URI") {
+                media.init = Some(resolve(&base, uri)?);
+            }
+        } else if !line.is_empty() && !line.starts_with('#') {
+            media.segments.push(Segment {
+                uri: resolve(&base, line)?,
+                sequence,
+                key: key.clone(),
+            });
+            sequence += 1;
+        }
+    }
+    Ok(media)
+}
+
+/// `BANDWIDTH=1280000,RESOLUTION=1280x720,CODECS="avc1.4d401f,mp4a.40.2"`
+fn parse_attributes(attributes: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = attributes.trim();
+    while let Some((name, value)) = rest.split_once('=') {
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => match value.find(',') {
+                Some(end) => (&value[..end], &value[end..]),
+                None => (value, ""),
+            },
+        };
+        parsed.insert(name.trim().to_string(), value.to_string());
+        rest = remaining.trim_start_matches(',').trim();
+    }
+    parsed
+}
+
+/// "0x1f2e..." -> 16 bytes
+fn parse_iv(iv: &str) -> Option<[u8; 16]> {
+    let hex = iv.strip_prefix("0x").or_else(|| iv.strip_prefix("0X"))?;
+    u128::from_str_radix(hex, 16).ok().map(u128::to_be_bytes)
+}
+
+fn resolve(base: &Url, uri: &str) -> Result<String, Report<HlsError>> {
+    base.join(uri)
+        .map(String::from)
+        .change_context(HlsError)
+        .attach_printable_lazy(|| format!("Invalid URI {} in the playlist", uri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-STREAM-INF:BANDWIDTH=400000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\"
+ep1.360.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1500000,RESOLUTION=1280x720,NAME=\"720p\"
+
+ep1.720.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1200000,RESOLUTION=1280x720
+/alt/ep1.720.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1920x1080
+https://cdn2.example/ep1.1080.m3u8
+";
+
+    const ENCRYPTED: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:7
+#EXTINF:10.0,
+seg-7.ts
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x000102030405060708090a0b0c0d0e0f
+#EXTINF:10.0,
+seg-8.ts
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example/k2\"
+#EXTINF:10.0,
+seg-9.ts
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:4.2,
+seg-10.ts
+#EXT-X-ENDLIST
+";
+
+    const FMP4: &str = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"
+#EXTINF:6.0,
+part0.m4s
+#EXTINF:6.0,
+part1.m4s
+#EXT-X-ENDLIST
+";
+
+    const BASE: &str = "https://cdn.example/hls/ep1/master.m3u8?token=abc";
+
+    #[test]
+    fn parses_master_playlist_variants() {
+        let variants = parse_master_playlist(MASTER, BASE).unwrap();
+        let parsed: Vec<_> = variants
+            .iter()
+            .map(|variant| {
+                (
+                    variant.resolution.as_deref(),
+                    variant.bandwidth,
+                    variant.uri.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (
+                    Some("640x360"),
+                    400000,
+                    "https://cdn.example/hls/ep1/ep1.360.m3u8"
+                ),
+                (
+                    Some("1280x720"),
+                    1500000,
+                    "https://cdn.example/hls/ep1/ep1.720.m3u8"
+                ),
+                (
+                    Some("1280x720"),
+                    1200000,
+                    "https://cdn.example/alt/ep1.720.m3u8"
+                ),
+                (
+                    Some("1920x1080"),
+                    3000000,
+                    "https://cdn2.example/ep1.1080.m3u8"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_the_closest_variant_with_the_most_bandwidth() {
+        let variants = parse_master_playlist(MASTER, BASE).unwrap();
+        let pick = |pref_res| pick_variant(&variants, pref_res).unwrap().uri.as_str();
+        assert_eq!(pick("1280x720"), "https://cdn.example/hls/ep1/ep1.720.m3u8");
+        assert_eq!(pick("1920x1080"), "https://cdn2.example/ep1.1080.m3u8");
+        assert_eq!(pick("480x270"), "https://cdn.example/hls/ep1/ep1.360.m3u8");
+
+        let unlabeled = parse_master_playlist(
+            "#EXT-X-STREAM-INF:BANDWIDTH=800000\nlow.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=2000000\nhigh.m3u8\n",
+            BASE,
+        )
+        .unwrap();
+        assert_eq!(
+            pick_variant(&unlabeled, "1280x720").unwrap().uri,
+            "https://cdn.example/hls/ep1/high.m3u8"
+        );
+        assert!(pick_variant(&[], "1280x720").is_none());
+    }
+
+    #[test]
+    fn parses_media_playlist_keys() {
+        let media = parse_media_playlist(ENCRYPTED, BASE).unwrap();
+        assert_eq!(media.init, None);
+        let segments: Vec<_> = media
+            .segments
+            .iter()
+            .map(|segment| (segment.uri.as_str(), segment.sequence))
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                ("https://cdn.example/hls/ep1/seg-7.ts", 7),
+                ("https://cdn.example/hls/ep1/seg-8.ts", 8),
+                ("https://cdn.example/hls/ep1/seg-9.ts", 9),
+                ("https://cdn.example/hls/ep1/seg-10.ts", 10),
+            ]
+        );
+
+        assert!(media.segments[0].key.is_none());
+        assert!(media.segments[3].key.is_none());
+
+        let explicit = media.segments[1].key.as_ref().unwrap();
+        assert_eq!(explicit.uri, "https://cdn.example/hls/ep1/key.bin");
+        let iv: [u8; 16] = std::array::from_fn(|idx| idx as u8);
+        assert_eq!(explicit.iv, Some(iv));
+        assert_eq!(key_iv(explicit, media.segments[1].sequence), iv);
+
+        let derived = media.segments[2].key.as_ref().unwrap();
+        assert_eq!(derived.uri, "https://keys.example/k2");
+        assert_eq!(derived.iv, None);
+        let mut iv = [0; 16];
+        iv[15] = 9;
+        assert_eq!(key_iv(derived, media.segments[2].sequence), iv);
+    }
+
+    #[test]
+    fn rejects_unsupported_keys() {
+        let sample_aes = "#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"key.bin\"\nseg-0.ts\n";
+        assert!(parse_media_playlist(sample_aes, BASE).is_err());
+        let no_uri = "#EXT-X-KEY:METHOD=AES-128\nseg-0.ts\n";
+        assert!(parse_media_playlist(no_uri, BASE).is_err());
+    }
+
+    #[test]
+    fn parses_fmp4_map() {
+        let media = parse_media_playlist(FMP4, BASE).unwrap();
+        assert_eq!(
+            media.init.as_deref(),
+            Some("https://cdn.example/hls/ep1/init.mp4")
+        );
+        let uris: Vec<_> = media
+            .segments
+            .iter()
+            .map(|segment| segment.uri.as_str())
+            .collect();
+        assert_eq!(
+            uris,
+            [
+                "https://cdn.example/hls/ep1/part0.m4s",
+                "https://cdn.example/hls/ep1/part1.m4s"
+            ]
+        );
+        assert_eq!(media.segments[0].sequence, 0);
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let attributes = parse_attributes(
+            "BANDWIDTH=1280000, RESOLUTION=1280x720,CODECS=\"avc1.4d401f,mp4a.40.2\",URI=\"a=b.m3u8\"",
+        );
+        assert_eq!(attributes["BANDWIDTH"], "1280000");
+        assert_eq!(attributes["RESOLUTION"], "1280x720");
+        assert_eq!(attributes["CODECS"], "avc1.4d401f,mp4a.40.2");
+        assert_eq!(attributes["URI"], "a=b.m3u8");
+        assert_eq!(attributes.len(), 4);
+        assert!(parse_attributes("").is_empty());
+    }
+
+    #[test]
+    fn parses_iv() {
+        let mut iv = [0; 16];
+        iv[14] = 0x1f;
+        iv[15] = 0x2e;
+        assert_eq!(parse_iv("0x1f2e"), Some(iv));
+        assert_eq!(parse_iv("0X00000000000000000000000000001F2E"), Some(iv));
+        assert_eq!(parse_iv("1f2e"), None);
+        assert_eq!(parse_iv("0xnothex"), None);
+    }
+}
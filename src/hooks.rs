@@ -0,0 +1,220 @@
+use error_stack::{Context, Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+#[derive(Debug)]
+pub struct HookError;
+
+impl fmt::Display for HookError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("A download hook failed")
+    }
+}
+
+impl Context for HookError {}
+
+/// Shell commands run when downloads finish. Each one gets the event as JSON
+/// on stdin and the most useful fields as `RUSTY_GOGO_*` variables.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// After an episode was downloaded.
+    pub on_episode_complete: Option<String>,
+    /// After an episode failed for good, i.e. ran out of retries.
+    pub on_episode_failed: Option<String>,
+    /// Once the download queue ran empty, with every episode that finished
+    /// since the last time, successfully or not.
+    pub on_batch_complete: Option<String>,
+    /// Seconds a hook may run before it's killed, 0 waits forever.
+    pub timeout: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_episode_complete: None,
+            on_episode_failed: None,
+            on_batch_complete: None,
+            timeout: 300,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EpisodeOutcome {
+    Downloaded,
+    Skipped,
+    Failed,
+    Cancelled,
+}
+
+impl EpisodeOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EpisodeOutcome::Downloaded => "downloaded",
+            EpisodeOutcome::Skipped => "skipped",
+            EpisodeOutcome::Failed => "failed",
+            EpisodeOutcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EpisodeEvent {
+    pub anime: String,
+    /// `None` if it couldn't be told from the episode URL.
+    pub episode: Option<String>,
+    pub url: String,
+    pub path: String,
+    pub outcome: EpisodeOutcome,
+    /// Which link or streaming server the episode came from.
+    pub source: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchEvent {
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub episodes: Vec<EpisodeEvent>,
+}
+
+impl BatchEvent {
+    pub fn new(episodes: Vec<EpisodeEvent>) -> Self {
+        let count = |outcome| episodes.iter().filter(|e| e.outcome == outcome).count();
+        Self {
+            downloaded: count(EpisodeOutcome::Downloaded),
+            skipped: count(EpisodeOutcome::Skipped),
+            failed: count(EpisodeOutcome::Failed),
+            cancelled: count(EpisodeOutcome::Cancelled),
+            episodes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a, T> {
+    event: &'a str,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+impl HooksConfig {
+    /// Runs `on_episode_complete` or `on_episode_failed`, skipped and
+    /// cancelled episodes don't trigger either.
+    pub async fn episode_finished(&self, event: &EpisodeEvent) -> Result<(), Report<HookError>> {
+        let (name, cmd) = match event.outcome {
+            EpisodeOutcome::Downloaded => ("episode_complete", &self.on_episode_complete),
+            EpisodeOutcome::Failed => ("episode_failed", &self.on_episode_failed),
+            EpisodeOutcome::Skipped | EpisodeOutcome::Cancelled => return Ok(()),
+        };
+        let Some(cmd) = cmd else {
+            return Ok(());
+        };
+        let env = vec![
+            ("RUSTY_GOGO_ANIME", event.anime.clone()),
+            (
+                "RUSTY_GOGO_EPISODE",
+                event.episode.clone().unwrap_or_default(),
+            ),
+            ("RUSTY_GOGO_URL", event.url.clone()),
+            ("RUSTY_GOGO_PATH", event.path.clone()),
+            ("RUSTY_GOGO_OUTCOME", event.outcome.as_str().to_string()),
+            (
+                "RUSTY_GOGO_SOURCE",
+                event.source.clone().unwrap_or_default(),
+            ),
+            ("RUSTY_GOGO_ERROR", event.error.clone().unwrap_or_default()),
+        ];
+        self.run(cmd, name, event, env).await
+    }
+
+    pub async fn batch_finished(&self, event: &BatchEvent) -> Result<(), Report<HookError>> {
+        let Some(cmd) = &self.on_batch_complete else {
+            return Ok(());
+        };
+        let env = vec![
+            ("RUSTY_GOGO_DOWNLOADED", event.downloaded.to_string()),
+            ("RUSTY_GOGO_SKIPPED", event.skipped.to_string()),
+            ("RUSTY_GOGO_FAILED", event.failed.to_string()),
+            ("RUSTY_GOGO_CANCELLED", event.cancelled.to_string()),
+        ];
+        self.run(cmd, "batch_complete", event, env).await
+    }
+
+    async fn run<T: Serialize>(
+        &self,
+        cmd: &str,
+        event: &str,
+        data: &T,
+        env: Vec<(&str, String)>,
+    ) -> Result<(), Report<HookError>> {
+        let payload = serde_json::to_vec(&Payload { event, data }).change_context(HookError)?;
+
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.args(["/C", cmd]);
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.args(["-c", cmd]);
+            command
+        };
+        // Inherited config overrides may hold the password or the API token.
+        for (name, _) in std::env::vars_os() {
+            if name.to_string_lossy().starts_with("RUSTY_GOGO_") {
+                command.env_remove(name);
+            }
+        }
+        // The output would garble the progress bars, it's only shown on failure.
+        let mut child = command
+            .env("RUSTY_GOGO_EVENT", event)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .change_context(HookError)
+            .attach_printable_lazy(|| format!("failed to run the {} hook `{}`", event, cmd))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        // Hooks that don't read stdin close it early, that's fine.
+        let _ = stdin.write_all(&payload).await;
+        drop(stdin);
+
+        let output = child.wait_with_output();
+        let output = if self.timeout > 0 {
+            tokio::time::timeout(Duration::from_secs(self.timeout), output)
+                .await
+                .change_context(HookError)
+                .attach_printable_lazy(|| {
+                    format!(
+                        "the {} hook `{}` took longer than {}s",
+                        event, cmd, self.timeout
+                    )
+                })?
+        } else {
+            output.await
+        }
+        .change_context(HookError)
+        .attach_printable_lazy(|| format!("failed to run the {} hook `{}`", event, cmd))?;
+
+        if !output.status.success() {
+            return Err(Report::new(HookError)
+                .attach_printable(format!(
+                    "the {} hook `{}` exited with {}",
+                    event, cmd, output.status
+                ))
+                .attach_printable(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(())
+    }
+}
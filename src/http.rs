@@ -0,0 +1,108 @@
+use error_stack::{Context, Report, ResultExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, ClientBuilder, Proxy};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct HttpClientError;
+
+impl fmt::Display for HttpClientError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Invalid HTTP client configuration")
+    }
+}
+
+impl Context for HttpClientError {}
+
+/// Settings shared by the scraping and the media download clients.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// `http://`, `https://` or `socks5://` proxy for every request. Without
+    /// it the usual `HTTP(S)_PROXY` environment variables are honoured.
+    pub proxy: Option<String>,
+    /// Seconds to wait for a connection, 0 waits forever.
+    pub connect_timeout: u64,
+    /// Seconds to wait for the next bit of a response, 0 waits forever.
+    pub read_timeout: u64,
+    pub user_agent: Option<String>,
+    /// Extra headers sent with every request.
+    pub headers: BTreeMap<String, String>,
+    /// PEM certificates to trust in addition to the system ones.
+    pub ca_certificates: Vec<PathBuf>,
+    /// Skips certificate validation. Only meant for testing.
+    pub insecure_tls: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            connect_timeout: 30,
+            read_timeout: 60,
+            user_agent: None,
+            headers: BTreeMap::new(),
+            ca_certificates: Vec::new(),
+            insecure_tls: false,
+        }
+    }
+}
+
+/// A client builder with everything from `config` applied.
+pub fn client_builder(config: &HttpConfig) -> Result<ClientBuilder, Report<HttpClientError>> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(proxy) = &config.proxy {
+        let proxy = Proxy::all(proxy)
+            .change_context(HttpClientError)
+            .attach_printable_lazy(|| format!("`http.proxy`: invalid proxy URL {:?}", proxy))?;
+        builder = builder.proxy(proxy);
+    }
+    if config.connect_timeout > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(config.connect_timeout));
+    }
+    if config.read_timeout > 0 {
+        builder = builder.read_timeout(Duration::from_secs(config.read_timeout));
+    }
+    if let Some(user_agent) = &config.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &config.headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .change_context(HttpClientError)
+            .attach_printable_lazy(|| format!("`http.headers`: invalid header name {:?}", name))?;
+        let header_value = HeaderValue::from_str(value)
+            .change_context(HttpClientError)
+            .attach_printable_lazy(|| format!("`http.headers.{}`: invalid header value", name))?;
+        headers.insert(header_name, header_value);
+    }
+    builder = builder.default_headers(headers);
+
+    for path in &config.ca_certificates {
+        let pem = std::fs::read(path)
+            .change_context(HttpClientError)
+            .attach_printable_lazy(|| {
+                format!("`http.ca_certificates`: failed to read {}", path.display())
+            })?;
+        let certificate = Certificate::from_pem(&pem)
+            .change_context(HttpClientError)
+            .attach_printable_lazy(|| {
+                format!(
+                    "`http.ca_certificates`: {} is not a PEM certificate",
+                    path.display()
+                )
+            })?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    if config.insecure_tls {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder)
+}
@@ -0,0 +1,713 @@
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, Notify};
+use tokio_util::sync::CancellationToken;
+
+pub type JobId = u64;
+
+/// Attached to the errors of downloads that stopped because their job was
+/// cancelled, so they aren't retried or reported as failures.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("The download was cancelled")
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Waiting for a free download slot.
+    Queued,
+    Running,
+    /// Paused by the user, it doesn't hold a slot until it's resumed.
+    Paused,
+    Finished,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Finished => "finished",
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressUnit {
+    Bytes,
+    /// Of an HLS stream.
+    Segments,
+    /// Milliseconds of video ffmpeg went through.
+    Processing,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    /// Most episodes of one show downloading at the same time, 0 for no
+    /// limit, so a long batch doesn't take every slot.
+    pub max_per_show: usize,
+    /// Priority of the episodes of shows that are still airing, so they're
+    /// downloaded before a back-catalogue batch. Other episodes get 0.
+    pub airing_priority: i32,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_per_show: 0,
+            airing_priority: 10,
+        }
+    }
+}
+
+/// What a job is up to at one point, e.g. for the control API.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobInfo {
+    pub id: JobId,
+    /// The episode's path.
+    pub name: String,
+    pub group: String,
+    pub state: JobState,
+    pub priority: i32,
+    pub retries: usize,
+    pub unit: ProgressUnit,
+    pub position: u64,
+    pub length: Option<u64>,
+    /// Units per second.
+    pub speed: f64,
+}
+
+/// Hands out the download slots, the highest priority job waiting goes first
+/// and jobs of the same priority in the order they were queued.
+#[derive(Debug)]
+pub struct Scheduler {
+    state: Mutex<SchedulerState>,
+    changed: Notify,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct SchedulerState {
+    limit: usize,
+    /// Of one group, `None` for no limit.
+    group_limit: Option<usize>,
+    running: usize,
+    running_by_group: HashMap<String, usize>,
+    waiting: Vec<JobHandle>,
+}
+
+impl Scheduler {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(SchedulerState {
+                limit,
+                group_limit: None,
+                running: 0,
+                running_by_group: HashMap::new(),
+                waiting: Vec::new(),
+            }),
+            changed: Notify::new(),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Changes how many jobs run at once. Lowering it doesn't stop running
+    /// jobs, the next ones just wait until enough of them finished.
+    pub fn set_limit(&self, limit: usize) {
+        self.state.lock().unwrap().limit = limit.max(1);
+        self.changed.notify_waiters();
+    }
+
+    /// Limits how many jobs of the same group run at once, `None` lifts it.
+    pub fn set_group_limit(&self, group_limit: Option<usize>) {
+        self.state.lock().unwrap().group_limit = group_limit.map(|limit| limit.max(1));
+        self.changed.notify_waiters();
+    }
+
+    /// Creates the handle of a new job, cancelling `parent` cancels it too.
+    /// Jobs of the same `group` (e.g. a show) share the group limit.
+    /// `progress` is the bar the download reports on.
+    pub fn new_job(
+        self: &Arc<Self>,
+        name: &str,
+        group: &str,
+        priority: i32,
+        parent: &CancellationToken,
+        progress: ProgressBar,
+    ) -> JobHandle {
+        let (paused, _) = watch::channel(false);
+        JobHandle {
+            inner: Arc::new(Job {
+                id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                name: name.to_string(),
+                group: group.to_string(),
+                priority: AtomicI32::new(priority),
+                paused,
+                token: parent.child_token(),
+                state: Mutex::new(JobState::Queued),
+                slot: tokio::sync::Mutex::new(None),
+                scheduler: self.clone(),
+                progress,
+                unit: Mutex::new(ProgressUnit::Bytes),
+                retries: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Waits until `job` is the next in line and a slot is free. Returns
+    /// `None` if the job is cancelled first.
+    async fn acquire(self: &Arc<Self>, job: &JobHandle) -> Option<Slot> {
+        self.state.lock().unwrap().waiting.push(job.clone());
+        let _waiting = WaitingGuard {
+            scheduler: self,
+            id: job.id(),
+        };
+        loop {
+            let notified = self.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.running < state.limit && state.next_in_line() == Some(job.id()) {
+                    state.running += 1;
+                    *state
+                        .running_by_group
+                        .entry(job.group().to_string())
+                        .or_default() += 1;
+                    return Some(Slot {
+                        scheduler: self.clone(),
+                        group: job.group().to_string(),
+                    });
+                }
+            }
+            tokio::select! {
+                _ = notified => {}
+                _ = job.inner.token.cancelled() => return None,
+            }
+        }
+    }
+}
+
+/// Takes a job out of the queue however its wait ended.
+struct WaitingGuard<'a> {
+    scheduler: &'a Scheduler,
+    id: JobId,
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.scheduler
+            .state
+            .lock()
+            .unwrap()
+            .waiting
+            .retain(|waiting| waiting.id() != self.id);
+        // Whoever is next now may be able to go.
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+impl SchedulerState {
+    fn next_in_line(&self) -> Option<JobId> {
+        self.waiting
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| !job.is_paused() && self.group_has_room(job.group()))
+            // The earliest of the highest priority wins, `max_by_key` would
+            // return the last one.
+            .max_by_key(|(idx, job)| (job.priority(), std::cmp::Reverse(*idx)))
+            .map(|(_, job)| job.id())
+    }
+
+    fn group_has_room(&self, group: &str) -> bool {
+        let running = self.running_by_group.get(group).copied().unwrap_or(0);
+        self.group_limit.is_none_or(|limit| running < limit)
+    }
+}
+
+/// A download slot, given back when dropped.
+#[derive(Debug)]
+struct Slot {
+    scheduler: Arc<Scheduler>,
+    group: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        {
+            let mut state = self.scheduler.state.lock().unwrap();
+            state.running -= 1;
+            if let Some(running) = state.running_by_group.get_mut(&self.group) {
+                *running -= 1;
+                if *running == 0 {
+                    state.running_by_group.remove(&self.group);
+                }
+            }
+        }
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+/// Controls a queued download while it runs. Cloning it is cheap, every
+/// clone controls the same job.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    inner: Arc<Job>,
+}
+
+#[derive(Debug)]
+struct Job {
+    id: JobId,
+    name: String,
+    group: String,
+    priority: AtomicI32,
+    paused: watch::Sender<bool>,
+    token: CancellationToken,
+    state: Mutex<JobState>,
+    slot: tokio::sync::Mutex<Option<Slot>>,
+    scheduler: Arc<Scheduler>,
+    progress: ProgressBar,
+    unit: Mutex<ProgressUnit>,
+    retries: AtomicUsize,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> JobId {
+        self.inner.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    pub fn group(&self) -> &str {
+        &self.inner.group
+    }
+
+    pub fn info(&self) -> JobInfo {
+        let progress = &self.inner.progress;
+        JobInfo {
+            id: self.id(),
+            name: self.name().to_string(),
+            group: self.group().to_string(),
+            state: self.state(),
+            priority: self.priority(),
+            retries: self.retries(),
+            unit: self.unit(),
+            position: progress.position(),
+            length: progress.length(),
+            speed: progress.per_sec(),
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        match *self.inner.state.lock().unwrap() {
+            JobState::Finished => JobState::Finished,
+            _ if self.is_paused() => JobState::Paused,
+            state => state,
+        }
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.inner.priority.load(Ordering::Relaxed)
+    }
+
+    pub fn progress(&self) -> &ProgressBar {
+        &self.inner.progress
+    }
+
+    /// What the position and length of `progress` count.
+    pub fn unit(&self) -> ProgressUnit {
+        *self.inner.unit.lock().unwrap()
+    }
+
+    pub fn set_unit(&self, unit: ProgressUnit) {
+        *self.inner.unit.lock().unwrap() = unit;
+    }
+
+    /// How many attempts failed so far.
+    pub fn retries(&self) -> usize {
+        self.inner.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn retried(&self) {
+        self.inner.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.inner.paused.borrow()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.token.is_cancelled()
+    }
+
+    /// Stops the download at the next chunk or segment and frees its slot
+    /// for the others. A queued job stays in the queue but isn't started.
+    pub fn pause(&self) {
+        self.inner.paused.send_replace(true);
+        self.inner.scheduler.changed.notify_waiters();
+    }
+
+    pub fn resume(&self) {
+        self.inner.paused.send_replace(false);
+        self.inner.scheduler.changed.notify_waiters();
+    }
+
+    /// Stops the download for good, what was downloaded so far is kept and
+    /// picked up again the next time the episode is downloaded.
+    pub fn cancel(&self) {
+        self.inner.token.cancel();
+    }
+
+    /// Moves a queued job ahead of (or behind) the others, higher goes first.
+    pub fn set_priority(&self, priority: i32) {
+        self.inner.priority.store(priority, Ordering::Relaxed);
+        self.inner.scheduler.changed.notify_waiters();
+    }
+
+    pub async fn cancelled(&self) {
+        self.inner.token.cancelled().await
+    }
+
+    /// Resolves once the job is paused or cancelled, to stop waiting on a
+    /// stalled connection.
+    pub async fn interrupted(&self) {
+        let mut paused = self.inner.paused.subscribe();
+        tokio::select! {
+            _ = self.inner.token.cancelled() => {}
+            _ = paused.wait_for(|paused| *paused) => {}
+        }
+    }
+
+    /// Takes a download slot unless the job holds one already.
+    pub async fn start(&self) -> Result<(), Cancelled> {
+        let mut slot = self.inner.slot.lock().await;
+        if slot.is_none() {
+            *slot = Some(self.inner.scheduler.acquire(self).await.ok_or(Cancelled)?);
+        }
+        self.set_state(JobState::Running);
+        Ok(())
+    }
+
+    /// Called between chunks of work: returns right away while the job runs,
+    /// waits out a pause (without holding a slot) and fails once cancelled.
+    pub async fn checkpoint(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        if !self.is_paused() {
+            return Ok(());
+        }
+
+        self.inner.slot.lock().await.take();
+        let mut paused = self.inner.paused.subscribe();
+        tokio::select! {
+            _ = self.inner.token.cancelled() => return Err(Cancelled),
+            _ = paused.wait_for(|paused| !*paused) => {}
+        }
+        self.start().await
+    }
+
+    /// Gives back the slot while the job goes on with work that doesn't
+    /// download, e.g. ffmpeg, so the next download can start.
+    pub async fn release_slot(&self) {
+        self.inner.slot.lock().await.take();
+    }
+
+    /// Gives back the slot once the job is done, whichever way it ended.
+    pub async fn finish(&self) {
+        self.inner.slot.lock().await.take();
+        self.set_state(JobState::Finished);
+    }
+
+    fn set_state(&self, state: JobState) {
+        *self.inner.state.lock().unwrap() = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    fn job(scheduler: &Arc<Scheduler>, name: &str, group: &str, priority: i32) -> JobHandle {
+        let parent = CancellationToken::new();
+        scheduler.new_job(name, group, priority, &parent, ProgressBar::hidden())
+    }
+
+    /// Starts `job` in the background and reports its name once it got a slot.
+    fn spawn_start(job: &JobHandle, started: &mpsc::UnboundedSender<String>) {
+        let (job, started) = (job.clone(), started.clone());
+        tokio::spawn(async move {
+            if job.start().await.is_ok() {
+                let _ = started.send(job.name().to_string());
+            }
+        });
+    }
+
+    async fn wait_queued(scheduler: &Scheduler, count: usize) {
+        timeout(WAIT, async {
+            while scheduler.state.lock().unwrap().waiting.len() != count {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("jobs should be queued");
+    }
+
+    async fn next_started(started: &mut mpsc::UnboundedReceiver<String>) -> String {
+        timeout(WAIT, started.recv())
+            .await
+            .expect("a job should start")
+            .unwrap()
+    }
+
+    async fn assert_none_started(started: &mut mpsc::UnboundedReceiver<String>) {
+        tokio::task::yield_now().await;
+        assert!(started.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn starts_highest_priority_first() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let running = job(&scheduler, "running", "a", 0);
+        running.start().await.unwrap();
+
+        let low = job(&scheduler, "low", "a", 0);
+        let high = job(&scheduler, "high", "a", 5);
+        let also_high = job(&scheduler, "also_high", "a", 5);
+        for job in [&low, &high, &also_high] {
+            spawn_start(job, &tx);
+        }
+        wait_queued(&scheduler, 3).await;
+        assert_none_started(&mut started).await;
+        assert_eq!(low.state(), JobState::Queued);
+
+        running.finish().await;
+        assert_eq!(next_started(&mut started).await, "high");
+        assert_eq!(high.state(), JobState::Running);
+        high.finish().await;
+        assert_eq!(next_started(&mut started).await, "also_high");
+        also_high.finish().await;
+        assert_eq!(next_started(&mut started).await, "low");
+        assert_eq!(running.state(), JobState::Finished);
+    }
+
+    #[tokio::test]
+    async fn pause_frees_the_slot() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let paused = job(&scheduler, "paused", "a", 0);
+        paused.start().await.unwrap();
+        let next = job(&scheduler, "next", "a", 0);
+        spawn_start(&next, &tx);
+        wait_queued(&scheduler, 1).await;
+
+        paused.pause();
+        assert_eq!(paused.state(), JobState::Paused);
+        let checkpoint = tokio::spawn({
+            let paused = paused.clone();
+            async move { paused.checkpoint().await }
+        });
+        assert_eq!(next_started(&mut started).await, "next");
+
+        // Resumed, it queues for a slot again instead of taking one.
+        paused.resume();
+        wait_queued(&scheduler, 1).await;
+        assert!(!checkpoint.is_finished());
+        next.finish().await;
+        timeout(WAIT, checkpoint)
+            .await
+            .expect("the resumed job should get the slot")
+            .unwrap()
+            .unwrap();
+        assert_eq!(paused.state(), JobState::Running);
+    }
+
+    #[tokio::test]
+    async fn skips_paused_jobs_in_the_queue() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let running = job(&scheduler, "running", "a", 0);
+        running.start().await.unwrap();
+        let paused = job(&scheduler, "paused", "a", 5);
+        let other = job(&scheduler, "other", "a", 0);
+        paused.pause();
+        spawn_start(&paused, &tx);
+        spawn_start(&other, &tx);
+        wait_queued(&scheduler, 2).await;
+
+        running.finish().await;
+        assert_eq!(next_started(&mut started).await, "other");
+        other.finish().await;
+        assert_none_started(&mut started).await;
+        paused.resume();
+        assert_eq!(next_started(&mut started).await, "paused");
+    }
+
+    #[tokio::test]
+    async fn cancel_while_queued() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let running = job(&scheduler, "running", "a", 0);
+        running.start().await.unwrap();
+        let cancelled = job(&scheduler, "cancelled", "a", 5);
+        let waiting = tokio::spawn({
+            let cancelled = cancelled.clone();
+            async move { cancelled.start().await }
+        });
+        let next = job(&scheduler, "next", "a", 0);
+        spawn_start(&next, &tx);
+        wait_queued(&scheduler, 2).await;
+
+        cancelled.cancel();
+        assert!(timeout(WAIT, waiting).await.unwrap().unwrap().is_err());
+        wait_queued(&scheduler, 1).await;
+        assert!(cancelled.is_cancelled());
+        assert!(cancelled.checkpoint().await.is_err());
+
+        running.finish().await;
+        assert_eq!(next_started(&mut started).await, "next");
+    }
+
+    #[tokio::test]
+    async fn dropped_wait_leaves_the_queue() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let running = job(&scheduler, "running", "a", 0);
+        running.start().await.unwrap();
+        let dropped = job(&scheduler, "dropped", "a", 5);
+        let waiting = tokio::spawn({
+            let dropped = dropped.clone();
+            async move { dropped.start().await }
+        });
+        let next = job(&scheduler, "next", "a", 0);
+        spawn_start(&next, &tx);
+        wait_queued(&scheduler, 2).await;
+
+        waiting.abort();
+        wait_queued(&scheduler, 1).await;
+        assert!(!scheduler
+            .state
+            .lock()
+            .unwrap()
+            .waiting
+            .iter()
+            .any(|job| job.id() == dropped.id()));
+
+        running.finish().await;
+        assert_eq!(next_started(&mut started).await, "next");
+        assert_eq!(scheduler.state.lock().unwrap().running, 1);
+    }
+
+    #[tokio::test]
+    async fn release_slot_lets_the_next_job_start() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let processing = job(&scheduler, "processing", "a", 0);
+        processing.start().await.unwrap();
+        let next = job(&scheduler, "next", "a", 0);
+        spawn_start(&next, &tx);
+        wait_queued(&scheduler, 1).await;
+
+        processing.release_slot().await;
+        assert_eq!(next_started(&mut started).await, "next");
+        processing.finish().await;
+        assert_eq!(scheduler.state.lock().unwrap().running, 1);
+    }
+
+    #[tokio::test]
+    async fn group_limit_lets_other_shows_go_first() {
+        let scheduler = Scheduler::new(3);
+        scheduler.set_group_limit(Some(1));
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let first = job(&scheduler, "naruto-1", "naruto", 0);
+        first.start().await.unwrap();
+        let second = job(&scheduler, "naruto-2", "naruto", 0);
+        let other = job(&scheduler, "bleach-1", "bleach", 0);
+        spawn_start(&second, &tx);
+        spawn_start(&other, &tx);
+
+        assert_eq!(next_started(&mut started).await, "bleach-1");
+        assert_none_started(&mut started).await;
+        assert_eq!(second.state(), JobState::Queued);
+
+        first.finish().await;
+        assert_eq!(next_started(&mut started).await, "naruto-2");
+    }
+
+    #[tokio::test]
+    async fn lifting_the_group_limit_starts_waiting_jobs() {
+        let scheduler = Scheduler::new(2);
+        scheduler.set_group_limit(Some(1));
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let first = job(&scheduler, "naruto-1", "naruto", 0);
+        first.start().await.unwrap();
+        let second = job(&scheduler, "naruto-2", "naruto", 0);
+        spawn_start(&second, &tx);
+        wait_queued(&scheduler, 1).await;
+        assert_none_started(&mut started).await;
+
+        scheduler.set_group_limit(None);
+        assert_eq!(next_started(&mut started).await, "naruto-2");
+    }
+
+    #[tokio::test]
+    async fn airing_episodes_overtake_the_back_catalogue() {
+        let scheduler = Scheduler::new(1);
+        let airing_priority = QueueConfig::default().airing_priority;
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let running = job(&scheduler, "naruto-1", "naruto", 0);
+        running.start().await.unwrap();
+        let back_catalogue = job(&scheduler, "naruto-2", "naruto", 0);
+        spawn_start(&back_catalogue, &tx);
+        wait_queued(&scheduler, 1).await;
+        let airing = job(&scheduler, "one-piece-1100", "one-piece", airing_priority);
+        spawn_start(&airing, &tx);
+        wait_queued(&scheduler, 2).await;
+
+        running.finish().await;
+        assert_eq!(next_started(&mut started).await, "one-piece-1100");
+        airing.finish().await;
+        assert_eq!(next_started(&mut started).await, "naruto-2");
+    }
+
+    #[tokio::test]
+    async fn raised_priority_moves_a_job_ahead() {
+        let scheduler = Scheduler::new(1);
+        let (tx, mut started) = mpsc::unbounded_channel();
+        let running = job(&scheduler, "running", "a", 0);
+        running.start().await.unwrap();
+        let first = job(&scheduler, "first", "a", 0);
+        let second = job(&scheduler, "second", "a", 0);
+        spawn_start(&first, &tx);
+        spawn_start(&second, &tx);
+        wait_queued(&scheduler, 2).await;
+
+        second.set_priority(1);
+        running.finish().await;
+        assert_eq!(next_started(&mut started).await, "second");
+    }
+}
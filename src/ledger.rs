@@ -0,0 +1,438 @@
+use error_stack::{Context, Report, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::download_manager::partial_source_path;
+
+/// Files `library scan` looks at.
+const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "mkv", "m4v", "webm", "ts"];
+
+#[derive(Debug)]
+pub struct LedgerError;
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while accessing the download ledger")
+    }
+}
+
+impl Context for LedgerError {}
+
+/// An episode independent of the name it's saved under, e.g. `one-piece#1100`.
+/// The anime part is the show's slug on Gogo, which is also what its episode
+/// pages and the downloaded files are named after. The ledger keys it by the
+/// show's ID once it learned that, see [`Ledger::learn_anime_id`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EpisodeId {
+    pub anime: String,
+    pub episode: String,
+}
+
+impl EpisodeId {
+    /// "https://anitaku.bz/one-piece-episode-1100" or "one-piece-episode-1100"
+    pub fn parse(url_or_name: &str) -> Option<Self> {
+        let slug = url_or_name.trim_end_matches('/').rsplit('/').next()?;
+        let (anime, episode) = slug.rsplit_once("-episode-")?;
+        if anime.is_empty() || episode.is_empty() {
+            return None;
+        }
+        Some(Self {
+            anime: anime.to_string(),
+            episode: episode.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for EpisodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} episode {}", self.anime, self.episode)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// xxh3-128 of the file contents, used to recognize moved files.
+    pub hash: String,
+    pub recorded_at: u64,
+}
+
+impl LedgerEntry {
+    /// Whether the file is still where it was recorded, at the same size.
+    fn is_present(&self) -> bool {
+        std::fs::metadata(&self.path).is_ok_and(|metadata| metadata.len() == self.size)
+    }
+}
+
+/// What `library scan` found.
+#[derive(Debug, Default)]
+pub struct ScanSummary {
+    pub files: usize,
+    pub indexed: usize,
+    pub moved: usize,
+    pub unrecognized: Vec<PathBuf>,
+    /// Files holding an episode that's already recorded at another path.
+    pub duplicates: Vec<(PathBuf, PathBuf)>,
+    /// Files that couldn't be read.
+    pub failed: Vec<(PathBuf, Report<LedgerError>)>,
+}
+
+/// Every episode that was downloaded or found in the library, so it isn't
+/// downloaded again after being renamed or moved.
+#[derive(Debug)]
+pub struct Ledger {
+    path: PathBuf,
+    entries: Mutex<LedgerData>,
+}
+
+/// The contents of the ledger file.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct LedgerData {
+    /// Slug -> Gogo's ID of the show, several slugs may share one ID after
+    /// the site renamed a show.
+    #[serde(default)]
+    anime_ids: BTreeMap<String, String>,
+    /// By `anime#episode`, where anime is the ID if it's known, else the slug.
+    episodes: BTreeMap<String, LedgerEntry>,
+}
+
+impl LedgerData {
+    fn key(&self, episode: &EpisodeId) -> String {
+        let anime = self.anime_ids.get(&episode.anime).unwrap_or(&episode.anime);
+        format!("{}#{}", anime, episode.episode)
+    }
+}
+
+impl Ledger {
+    pub fn open(path: PathBuf) -> Result<Self, Report<LedgerError>> {
+        let entries = if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .change_context(LedgerError)
+                .attach_printable_lazy(|| format!("failed to read {}", path.display()))?;
+            serde_json::from_str(&contents)
+                // Ledgers from before the IDs were only keyed by slug.
+                .or_else(|_| {
+                    serde_json::from_str(&contents).map(|episodes| LedgerData {
+                        anime_ids: BTreeMap::new(),
+                        episodes,
+                    })
+                })
+                .change_context(LedgerError)
+                .attach_printable_lazy(|| format!("failed to parse {}", path.display()))?
+        } else {
+            LedgerData::default()
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("rusty-gogo-dl")
+            .join("ledger.json")
+    }
+
+    /// Where `episode` already is, if it was recorded and the file is still there.
+    pub fn find(&self, episode: &EpisodeId) -> Option<PathBuf> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .episodes
+            .get(&entries.key(episode))
+            .filter(|entry| entry.is_present())
+            .map(|entry| entry.path.clone())
+    }
+
+    /// Whether `episode` was downloaded before, even if the file was deleted
+    /// since.
+    pub fn contains(&self, episode: &EpisodeId) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.episodes.contains_key(&entries.key(episode))
+    }
+
+    /// Keys the episodes of the show with the slug `anime` by its ID from now
+    /// on, so they're still found after the site renames the show.
+    pub fn learn_anime_id(&self, anime: &str, anime_id: &str) -> Result<(), Report<LedgerError>> {
+        let mut entries = self.entries.lock().unwrap();
+        if anime_id.is_empty()
+            || entries
+                .anime_ids
+                .get(anime)
+                .is_some_and(|id| id == anime_id)
+        {
+            return Ok(());
+        }
+        let prefix = format!("{}#", anime);
+        let by_slug: Vec<String> = entries
+            .episodes
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for key in by_slug {
+            let entry = entries.episodes.remove(&key).unwrap();
+            let episode = &key[prefix.len()..];
+            entries
+                .episodes
+                .entry(format!("{}#{}", anime_id, episode))
+                .or_insert(entry);
+        }
+        entries
+            .anime_ids
+            .insert(anime.to_string(), anime_id.to_string());
+        self.persist(&entries)
+    }
+
+    /// Records `episode` as downloaded to `path`. Hashes the whole file, so
+    /// this blocks for a while on large files.
+    pub fn record(&self, episode: &EpisodeId, path: &Path) -> Result<(), Report<LedgerError>> {
+        let entry = describe_file(path)?;
+        let mut entries = self.entries.lock().unwrap();
+        let key = entries.key(episode);
+        entries.episodes.insert(key, entry);
+        self.persist(&entries)
+    }
+
+    /// Indexes the video files below `folder`. Files named like Gogo episodes
+    /// are recorded under their episode, others are matched to recorded
+    /// episodes by their contents so moved and renamed files are picked up.
+    pub fn scan(
+        &self,
+        folder: &Path,
+        mut on_file: impl FnMut(&Path),
+    ) -> Result<ScanSummary, Report<LedgerError>> {
+        let mut files = Vec::new();
+        collect_videos(folder, &mut files)
+            .change_context(LedgerError)
+            .attach_printable_lazy(|| format!("failed to list {}", folder.display()))?;
+        files.sort();
+
+        let mut summary = ScanSummary {
+            files: files.len(),
+            ..Default::default()
+        };
+        let mut entries = self.entries.lock().unwrap();
+        for file in files {
+            on_file(&file);
+            let file = std::path::absolute(&file).unwrap_or(file);
+            // Unchanged files don't need to be hashed again.
+            if entries
+                .episodes
+                .values()
+                .any(|entry| entry.path == file && entry.is_present())
+            {
+                summary.indexed += 1;
+                continue;
+            }
+            // One unreadable file doesn't spoil the rest of the library.
+            let entry = match describe_file(&file) {
+                Ok(entry) => entry,
+                Err(report) => {
+                    summary.failed.push((file, report));
+                    continue;
+                }
+            };
+            let episode = file
+                .file_stem()
+                .and_then(|stem| EpisodeId::parse(&stem.to_string_lossy()));
+            let known = match &episode {
+                Some(episode) => {
+                    let key = entries.key(episode);
+                    entries
+                        .episodes
+                        .get(&key)
+                        .map(|known| (key.clone(), known.clone()))
+                }
+                None => entries
+                    .episodes
+                    .iter()
+                    .find(|(_, known)| known.hash == entry.hash && known.size == entry.size)
+                    .map(|(key, known)| (key.clone(), known.clone())),
+            };
+            match known {
+                Some((_, known)) if known.path != file && known.is_present() => {
+                    summary.duplicates.push((file, known.path.clone()));
+                }
+                Some((key, known)) => {
+                    if known.path != file {
+                        summary.moved += 1;
+                    }
+                    summary.indexed += 1;
+                    entries.episodes.insert(key, entry);
+                }
+                None => match episode {
+                    Some(episode) => {
+                        summary.indexed += 1;
+                        let key = entries.key(&episode);
+                        entries.episodes.insert(key, entry);
+                    }
+                    None => summary.unrecognized.push(file),
+                },
+            }
+        }
+        self.persist(&entries)?;
+        Ok(summary)
+    }
+
+    fn persist(&self, entries: &LedgerData) -> Result<(), Report<LedgerError>> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).change_context(LedgerError)?;
+        }
+        let contents = serde_json::to_string_pretty(entries).change_context(LedgerError)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, contents).change_context(LedgerError)?;
+        std::fs::rename(&tmp_path, &self.path).change_context(LedgerError)?;
+        Ok(())
+    }
+}
+
+fn describe_file(path: &Path) -> Result<LedgerEntry, Report<LedgerError>> {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let (size, hash) = hash_file(&path)
+        .change_context(LedgerError)
+        .attach_printable_lazy(|| format!("failed to hash {}", path.display()))?;
+    Ok(LedgerEntry {
+        path,
+        size,
+        hash,
+        recorded_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    })
+}
+
+fn hash_file(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0; 1024 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+    Ok((size, format!("{:032x}", hasher.digest128())))
+}
+
+fn collect_videos(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            // The segments of an HLS download that didn't finish.
+            if path.extension().is_none_or(|ext| ext != "parts") {
+                collect_videos(&path, files)?;
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+            && !is_unfinished(&path)
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Videos that aren't episodes (yet): interrupted downloads, which keep their
+/// source or segments next to them, and the output and backup of
+/// post-processing.
+fn is_unfinished(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    partial_source_path(path).exists()
+        || path.with_extension("parts").is_dir()
+        || stem.ends_with(".part")
+        || stem.ends_with(".original")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(name: &str) -> EpisodeId {
+        EpisodeId::parse(name).unwrap()
+    }
+
+    fn write(path: &Path, contents: &str) -> PathBuf {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+        std::path::absolute(path).unwrap()
+    }
+
+    #[test]
+    fn scan_follows_moved_files_and_reports_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        let ledger = Ledger::open(dir.path().join("ledger.json")).unwrap();
+
+        let downloaded = write(&library.join("Naruto/naruto-episode-1.mp4"), "episode 1");
+        ledger
+            .record(&episode("naruto-episode-1"), &downloaded)
+            .unwrap();
+        let moved = write(&library.join("Watched/Naruto 01.mp4"), "episode 1");
+        std::fs::remove_file(&downloaded).unwrap();
+
+        let original = write(&library.join("Naruto/naruto-episode-2.mp4"), "episode 2");
+        ledger
+            .record(&episode("naruto-episode-2"), &original)
+            .unwrap();
+        let copy = write(&library.join("Backup/naruto-episode-2.mp4"), "episode 2");
+
+        let summary = ledger.scan(&library, |_| {}).unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(summary.moved, 1);
+        assert_eq!(summary.duplicates, vec![(copy, original.clone())]);
+        assert!(summary.unrecognized.is_empty());
+        assert_eq!(ledger.find(&episode("naruto-episode-1")), Some(moved));
+        assert_eq!(ledger.find(&episode("naruto-episode-2")), Some(original));
+    }
+
+    #[test]
+    fn scan_skips_unfinished_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        let ledger = Ledger::open(dir.path().join("ledger.json")).unwrap();
+
+        let partial = write(&library.join("Naruto/naruto-episode-3.mp4"), "half");
+        write(&partial_source_path(&partial), "example.com/naruto-3.mp4");
+        let stream = write(&library.join("Naruto/naruto-episode-4.ts"), "segments");
+        write(&stream.with_extension("parts").join("00001.ts"), "segment");
+        write(&library.join("Naruto/naruto-episode-5.part.mkv"), "muxing");
+        write(
+            &library.join("Naruto/naruto-episode-6.original.mp4"),
+            "backup",
+        );
+        let finished = write(&library.join("Naruto/naruto-episode-7.mp4"), "episode 7");
+
+        let summary = ledger.scan(&library, |_| {}).unwrap();
+        assert_eq!(summary.files, 1);
+        assert_eq!(summary.indexed, 1);
+        assert!(summary.unrecognized.is_empty());
+        assert_eq!(ledger.find(&episode("naruto-episode-7")), Some(finished));
+        for skipped in [
+            "naruto-episode-3",
+            "naruto-episode-4",
+            "naruto-episode-5.part",
+        ] {
+            assert!(
+                !ledger.contains(&episode(skipped)),
+                "{} was indexed",
+                skipped
+            );
+        }
+    }
+}
@@ -1,147 +1,684 @@
 mod anime_downloader;
+mod api;
+mod cache;
+mod cli;
+mod config;
+mod credentials;
+mod daemon;
+mod disk_space;
 mod download_manager;
+mod hls;
+mod hooks;
+mod http;
+mod jobs;
+mod ledger;
+mod post_process;
+mod shutdown;
+mod subtitles;
+mod tui;
 mod utils;
 
-use anime_downloader::gogo::{AnimeDetailedInfo, GogoAnime};
+use anime_downloader::gogo::{
+    Anime, AnimeDetailedInfo, GogoAnime, SearchFilters, SearchPage, SearchSort, Season,
+};
+use cache::{CacheTtls, MetadataCache};
+use clap::Parser;
+use cli::{CacheCommand, Cli, Command, ConfigCommand, LibraryCommand};
+use config::{
+    config_diff, default_config_path, fetch_remote_config, find_config_file, parse_and_load_config,
+    render_config_file, render_credentials_template, Config, InitValues, LoadedConfig,
+};
 use console::style;
-use download_manager::{ConcurrentDownloadManager, DownloadError};
-use error_stack::{Context, Report, ResultExt};
+use credentials::{default_credentials_path, load_credentials, write_private_file, Secret};
+use disk_space::PreflightMode;
+use download_manager::{ConcurrentDownloadManager, DownloadStatus, JobResults};
+use indicatif::{HumanBytes, ProgressBar};
 use inquire::{
     ui::{Attributes, Color, RenderConfig, StyleSheet, Styled},
     validator::Validation,
-    Confirm, CustomType, Select, Text,
+    Confirm, CustomType, InquireError, Password, Select, Text,
 };
-use std::collections::HashMap;
+use jobs::JobState;
+use ledger::Ledger;
+use shutdown::{InterruptedDownloads, Shutdown};
+use std::collections::HashSet;
+use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::io::IsTerminal;
 use std::path::Path;
 use std::sync::Arc;
-use std::{error::Error, path::PathBuf};
 
 use console::{Emoji, Term};
 
-const LINK_TO_CONFIG: &str =
-    "https://raw.githubusercontent.com/Zai-Kun/rusty-gogo-dl/master/config.json";
-
-#[derive(Debug)]
-struct ParseConfigError;
-
-impl fmt::Display for ParseConfigError {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.write_str("Could not parse configuration file")
-    }
+enum SearchChoice {
+    Anime(Anime),
+    LoadMore,
 }
 
-impl Context for ParseConfigError {}
-
-#[derive(Debug)]
-struct ConfigDownloadError;
-
-impl fmt::Display for ConfigDownloadError {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.write_str("Failed to download the config file")
+impl fmt::Display for SearchChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchChoice::Anime(anime) => write!(f, "{}", anime),
+            SearchChoice::LoadMore => write!(f, "{}", make_bold("Load more results...")),
+        }
     }
 }
 
-impl Context for ConfigDownloadError {}
-
-#[derive(serde::Deserialize, Debug)]
-struct Config {
-    gogo_base_url: String,
-    fetch_ep_list_api: String,
-    registered_account_emails: Vec<String>,
-    password: String,
-    preferred_res: String,
-    concurrent_downloads: usize,
-    download_folder: PathBuf,
-    retries: usize,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    if let Some(Command::Cache {
+        action: CacheCommand::Clear,
+    }) = &cli.command
+    {
+        MetadataCache::open(MetadataCache::default_path(), CacheTtls::default(), true).clear()?;
+        println!("{}", style("Cache cleared").green());
+        return Ok(());
+    }
+    let config_overrides = cli.config_overrides();
+    match &cli.command {
+        Some(Command::Config {
+            action: ConfigCommand::Init { force },
+        }) => {
+            // Not the config that is found, a --force would replace it with
+            // the template.
+            let path = config_overrides
+                .config_path
+                .clone()
+                .unwrap_or_else(default_config_path);
+            init_config(&path, *force)?;
+            let found = find_config_file(&config_overrides).filter(|found| *found != path);
+            if let Some(found) = found {
+                print_err(&format!(
+                    "{} is used before it, remove it to use the new config",
+                    found.display()
+                ));
+            }
+            return Ok(());
+        }
+        Some(Command::Config {
+            action: ConfigCommand::Fetch { yes },
+        }) => {
+            let path = find_config_file(&config_overrides).unwrap_or_else(default_config_path);
+            return fetch_config(&path, *yes).await;
+        }
+        _ => {}
+    }
+    let loaded_config = parse_and_load_config(&config_overrides)?;
+    if let Some(Command::Config {
+        action: ConfigCommand::Show,
+    }) = &cli.command
+    {
+        print_config(&loaded_config);
+        return Ok(());
+    }
+    if let Some(Command::Library {
+        action: LibraryCommand::Scan { folder },
+    }) = &cli.command
+    {
+        let folder = folder
+            .as_ref()
+            .unwrap_or(&loaded_config.config.download_folder);
+        return scan_library(folder);
+    }
+    if loaded_config.is_from_file("password") {
+        print_err("Storing the password in the config file is deprecated, move it to credentials.toml or use password_cmd");
+    }
+    loaded_config.check_environment()?;
+    let config = loaded_config.config;
+    let shutdown = Shutdown::listen();
+    let accounts = load_credentials(&config)?;
+    let search_filters = cli.search_filters();
     clear_screen();
     inquire::set_global_render_config(get_render_config());
-    let config = parse_and_load_config().await?;
-    let gogo_anime = Arc::new(GogoAnime::new(
-        &config.gogo_base_url,
-        &config.fetch_ep_list_api,
-        &config.password,
-        config.registered_account_emails,
-    ));
+    let cache = MetadataCache::open(
+        MetadataCache::default_path(),
+        config.cache_ttl.clone(),
+        cli.refresh,
+    );
+    let gogo_anime = Arc::new(
+        GogoAnime::new(
+            &config.gogo_base_url,
+            &config.fetch_ep_list_api,
+            accounts,
+            http::client_builder(&config.http)?,
+        )
+        .with_mirrors(&config.gogo_mirrors)
+        .with_cache(cache)
+        .with_rate_limit(&config.rate_limit),
+    );
     gogo_anime.init().await?;
-    loop {
-        let query = Text::new(&make_bold("Search an anime:"))
-            .with_help_message("Press enter to exit")
-            .prompt()?;
-        if query.is_empty() {
-            break;
+    let ledger = Arc::new(Ledger::open(Ledger::default_path())?);
+    let interrupted = InterruptedDownloads::new(InterruptedDownloads::default_path());
+    // One manager for the whole session, so episodes of different shows share
+    // the download slots.
+    let mut download_manager = new_download_manager(&config, &ledger)?;
+    if let Some(Command::Daemon) = &cli.command {
+        return daemon::run(
+            &config_overrides,
+            config,
+            gogo_anime,
+            ledger,
+            download_manager,
+            &shutdown,
+            &interrupted,
+        )
+        .await;
+    }
+    if let Some(Command::Tui) = &cli.command {
+        return tui::run(
+            &config,
+            gogo_anime,
+            download_manager,
+            search_filters,
+            &shutdown,
+            &interrupted,
+        )
+        .await;
+    }
+    // The bars would garble the prompts, they're shown by `:status` and while
+    // waiting for the downloads at the end.
+    download_manager.set_progress_visible(false);
+    resume_interrupted(&config, &gogo_anime, &mut download_manager, &interrupted)?;
+    let mut session_error = None;
+    while !shutdown.is_requested() {
+        // Until something is queued a signal just quits.
+        if !download_manager.jobs().is_empty() {
+            shutdown.downloads_started(download_manager.cancellation_token());
         }
-        let results = gogo_anime.search_anime(&query).await?;
-        if results.len() == 0 {
-            print_err("No results found, please try again");
-            continue;
+        let listing = match &cli.command {
+            None => match prompt_search(&download_manager, &shutdown).await {
+                Ok(Some(query)) => Listing::Search(query, search_filters.clone()),
+                Ok(None) => break,
+                Err(InquireError::OperationInterrupted) => {
+                    download_manager.cancel_all();
+                    break;
+                }
+                Err(err) => {
+                    session_error = Some(err.into());
+                    break;
+                }
+            },
+            Some(Command::Recent) => Listing::Recent,
+            Some(Command::Popular) => Listing::Popular,
+            Some(Command::Ongoing) => Listing::Ongoing,
+            Some(Command::Genre { genre }) => Listing::Genre(genre.clone()),
+            Some(Command::Season { season, year }) => Listing::Season(*season, *year),
+            Some(Command::Cache { .. } | Command::Config { .. } | Command::Library { .. }) => {
+                unreachable!("handled before logging in")
+            }
+            Some(Command::Tui | Command::Daemon) => unreachable!("handled before the loop"),
+        };
+        match queue_from_listing(&config, &gogo_anime, &mut download_manager, &listing).await {
+            Ok(_) if matches!(listing, Listing::Search(..)) => {}
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) if is_interrupted(err.as_ref()) => {
+                download_manager.cancel_all();
+                break;
+            }
+            // The queued downloads keep going, only this search failed.
+            Err(err) if matches!(listing, Listing::Search(..)) => print_err(&format!("{:?}", err)),
+            Err(err) => {
+                session_error = Some(err);
+                break;
+            }
         }
+    }
+    finish_downloads(&mut download_manager, &shutdown, &interrupted).await;
+    session_error.map_or(Ok(()), Err)
+}
 
-        let selected_anime = Select::new("Select an anime:", results)
-            .with_page_size(10)
-            .prompt()?;
-        let detailed_anime_info = gogo_anime
-            .fetch_detailed_anime_info(&selected_anime.url)
-            .await?;
-        clear_screen();
-        print_details(&detailed_anime_info);
+/// Asks for the next search, `None` once the user is done or a signal asked
+/// to shut down. `:status` shows the downloads of the session in between,
+/// `:concurrency N` changes how many of them run at once.
+async fn prompt_search(
+    download_manager: &ConcurrentDownloadManager,
+    shutdown: &Shutdown,
+) -> Result<Option<String>, InquireError> {
+    loop {
+        let help = if download_manager.jobs().is_empty() {
+            "Press enter to exit"
+        } else {
+            "Enter :status to see the downloads or :concurrency N to change how many run at once, nothing to wait for them and exit"
+        };
+        let query = prompt_until_shutdown(shutdown, move || {
+            Text::new(&make_bold("Search an anime:"))
+                .with_help_message(help)
+                .prompt()
+        })
+        .await;
+        let Some(query) = query else {
+            return Ok(None);
+        };
+        let query = query?;
+        let command = query.trim();
+        if command.is_empty() {
+            return Ok(None);
+        } else if command == ":status" {
+            show_status(download_manager);
+        } else if let Some(n) = command.strip_prefix(":concurrency") {
+            match n.trim().parse::<usize>() {
+                Ok(n) if n > 0 => {
+                    download_manager.set_concurrency(n);
+                    println!(
+                        "{}",
+                        style(format!("Downloading {} episode(s) at once", n)).green()
+                    );
+                }
+                _ => print_err("Expected :concurrency followed by a number above 0"),
+            }
+        } else {
+            return Ok(Some(query));
+        }
+    }
+}
 
-        let ans = Confirm::new(&make_bold(
+/// Lets the user pick an anime from `listing` and queues the episodes they
+/// choose. Returns `false` if nothing was picked.
+async fn queue_from_listing(
+    config: &Config,
+    gogo_anime: &Arc<GogoAnime>,
+    download_manager: &mut ConcurrentDownloadManager,
+    listing: &Listing,
+) -> Result<bool, Box<dyn Error>> {
+    let Some(selected_anime) = select_anime(gogo_anime, listing).await? else {
+        return Ok(false);
+    };
+    let selected_anime = match config.preferred_language {
+        Some(language) if selected_anime.language != language => gogo_anime
+            .find_variant(&selected_anime, language)
+            .await?
+            .unwrap_or(selected_anime),
+        _ => selected_anime,
+    };
+    let detailed_anime_info = gogo_anime
+        .fetch_detailed_anime_info(&selected_anime.url)
+        .await?;
+    clear_screen();
+    print_details(&detailed_anime_info);
+
+    let ans = prompt(|| {
+        Confirm::new(&make_bold(
             "Do you want to download episodes from this anime?",
         ))
         .with_default(true)
         .prompt()
-        .unwrap();
-        if !ans {
-            continue;
+    })?;
+    if !ans {
+        return Ok(true);
+    }
+
+    let (start, end) = prompt(|| get_ep_start_and_ep_end(&detailed_anime_info))?;
+    let episodes: Vec<(String, String)> = detailed_anime_info.episode_links[start - 1..end]
+        .iter()
+        .map(|link| {
+            let ep_path = utils::episode_path(&config.download_folder, &detailed_anime_info, link);
+            (ep_path, link.clone())
+        })
+        .collect();
+
+    if !check_free_space(config, download_manager, gogo_anime, &episodes).await {
+        return Ok(true);
+    }
+
+    let mut queued = 0;
+    for (ep_path, link) in &episodes {
+        let job = download_manager.add_gogo_download(
+            gogo_anime.clone(),
+            &detailed_anime_info,
+            &config.preferred_res,
+            ep_path,
+            link,
+        );
+        queued += usize::from(job.is_some());
+    }
+    println!(
+        "{}",
+        style(format!(
+            "Queued {} episode(s) of {}",
+            queued, detailed_anime_info.name
+        ))
+        .green()
+    );
+    Ok(true)
+}
+
+/// Counts the jobs of the session and shows their progress bars until enter
+/// is pressed.
+fn show_status(download_manager: &ConcurrentDownloadManager) {
+    let jobs = download_manager.jobs();
+    let count = |state| jobs.iter().filter(|job| job.state() == state).count();
+    println!(
+        "{}",
+        make_bold(&format!(
+            "{} running, {} queued, {} paused, {} finished (press enter to go back)",
+            count(JobState::Running),
+            count(JobState::Queued),
+            count(JobState::Paused),
+            count(JobState::Finished)
+        ))
+    );
+    download_manager.set_progress_visible(true);
+    let _ = prompt(|| std::io::stdin().read_line(&mut String::new()));
+    download_manager.set_progress_visible(false);
+}
+
+/// Runs a blocking prompt without holding up the downloads scheduled on the
+/// same worker thread.
+fn prompt<T>(prompt: impl FnOnce() -> T) -> T {
+    tokio::task::block_in_place(prompt)
+}
+
+/// Runs `prompt` on a thread of its own, so a SIGTERM doesn't wait for
+/// someone to answer it. `None` if a shutdown was requested first, the
+/// prompt is then left to end with the process.
+async fn prompt_until_shutdown<T: Send + 'static>(
+    shutdown: &Shutdown,
+    prompt: impl FnOnce() -> T + Send + 'static,
+) -> Option<T> {
+    let (answer, answered) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = answer.send(prompt());
+    });
+    tokio::select! {
+        answer = answered => answer.ok(),
+        _ = shutdown.requested() => {
+            shutdown::restore_terminal();
+            println!();
+            None
         }
+    }
+}
 
-        let (start, end) = get_ep_start_and_ep_end(&detailed_anime_info);
-        let eps_to_download = &detailed_anime_info.episode_links[start - 1..end];
-
-        let mut download_manager =
-            ConcurrentDownloadManager::new(config.concurrent_downloads, config.retries);
-
-        for (idx, link) in eps_to_download.iter().enumerate() {
-            let ep_path = utils::combine_path(
-                &config.download_folder,
-                &detailed_anime_info.name,
-                &eps_to_download[idx],
-            ) + ".mp4";
-            download_manager.add_gogo_download(
-                gogo_anime.clone(),
-                &config.preferred_res,
-                &ep_path,
-                link,
-            );
+/// Whether the user pressed Ctrl-C in a prompt, which reads it as a key
+/// instead of letting it become a signal.
+fn is_interrupted(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<InquireError>(),
+        Some(InquireError::OperationInterrupted)
+    )
+}
+
+fn new_download_manager(
+    config: &Config,
+    ledger: &Arc<Ledger>,
+) -> Result<ConcurrentDownloadManager, Box<dyn Error>> {
+    Ok(ConcurrentDownloadManager::new(
+        config.concurrent_downloads,
+        config.retries,
+        http::client_builder(&config.http)?,
+    )
+    .with_queue(&config.queue)
+    .with_disk_space(&config.disk_space)
+    .with_ledger(ledger.clone())
+    .with_hooks(&config.hooks)
+    .with_post_process(&config.post_process)
+    .with_subtitles(&config.subtitles))
+}
+
+/// Waits for the queued downloads and prints their summary. Episodes a
+/// shutdown cancelled are saved to be offered again on the next start.
+async fn finish_downloads(
+    download_manager: &mut ConcurrentDownloadManager,
+    shutdown: &Shutdown,
+    interrupted: &InterruptedDownloads,
+) {
+    let unfinished = download_manager.unfinished_episodes().len();
+    if unfinished > 0 {
+        println!(
+            "{}",
+            make_bold(&format!(
+                "Waiting for {} episode(s), press Ctrl-C to stop them",
+                unfinished
+            ))
+        );
+    }
+    shutdown.downloads_started(download_manager.cancellation_token());
+    download_manager.set_progress_visible(true);
+    let results = download_manager.await_results().await;
+    shutdown.downloads_finished();
+    if results.is_empty() {
+        return;
+    }
+    let cancelled = download_manager.cancelled_episodes(&results);
+    print_stats(results);
+    if !cancelled.is_empty() {
+        match interrupted.save(&cancelled) {
+            Ok(()) => println!(
+                "{}",
+                style(format!(
+                    "{} interrupted episode(s) will be offered again next time",
+                    cancelled.len()
+                ))
+                .yellow()
+            ),
+            Err(report) => print_err(&utils::report_summary(&report)),
         }
+    }
+}
 
-        print_stats(download_manager.await_results().await);
+/// Offers to queue the episodes the last run was interrupted in.
+fn resume_interrupted(
+    config: &Config,
+    gogo_anime: &Arc<GogoAnime>,
+    download_manager: &mut ConcurrentDownloadManager,
+    interrupted: &InterruptedDownloads,
+) -> Result<(), Box<dyn Error>> {
+    let episodes = match interrupted.load() {
+        Ok(episodes) => episodes,
+        Err(report) => {
+            print_err(&utils::report_summary(&report));
+            return Ok(());
+        }
+    };
+    if episodes.is_empty() {
+        return Ok(());
+    }
+    let resume = prompt(|| {
+        Confirm::new(&make_bold(&format!(
+            "{} episode(s) were interrupted last time, download them now?",
+            episodes.len()
+        )))
+        .with_default(true)
+        .prompt()
+    })?;
+    // Whatever is cancelled again is saved again.
+    interrupted.clear()?;
+    if !resume {
+        return Ok(());
+    }
+
+    for episode in episodes {
+        download_manager.add_episode(gogo_anime.clone(), &config.preferred_res, episode);
     }
     Ok(())
 }
 
-fn print_stats(results: HashMap<String, Result<(), Report<DownloadError>>>) {
+/// Compares the size of `episodes` with the free space in the download folder.
+/// Returns whether the downloads should go ahead.
+async fn check_free_space(
+    config: &Config,
+    download_manager: &ConcurrentDownloadManager,
+    gogo_anime: &Arc<GogoAnime>,
+    episodes: &[(String, String)],
+) -> bool {
+    if config.disk_space.preflight == PreflightMode::Off {
+        return true;
+    }
+    let available = match disk_space::available_space(&config.download_folder) {
+        Ok(available) => available,
+        Err(err) => {
+            print_err(&format!("Could not check the free disk space: {}", err));
+            return true;
+        }
+    };
+
+    println!("{}", make_bold("Checking the size of the episodes..."));
+    let estimate = download_manager
+        .estimate_size(gogo_anime, &config.preferred_res, episodes)
+        .await;
+    if estimate.unknown > 0 {
+        print_err(&format!(
+            "The size of {} episode(s) could not be determined",
+            estimate.unknown
+        ));
+    }
+
+    let needed = estimate
+        .remaining_bytes
+        .saturating_add(config.disk_space.reserve_bytes());
+    if needed <= available {
+        return true;
+    }
+    print_err(&format!(
+        "The episodes need {} (plus {} kept free), but only {} is available in {}",
+        HumanBytes(estimate.remaining_bytes),
+        HumanBytes(config.disk_space.reserve_bytes()),
+        HumanBytes(available),
+        config.download_folder.display()
+    ));
+    match config.disk_space.preflight {
+        PreflightMode::Abort => false,
+        _ => prompt(|| {
+            Confirm::new(&make_bold("Download anyway?"))
+                .with_default(false)
+                .prompt()
+                .unwrap_or(false)
+        }),
+    }
+}
+
+/// Where the anime to pick from come from.
+enum Listing {
+    Search(String, SearchFilters),
+    Recent,
+    Popular,
+    Ongoing,
+    Genre(String),
+    Season(Season, u32),
+}
+
+impl Listing {
+    async fn fetch_page(
+        &self,
+        gogo_anime: &GogoAnime,
+        page: usize,
+    ) -> Result<SearchPage, Box<dyn Error>> {
+        let search_page = match self {
+            Listing::Search(query, filters) => {
+                gogo_anime.search_anime_page(query, filters, page).await?
+            }
+            Listing::Recent => gogo_anime.recent_releases(page).await?,
+            Listing::Popular => gogo_anime.popular(page).await?,
+            Listing::Ongoing => gogo_anime.ongoing(page).await?,
+            Listing::Genre(genre) => gogo_anime.genre(genre, page).await?,
+            Listing::Season(season, year) => gogo_anime.season(*season, *year, page).await?,
+        };
+        Ok(search_page)
+    }
+
+    fn sort(&self) -> SearchSort {
+        match self {
+            Listing::Search(_, filters) => filters.sort,
+            _ => SearchSort::Relevance,
+        }
+    }
+}
+
+/// Lets the user pick an anime from the listing, loading more pages on demand.
+/// Returns `None` if the listing is empty or the user backed out with escape.
+async fn select_anime(
+    gogo_anime: &GogoAnime,
+    listing: &Listing,
+) -> Result<Option<Anime>, Box<dyn Error>> {
+    let mut search_page = listing.fetch_page(gogo_anime, 1).await?;
+    let mut results = Vec::new();
+    loop {
+        let loaded: HashSet<String> = search_page
+            .results
+            .iter()
+            .map(|anime| anime.url.clone())
+            .collect();
+        results.append(&mut search_page.results);
+        listing.sort().apply(&mut results);
+        // The cursor starts at the first of the results just loaded.
+        let starting_cursor = results
+            .iter()
+            .position(|anime| loaded.contains(&anime.url))
+            .unwrap_or(0);
+        if results.is_empty() {
+            print_err("No results found, please try again");
+            return Ok(None);
+        }
+
+        let mut choices: Vec<SearchChoice> =
+            results.iter().cloned().map(SearchChoice::Anime).collect();
+        if search_page.has_next_page {
+            choices.push(SearchChoice::LoadMore);
+        }
+
+        let choice = prompt(|| {
+            Select::new("Select an anime:", choices)
+                .with_page_size(10)
+                .with_starting_cursor(starting_cursor.min(results.len() - 1))
+                .prompt_skippable()
+        })?;
+        match choice {
+            Some(SearchChoice::Anime(anime)) => return Ok(Some(anime)),
+            Some(SearchChoice::LoadMore) => {
+                search_page = listing.fetch_page(gogo_anime, search_page.page + 1).await?;
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+fn print_stats(results: JobResults) {
     clear_screen();
 
     let mut success_count = 0;
+    let mut skipped_count = 0;
     let mut failure_count = 0;
+    let mut cancelled_count = 0;
 
-    for (path, result) in results {
+    for (path, result) in results.into_values() {
         match result {
-            Ok(_) => {
+            Ok(DownloadStatus::Downloaded {
+                path: final_path,
+                source,
+            }) => {
                 success_count += 1;
+                let path = if final_path == Path::new(&path) {
+                    path
+                } else {
+                    final_path.display().to_string()
+                };
                 println!(
                     "{} {}",
                     Emoji("✅", "✔️"),
-                    style(format!("{} - Downloaded successfully", path)).green()
+                    style(format!("{} - Downloaded successfully ({})", path, source)).green()
+                );
+            }
+            Ok(DownloadStatus::Skipped(existing)) => {
+                skipped_count += 1;
+                println!(
+                    "{} {}",
+                    Emoji("⏭️ ", "-"),
+                    style(format!(
+                        "{} - Skipped, already at {}",
+                        path,
+                        existing.display()
+                    ))
+                    .yellow()
+                );
+            }
+            Ok(DownloadStatus::Cancelled(_)) => {
+                cancelled_count += 1;
+                println!(
+                    "{} {}",
+                    Emoji("⏹️ ", "-"),
+                    style(format!("{} - Cancelled, resumes from here next time", path)).yellow()
                 );
             }
             Err(report) => {
@@ -161,15 +698,222 @@ fn print_stats(results: HashMap<String, Result<(), Report<DownloadError>>>) {
         Emoji("📂", ""),
         style(format!("Successful downloads: {}", success_count)).green()
     );
+    println!(
+        "{} {}",
+        Emoji("📂", ""),
+        style(format!("Skipped downloads: {}", skipped_count)).yellow()
+    );
     println!(
         "{} {}",
         Emoji("📂", ""),
         style(format!("Failed downloads: {}", failure_count)).red()
     );
+    if cancelled_count > 0 {
+        println!(
+            "{} {}",
+            Emoji("📂", ""),
+            style(format!("Cancelled downloads: {}", cancelled_count)).yellow()
+        );
+    }
+}
+
+fn scan_library(folder: &Path) -> Result<(), Box<dyn Error>> {
+    let ledger = Ledger::open(Ledger::default_path())?;
+    let pb = ProgressBar::new_spinner();
+    let summary = ledger.scan(folder, |file| {
+        pb.set_message(format!("Indexing {}", file.display()));
+        pb.tick();
+    })?;
+    pb.finish_and_clear();
+
+    for file in &summary.unrecognized {
+        print_err(&format!("Not a known episode: {}", file.display()));
+    }
+    for (file, original) in &summary.duplicates {
+        print_err(&format!(
+            "Duplicate of {}: {}",
+            original.display(),
+            file.display()
+        ));
+    }
+    for (file, report) in &summary.failed {
+        print_err(&format!(
+            "Couldn't index {}: {}",
+            file.display(),
+            utils::report_summary(report)
+        ));
+    }
+    println!(
+        "{}",
+        style(format!(
+            "Scanned {} files: {} indexed ({} moved), {} unrecognized, {} duplicates, {} failed",
+            summary.files,
+            summary.indexed,
+            summary.moved,
+            summary.unrecognized.len(),
+            summary.duplicates.len(),
+            summary.failed.len()
+        ))
+        .green()
+    );
+    Ok(())
+}
+
+fn init_config(path: &Path, force: bool) -> Result<(), Box<dyn Error>> {
+    let interactive = std::io::stdin().is_terminal();
+    if path.exists() && !force {
+        let overwrite = interactive
+            && Confirm::new(&make_bold(&format!(
+                "{} already exists, overwrite it?",
+                path.display()
+            )))
+            .with_default(false)
+            .prompt()?;
+        if !overwrite {
+            print_err(&format!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ));
+            return Ok(());
+        }
+    }
+
+    let mut values = if interactive {
+        prompt_init_values()?
+    } else {
+        InitValues::default()
+    };
+    // Credentials next to a config anywhere else are only found through the
+    // config, e.g. next to ./config.json or a --config path.
+    let credentials_path = std::path::absolute(path)?.with_file_name("credentials.toml");
+    if credentials_path != default_credentials_path() {
+        values.credentials_file = Some(credentials_path.clone());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, render_config_file(&values, path))?;
+    println!(
+        "{}",
+        style(format!("Config written to {}", path.display())).green()
+    );
+
+    if credentials_path.exists() && !force {
+        println!("Keeping the existing {}", credentials_path.display());
+    } else {
+        write_private_file(&credentials_path, &render_credentials_template(&values))?;
+        println!(
+            "{}",
+            style(format!(
+                "Credentials written to {}",
+                credentials_path.display()
+            ))
+            .green()
+        );
+    }
+    Ok(())
+}
+
+fn prompt_init_values() -> Result<InitValues, Box<dyn Error>> {
+    let emails = Text::new(&make_bold("Registered account emails:"))
+        .with_help_message("Separate multiple emails with commas")
+        .prompt()?;
+    let password = Password::new(&make_bold("Account password:"))
+        .without_confirmation()
+        .prompt()?;
+    let download_folder = Text::new(&make_bold("Download folder:"))
+        .with_default("downloaded_anime")
+        .prompt()?;
+    let preferred_res = Text::new(&make_bold("Preferred resolution:"))
+        .with_default("1280x720")
+        .with_validator(|res: &str| {
+            if utils::parse_resolution(res).is_some() {
+                Ok(Validation::Valid)
+            } else {
+                Ok(Validation::Invalid(
+                    "Expected WIDTHxHEIGHT, e.g. 1920x1080".into(),
+                ))
+            }
+        })
+        .prompt()?;
+    let concurrent_downloads: usize = CustomType::new(&make_bold("Concurrent downloads:"))
+        .with_default(2)
+        .with_validator(|n: &usize| {
+            if *n > 0 {
+                Ok(Validation::Valid)
+            } else {
+                Ok(Validation::Invalid(
+                    "At least one download is needed.".into(),
+                ))
+            }
+        })
+        .prompt()?;
+
+    Ok(InitValues {
+        registered_account_emails: emails
+            .split(',')
+            .map(str::trim)
+            .filter(|email| !email.is_empty())
+            .map(str::to_string)
+            .collect(),
+        password: Secret::new(password),
+        download_folder: Some(download_folder.into()),
+        preferred_res: Some(preferred_res),
+        concurrent_downloads: Some(concurrent_downloads),
+        credentials_file: None,
+    })
+}
+
+async fn fetch_config(path: &Path, yes: bool) -> Result<(), Box<dyn Error>> {
+    let remote = fetch_remote_config(path).await?;
+    let current = std::fs::read_to_string(path).unwrap_or_default();
+    if current == remote {
+        println!("{} is already up to date", path.display());
+        return Ok(());
+    }
+
+    for line in config_diff(&current, &remote).lines() {
+        if line.starts_with('+') && !line.starts_with("+++") {
+            println!("{}", style(line).green());
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            println!("{}", style(line).red());
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    let overwrite = yes
+        || Confirm::new(&make_bold(&format!(
+            "Overwrite {} with the remote config?",
+            path.display()
+        )))
+        .with_default(false)
+        .prompt()?;
+    if !overwrite {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, remote)?;
+    println!("{}", style(format!("Updated {}", path.display())).green());
+    Ok(())
+}
+
+fn print_config(loaded_config: &LoadedConfig) {
+    for (key, value, source) in loaded_config.entries() {
+        println!(
+            "{} = {} {}",
+            make_bold(&key),
+            value,
+            style(format!("({})", source)).dim()
+        );
+    }
 }
 
 fn print_details(anime: &AnimeDetailedInfo) {
     println!("{}{}", make_bold("Name: "), anime.name);
+    println!("{}{}", make_bold("Language: "), anime.language);
     println!("{}{}", make_bold("Thumbnail: "), anime.thumbnail);
     println!("{}", make_bold("About:"));
     for (key, value) in &anime.about {
@@ -182,7 +926,7 @@ fn print_details(anime: &AnimeDetailedInfo) {
     );
 }
 
-fn get_ep_start_and_ep_end(anime: &AnimeDetailedInfo) -> (usize, usize) {
+fn get_ep_start_and_ep_end(anime: &AnimeDetailedInfo) -> Result<(usize, usize), InquireError> {
     let eps_len = anime.episode_links.len();
     let ep_start: usize = CustomType::new(&make_bold("Starting episode index:"))
         .with_default(1)
@@ -200,8 +944,7 @@ fn get_ep_start_and_ep_end(anime: &AnimeDetailedInfo) -> (usize, usize) {
                 ))
             }
         })
-        .prompt()
-        .unwrap();
+        .prompt()?;
 
     let ep_end: usize = CustomType::new(&make_bold("Ending episode index:"))
         .with_default(eps_len)
@@ -223,55 +966,8 @@ fn get_ep_start_and_ep_end(anime: &AnimeDetailedInfo) -> (usize, usize) {
                 ))
             }
         })
-        .prompt()
-        .unwrap();
-    (ep_start, ep_end)
-}
-
-async fn download_and_save_config() -> Result<(), Report<ConfigDownloadError>> {
-    let response = reqwest::get(LINK_TO_CONFIG)
-        .await
-        .change_context(ConfigDownloadError)?;
-
-    let contents = response.text().await.change_context(ConfigDownloadError)?;
-
-    let mut file = File::create("config.json").change_context(ConfigDownloadError)?;
-
-    file.write_all(contents.as_bytes())
-        .change_context(ConfigDownloadError)?;
-
-    Ok(())
-}
-
-async fn parse_and_load_config() -> Result<Config, Report<ParseConfigError>> {
-    let config_path = Path::new("config.json");
-    if !config_path.exists() {
-        download_and_save_config()
-            .await
-            .change_context(ParseConfigError)?;
-    }
-    let mut config_file = File::open(config_path)
-        .change_context(ParseConfigError)
-        .attach_printable_lazy(|| {
-            format!("failed to open the config file {}", config_path.display())
-        })?;
-
-    let mut contents = String::new();
-    config_file
-        .read_to_string(&mut contents)
-        .change_context(ParseConfigError)
-        .attach_printable(format!(
-            "failed to read the config file {}",
-            config_path.display()
-        ))?;
-    let config: Config = serde_json::from_str(&contents)
-        .change_context(ParseConfigError)
-        .attach_printable(format!(
-            "failed to parse the config file as json {}",
-            config_path.display()
-        ))?;
-
-    Ok(config)
+        .prompt()?;
+    Ok((ep_start, ep_end))
 }
 
 fn clear_screen() {
@@ -0,0 +1,355 @@
+use error_stack::{Context, Report, ResultExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::subtitles::SavedSubtitle;
+
+#[derive(Debug)]
+pub struct PostProcessError;
+
+impl fmt::Display for PostProcessError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while post-processing the episode with ffmpeg")
+    }
+}
+
+impl Context for PostProcessError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Mkv,
+    Mp4,
+}
+
+impl Container {
+    fn extension(&self) -> &'static str {
+        match self {
+            Container::Mkv => "mkv",
+            Container::Mp4 => "mp4",
+        }
+    }
+
+    fn subtitle_codec(&self) -> &'static str {
+        match self {
+            Container::Mkv => "srt",
+            Container::Mp4 => "mov_text",
+        }
+    }
+}
+
+/// Runs every downloaded episode through ffmpeg.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostProcessConfig {
+    pub enabled: bool,
+    /// The ffmpeg binary, looked up on the `PATH` unless it's a path.
+    pub ffmpeg: PathBuf,
+    pub container: Container,
+    /// e.g. "libx265", the video stream is copied as is when unset.
+    pub video_codec: Option<String>,
+    /// e.g. "2M", only used when transcoding.
+    pub video_bitrate: Option<String>,
+    /// e.g. "libopus", the audio stream is copied as is when unset.
+    pub audio_codec: Option<String>,
+    pub audio_bitrate: Option<String>,
+    /// Embeds the show's poster as cover art.
+    pub embed_cover: bool,
+    /// Keeps the file as it was downloaded next to the processed one, as
+    /// "Episode 1.original.mp4" when both would have the same name.
+    pub keep_original: bool,
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            container: Container::Mkv,
+            video_codec: None,
+            video_bitrate: None,
+            audio_codec: None,
+            audio_bitrate: None,
+            embed_cover: true,
+            keep_original: false,
+        }
+    }
+}
+
+impl PostProcessConfig {
+    /// Checks that ffmpeg can be run at all.
+    pub fn check_ffmpeg(&self) -> std::io::Result<()> {
+        let output = std::process::Command::new(&self.ffmpeg)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !output.success() {
+            return Err(std::io::Error::other(format!(
+                "`-version` exited with {}",
+                output
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What's written into the container's tags.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EpisodeMetadata {
+    pub show: String,
+    pub episode: Option<String>,
+    pub cover_url: Option<String>,
+}
+
+impl EpisodeMetadata {
+    pub fn title(&self) -> String {
+        match &self.episode {
+            Some(episode) => format!("{} - Episode {}", self.show, episode),
+            None => self.show.clone(),
+        }
+    }
+}
+
+/// Remuxes (and if configured transcodes) `input` and returns the path of the
+/// processed file. `subtitles` are muxed in as extra tracks and removed once
+/// they are. Progress is reported on `pb` in milliseconds of video.
+pub async fn post_process(
+    config: &PostProcessConfig,
+    client: &Client,
+    input: &Path,
+    metadata: &EpisodeMetadata,
+    subtitles: &[SavedSubtitle],
+    pb: &ProgressBar,
+) -> Result<PathBuf, Report<PostProcessError>> {
+    let output = input.with_extension(config.container.extension());
+    let tmp_output = input.with_extension(format!("part.{}", config.container.extension()));
+    let cover = match (&metadata.cover_url, config.embed_cover) {
+        (Some(url), true) => download_cover(client, url, input).await,
+        _ => None,
+    };
+
+    let mut command = Command::new(&config.ffmpeg);
+    command
+        .args(["-hide_banner", "-nostdin", "-y", "-i"])
+        .arg(input);
+    let mut inputs = 1;
+    if let (Some(cover), Container::Mp4) = (&cover, config.container) {
+        command.arg("-i").arg(cover);
+        inputs += 1;
+    }
+    for subtitle in subtitles {
+        command.arg("-i").arg(&subtitle.path);
+    }
+    // Data streams (e.g. timecodes) aren't carried over, MKV can't hold them.
+    command.args(["-map", "0:v", "-map", "0:a?", "-map", "0:s?"]);
+    // Downloaded videos come without subtitle streams of their own, so the
+    // added tracks are numbered from 0.
+    for (track, subtitle) in subtitles.iter().enumerate() {
+        command
+            .arg("-map")
+            .arg((inputs + track).to_string())
+            .arg(format!("-metadata:s:s:{}", track))
+            .arg(format!("language={}", subtitle.language));
+    }
+
+    match &config.video_codec {
+        Some(codec) => {
+            command.args(["-c:v", codec]);
+            if let Some(bitrate) = &config.video_bitrate {
+                command.args(["-b:v", bitrate]);
+            }
+        }
+        None => {
+            command.args(["-c:v", "copy"]);
+        }
+    }
+    match &config.audio_codec {
+        Some(codec) => {
+            command.args(["-c:a", codec]);
+            if let Some(bitrate) = &config.audio_bitrate {
+                command.args(["-b:a", bitrate]);
+            }
+        }
+        None => {
+            command.args(["-c:a", "copy"]);
+        }
+    }
+    command.args(["-c:s", config.container.subtitle_codec()]);
+
+    command
+        .arg("-metadata")
+        .arg(format!("title={}", metadata.title()));
+    command
+        .arg("-metadata")
+        .arg(format!("show={}", metadata.show));
+    if let Some(episode) = &metadata.episode {
+        command
+            .arg("-metadata")
+            .arg(format!("episode_id={}", episode));
+        if episode.parse::<u32>().is_ok() {
+            command
+                .arg("-metadata")
+                .arg(format!("episode_sort={}", episode));
+        }
+    }
+
+    if let Some(cover) = &cover {
+        match config.container {
+            // Matroska keeps cover art as an attachment...
+            Container::Mkv => {
+                command
+                    .arg("-attach")
+                    .arg(cover)
+                    .args(["-metadata:s:t", "mimetype=image/jpeg"])
+                    .args(["-metadata:s:t", "filename=cover.jpg"]);
+            }
+            // ...and MP4 as an extra video stream marked as the poster.
+            Container::Mp4 => {
+                command.args([
+                    "-map",
+                    "1",
+                    "-c:v:1",
+                    "mjpeg",
+                    "-disposition:v:1",
+                    "attached_pic",
+                ]);
+            }
+        }
+    }
+    command
+        .args(["-progress", "pipe:1", "-nostats"])
+        .arg(&tmp_output)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let result = run_ffmpeg(command, metadata, pb).await;
+    if let Some(cover) = &cover {
+        let _ = tokio::fs::remove_file(cover).await;
+    }
+    if let Err(report) = result {
+        let _ = tokio::fs::remove_file(&tmp_output).await;
+        return Err(report.attach_printable(format!("while processing {}", input.display())));
+    }
+
+    if !config.keep_original && output != input {
+        tokio::fs::remove_file(input)
+            .await
+            .change_context(PostProcessError)
+            .attach_printable_lazy(|| format!("failed to remove {}", input.display()))?;
+    } else if config.keep_original && output == input {
+        let original = input.with_extension(format!("original.{}", config.container.extension()));
+        tokio::fs::rename(input, &original)
+            .await
+            .change_context(PostProcessError)
+            .attach_printable_lazy(|| format!("failed to move {}", input.display()))?;
+    }
+    tokio::fs::rename(&tmp_output, &output)
+        .await
+        .change_context(PostProcessError)
+        .attach_printable_lazy(|| format!("failed to move the result to {}", output.display()))?;
+    for subtitle in subtitles {
+        let _ = tokio::fs::remove_file(&subtitle.path).await;
+    }
+    pb.finish_with_message(format!("Processed {}", metadata.title()));
+    Ok(output)
+}
+
+async fn run_ffmpeg(
+    mut command: Command,
+    metadata: &EpisodeMetadata,
+    pb: &ProgressBar,
+) -> Result<(), Report<PostProcessError>> {
+    let mut child = command
+        .spawn()
+        .change_context(PostProcessError)
+        .attach_printable("failed to start ffmpeg, is it installed?")?;
+
+    pb.set_message(format!("Processing {}", metadata.title()));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.magenta/blue}] {percent}% ({eta})",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+    pb.reset();
+    pb.set_length(0);
+
+    // The length comes from the "Duration:" line ffmpeg logs while reading
+    // the input, the position from `out_time_us` in its progress reports.
+    let stderr = child.stderr.take().unwrap();
+    let stderr_pb = pb.clone();
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut log = Vec::new();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(duration) = parse_duration(&line) {
+                if stderr_pb.length().unwrap_or(0) == 0 {
+                    stderr_pb.set_length(duration);
+                }
+            }
+            log.push(line);
+            if log.len() > 20 {
+                log.remove(0);
+            }
+        }
+        log
+    });
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Some(out_time) = line.strip_prefix("out_time_us=") {
+            if let Ok(us) = out_time.trim().parse::<u64>() {
+                pb.set_position(us / 1000);
+            }
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .change_context(PostProcessError)
+        .attach_printable("failed to wait for ffmpeg")?;
+    let log = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        return Err(Report::new(PostProcessError)
+            .attach_printable(format!("ffmpeg exited with {}", status))
+            .attach_printable(log.join("\n")));
+    }
+    Ok(())
+}
+
+/// "  Duration: 00:23:40.05, start: ..." -> milliseconds
+fn parse_duration(line: &str) -> Option<u64> {
+    let rest = line.trim_start().strip_prefix("Duration: ")?;
+    let timestamp = rest.split(',').next()?;
+    let mut parts = timestamp.split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(((hours * 3600.0 + minutes * 60.0 + seconds) * 1000.0) as u64)
+}
+
+/// Saves the poster next to `episode`, it's deleted again after processing.
+async fn download_cover(client: &Client, url: &str, episode: &Path) -> Option<PathBuf> {
+    let bytes = client
+        .get(url)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .bytes()
+        .await
+        .ok()?;
+    let path = episode.with_extension("cover.jpg");
+    tokio::fs::write(&path, bytes).await.ok()?;
+    Some(path)
+}
@@ -0,0 +1,175 @@
+use console::Term;
+use error_stack::{Context, Report, ResultExt};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{disable_raw_mode, LeaveAlternateScreen};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+use crate::download_manager::QueuedEpisode;
+
+#[derive(Debug)]
+pub struct InterruptedError;
+
+impl fmt::Display for InterruptedError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while accessing the interrupted downloads")
+    }
+}
+
+impl Context for InterruptedError {}
+
+/// Turns SIGINT (Ctrl-C) and SIGTERM into a graceful shutdown: the running
+/// downloads are cancelled, which lets them write and flush what they
+/// received, and no new work is started. A second signal quits right away.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    requested: CancellationToken,
+    downloads: Mutex<Option<CancellationToken>>,
+}
+
+impl Shutdown {
+    /// Installs the signal handlers. From here on signals no longer kill the
+    /// process by themselves.
+    pub fn listen() -> Arc<Self> {
+        let shutdown = Arc::new(Self::default());
+        let listener = shutdown.clone();
+        tokio::spawn(async move {
+            loop {
+                let exit_code = next_signal().await;
+                // Without downloads there's nothing to save, and a second
+                // signal means the user doesn't want to wait for them.
+                let downloads = listener.downloads.lock().unwrap().take();
+                match downloads {
+                    Some(downloads) if !listener.requested.is_cancelled() => {
+                        listener.requested.cancel();
+                        downloads.cancel();
+                    }
+                    _ => force_quit(exit_code),
+                }
+            }
+        });
+        shutdown
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.is_cancelled()
+    }
+
+    /// Resolves once a signal asked to shut down.
+    pub async fn requested(&self) {
+        self.requested.cancelled().await
+    }
+
+    /// Cancels `downloads` on the first signal until `downloads_finished`.
+    pub fn downloads_started(&self, downloads: CancellationToken) {
+        *self.downloads.lock().unwrap() = Some(downloads);
+    }
+
+    pub fn downloads_finished(&self) {
+        self.downloads.lock().unwrap().take();
+    }
+}
+
+/// Waits for SIGINT or SIGTERM and returns the exit code it calls for.
+#[cfg(unix)]
+async fn next_signal() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+            return 130;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => 130,
+        _ = terminate.recv() => 143,
+    }
+}
+
+#[cfg(not(unix))]
+async fn next_signal() -> i32 {
+    let _ = tokio::signal::ctrl_c().await;
+    130
+}
+
+fn force_quit(exit_code: i32) -> ! {
+    restore_terminal();
+    std::process::exit(exit_code)
+}
+
+/// Undoes what an unfinished prompt or the progress bars did to the terminal.
+pub fn restore_terminal() {
+    // indicatif hides the cursor while drawing the bars, and the dashboard
+    // and the prompts run in raw mode, the dashboard on the alternate screen.
+    let _ = disable_raw_mode();
+    let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+    let _ = Term::stdout().show_cursor();
+    let _ = Term::stderr().show_cursor();
+}
+
+/// The episodes a shutdown cancelled, offered to be downloaded again on the
+/// next start.
+#[derive(Debug)]
+pub struct InterruptedDownloads {
+    path: PathBuf,
+}
+
+impl InterruptedDownloads {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("rusty-gogo-dl")
+            .join("interrupted.json")
+    }
+
+    pub fn load(&self) -> Result<Vec<QueuedEpisode>, Report<InterruptedError>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(Report::new(err)
+                    .change_context(InterruptedError)
+                    .attach_printable(format!("Failed to read {}", self.path.display())))
+            }
+        };
+        serde_json::from_str(&contents)
+            .change_context(InterruptedError)
+            .attach_printable_lazy(|| format!("{} is corrupted", self.path.display()))
+    }
+
+    /// Adds `episodes` to the ones saved before.
+    pub fn save(&self, episodes: &[QueuedEpisode]) -> Result<(), Report<InterruptedError>> {
+        let mut saved = self.load().unwrap_or_default();
+        for episode in episodes {
+            if !saved.iter().any(|known| known.ep_path == episode.ep_path) {
+                saved.push(episode.clone());
+            }
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .change_context(InterruptedError)
+                .attach_printable_lazy(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let contents = serde_json::to_string_pretty(&saved).change_context(InterruptedError)?;
+        std::fs::write(&self.path, contents)
+            .change_context(InterruptedError)
+            .attach_printable_lazy(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn clear(&self) -> Result<(), Report<InterruptedError>> {
+        match std::fs::remove_file(&self.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(Report::new(err)
+                .change_context(InterruptedError)
+                .attach_printable(format!("Failed to remove {}", self.path.display()))),
+            _ => Ok(()),
+        }
+    }
+}
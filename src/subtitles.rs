@@ -0,0 +1,324 @@
+use error_stack::{Context, Report, ResultExt};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::anime_downloader::gogo::ResolvedStream;
+use crate::anime_downloader::streaming::SubtitleTrack;
+use crate::hls;
+
+#[derive(Debug)]
+pub struct SubtitleError;
+
+impl fmt::Display for SubtitleError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Error while downloading subtitles")
+    }
+}
+
+impl Context for SubtitleError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    /// Keep whatever the server sent.
+    Original,
+    /// Convert WebVTT to SubRip, which more players understand.
+    Srt,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubtitlesConfig {
+    /// Saves the subtitle tracks of streamed episodes next to the video.
+    pub download: bool,
+    pub format: SubtitleFormat,
+    /// Language codes to keep, e.g. ["en"], every track when empty.
+    pub languages: Vec<String>,
+    /// Muxes the tracks into the video during post-processing instead of
+    /// keeping them as separate files.
+    pub embed: bool,
+}
+
+impl Default for SubtitlesConfig {
+    fn default() -> Self {
+        Self {
+            download: true,
+            format: SubtitleFormat::Srt,
+            languages: Vec::new(),
+            embed: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SavedSubtitle {
+    pub language: String,
+    pub path: PathBuf,
+}
+
+/// Saves the subtitle tracks of `stream` next to `video`, named like
+/// `Episode.en.srt`. Tracks that fail are reported but don't stop the rest.
+pub async fn save_subtitles(
+    config: &SubtitlesConfig,
+    client: &Client,
+    stream: &ResolvedStream,
+    video: &Path,
+) -> (Vec<SavedSubtitle>, Vec<Report<SubtitleError>>) {
+    let mut tracks = stream.subtitles.clone();
+    for track in hls::subtitle_tracks(client, stream).await {
+        if !tracks.iter().any(|known| known.language == track.language) {
+            tracks.push(track);
+        }
+    }
+    if !config.languages.is_empty() {
+        tracks.retain(|track| {
+            config
+                .languages
+                .iter()
+                .any(|language| language.eq_ignore_ascii_case(&track.language))
+        });
+    }
+
+    let mut saved: Vec<SavedSubtitle> = Vec::new();
+    let mut errors = Vec::new();
+    for track in tracks {
+        // Two tracks of the same language (e.g. full and signs only) get a
+        // counter so they don't overwrite each other.
+        let taken = saved
+            .iter()
+            .filter(|subtitle| subtitle.language == track.language)
+            .count();
+        let suffix = match taken {
+            0 => track.language.clone(),
+            n => format!("{}{}", track.language, n + 1),
+        };
+        match save_track(config, client, stream, &track, video, &suffix).await {
+            Ok(path) => saved.push(SavedSubtitle {
+                language: track.language,
+                path,
+            }),
+            Err(err) => errors.push(err),
+        }
+    }
+    (saved, errors)
+}
+
+async fn save_track(
+    config: &SubtitlesConfig,
+    client: &Client,
+    stream: &ResolvedStream,
+    track: &SubtitleTrack,
+    video: &Path,
+    suffix: &str,
+) -> Result<PathBuf, Report<SubtitleError>> {
+    let contents = if track.url.contains(".m3u8") {
+        hls::fetch_subtitle_playlist(client, stream, &track.url)
+            .await
+            .change_context(SubtitleError)?
+    } else {
+        client
+            .get(&track.url)
+            .header("Referer", &stream.referer)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .change_context(SubtitleError)
+            .attach_printable_lazy(|| format!("Failed to request {}", track.url))?
+            .text()
+            .await
+            .change_context(SubtitleError)?
+    };
+
+    let is_vtt = contents
+        .trim_start_matches('\u{feff}')
+        .starts_with("WEBVTT");
+    let (contents, extension) = match (is_vtt, config.format) {
+        (true, SubtitleFormat::Srt) => (vtt_to_srt(&contents), "srt"),
+        (true, SubtitleFormat::Original) => (contents, "vtt"),
+        (false, _) => (contents, "srt"),
+    };
+    let path = video.with_extension(format!("{}.{}", suffix, extension));
+    tokio::fs::write(&path, contents)
+        .await
+        .change_context(SubtitleError)
+        .attach_printable_lazy(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Converts WebVTT to SubRip: numbers the cues, turns `00:01.500` into
+/// `00:00:01,500` and drops cue settings, styles, notes and tags SubRip
+/// doesn't know.
+pub fn vtt_to_srt(vtt: &str) -> String {
+    let vtt = vtt.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut srt = String::new();
+    let mut cue_number = 0;
+    for block in vtt.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+        let Some(first) = lines.next() else {
+            continue;
+        };
+        // Cues may start with an identifier before their timing line.
+        let timing = if first.contains("-->") {
+            first
+        } else {
+            match lines.next() {
+                Some(line) if line.contains("-->") => line,
+                _ => continue, // WEBVTT header, NOTE, STYLE or REGION block
+            }
+        };
+        let Some((start, end)) = timing.split_once("-->") else {
+            continue;
+        };
+        let end = end.split_whitespace().next().unwrap_or("");
+        let (Some(start), Some(end)) = (srt_timestamp(start.trim()), srt_timestamp(end)) else {
+            continue;
+        };
+
+        cue_number += 1;
+        srt.push_str(&format!("{}\n{} --> {}\n", cue_number, start, end));
+        for line in lines {
+            srt.push_str(&strip_vtt_tags(line));
+            srt.push('\n');
+        }
+        srt.push('\n');
+    }
+    srt
+}
+
+/// "01:02.345" or "1:01:02.345" -> "01:01:02,345"
+fn srt_timestamp(timestamp: &str) -> Option<String> {
+    let (time, millis) = timestamp.split_once('.')?;
+    let parts: Vec<u32> = time
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [minutes, seconds] => (0, minutes, seconds),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return None,
+    };
+    Some(format!(
+        "{:02}:{:02}:{:02},{:0<3}",
+        hours, minutes, seconds, millis
+    ))
+}
+
+/// Keeps `<i>`, `<b>` and `<u>` without their classes, drops voice, class
+/// and timestamp tags.
+fn strip_vtt_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            text.push_str(&rest[start..]);
+            return text;
+        };
+        let tag = &rest[start..start + end + 1];
+        let name = tag
+            .trim_start_matches(['<', '/'])
+            .split(['.', ' ', '>'])
+            .next()
+            .unwrap_or("");
+        if matches!(name, "i" | "b" | "u") {
+            let close = if tag.starts_with("</") { "/" } else { "" };
+            text.push_str(&format!("<{}{}>", close, name));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    text.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VTT: &str = "WEBVTT
+Kind: captions
+Language: en
+
+STYLE
+::cue(.yellow) { color: yellow; }
+
+NOTE This cue was translated
+by the fansub group
+
+intro
+00:01.500 --> 00:04.000 line:85% align:center
+<v Narrator>Long ago, the <c.yellow>nine-tailed fox</c> attacked.
+
+00:04.250 --> 00:06.000
+<i>Naruto!</i>
+Tom &amp; Jerry &lt;3
+
+1:02:03.004 --> 1:02:05.120
+<b>Believe it!</b> <00:00:01.000>dattebayo
+";
+
+    const SRT: &str = "1
+00:00:01,500 --> 00:00:04,000
+Long ago, the nine-tailed fox attacked.
+
+2
+00:00:04,250 --> 00:00:06,000
+<i>Naruto!</i>
+Tom & Jerry <3
+
+3
+01:02:03,004 --> 01:02:05,120
+<b>Believe it!</b> dattebayo
+
+";
+
+    #[test]
+    fn converts_cues_to_srt() {
+        assert_eq!(vtt_to_srt(VTT), SRT);
+    }
+
+    #[test]
+    fn converts_crlf_and_bom() {
+        let vtt = format!("\u{feff}{}", VTT.replace('\n', "\r\n"));
+        assert_eq!(vtt_to_srt(&vtt), SRT);
+    }
+
+    #[test]
+    fn skips_cues_with_invalid_timings() {
+        let vtt = "WEBVTT\n\n00:01 --> 00:02.000\nno millis\n\n00:03.000 --> 00:04.000\nkept\n";
+        assert_eq!(
+            vtt_to_srt(vtt),
+            "1\n00:00:03,000 --> 00:00:04,000\nkept\n\n"
+        );
+    }
+
+    #[test]
+    fn pads_timestamps() {
+        assert_eq!(srt_timestamp("01:02.345").as_deref(), Some("00:01:02,345"));
+        assert_eq!(
+            srt_timestamp("1:01:02.345").as_deref(),
+            Some("01:01:02,345")
+        );
+        assert_eq!(srt_timestamp("00:05.5").as_deref(), Some("00:00:05,500"));
+        assert_eq!(srt_timestamp("05.500"), None);
+        assert_eq!(srt_timestamp("1:2:3:4.000"), None);
+        assert_eq!(srt_timestamp("aa:05.500"), None);
+    }
+
+    #[test]
+    fn keeps_only_srt_tags() {
+        assert_eq!(
+            strip_vtt_tags("<i>a</i> <b>b</b> <u>c</u>"),
+            "<i>a</i> <b>b</b> <u>c</u>"
+        );
+        assert_eq!(strip_vtt_tags("<i.loud>a</i>"), "<i>a</i>");
+        assert_eq!(strip_vtt_tags("<v.first Sasuke>Hmph.</v>"), "Hmph.");
+        assert_eq!(strip_vtt_tags("<ruby>漢<rt>kan</rt></ruby>"), "漢kan");
+        assert_eq!(strip_vtt_tags("1 < 2"), "1 < 2");
+        assert_eq!(strip_vtt_tags("a&nbsp;b &gt; c"), "a b > c");
+    }
+}
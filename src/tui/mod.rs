@@ -0,0 +1,565 @@
+//! The full-screen dashboard: search and queue episodes while others download.
+
+mod ui;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::widgets::{ListState, TableState};
+use ratatui::Terminal;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io::{self, Stdout};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::anime_downloader::gogo::{
+    Anime, AnimeDetailedInfo, GogoAnime, SearchFilters, SearchSort,
+};
+use crate::config::Config;
+use crate::download_manager::{ConcurrentDownloadManager, DownloadError, DownloadStatus};
+use crate::jobs::{JobHandle, JobState};
+use crate::shutdown::{InterruptedDownloads, Shutdown};
+use crate::utils;
+use error_stack::Report;
+
+const TICK: Duration = Duration::from_millis(250);
+const MAX_LOG_LINES: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Search,
+    Results,
+    Details,
+    Queue,
+}
+
+impl Focus {
+    fn next(self) -> Self {
+        match self {
+            Focus::Search => Focus::Results,
+            Focus::Results => Focus::Details,
+            Focus::Details => Focus::Queue,
+            Focus::Queue => Focus::Search,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Focus::Search => Focus::Queue,
+            Focus::Results => Focus::Search,
+            Focus::Details => Focus::Results,
+            Focus::Queue => Focus::Details,
+        }
+    }
+
+    /// Whether typed characters go into a text field.
+    fn takes_text(self) -> bool {
+        matches!(self, Focus::Search | Focus::Details)
+    }
+}
+
+/// Results of the requests that run in the background.
+enum Message {
+    Search {
+        page: usize,
+        result: Result<(Vec<Anime>, bool), String>,
+    },
+    Details(Result<AnimeDetailedInfo, String>),
+}
+
+struct App {
+    focus: Focus,
+    query: String,
+    results: Vec<Anime>,
+    results_state: ListState,
+    /// The page to load next, if there is one.
+    next_page: Option<usize>,
+    loading: Option<String>,
+    details: Option<AnimeDetailedInfo>,
+    episodes_input: String,
+    queue_state: TableState,
+    /// How each finished job ended, by episode path.
+    outcomes: HashMap<String, String>,
+    logs: VecDeque<String>,
+    download_manager: ConcurrentDownloadManager,
+    quitting: bool,
+    started: Instant,
+}
+
+impl App {
+    fn log(&mut self, line: impl Into<String>) {
+        let elapsed = self.started.elapsed().as_secs();
+        self.logs.push_back(format!(
+            "[{:02}:{:02}:{:02}] {}",
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60,
+            line.into()
+        ));
+        while self.logs.len() > MAX_LOG_LINES {
+            self.logs.pop_front();
+        }
+    }
+
+    fn has_unfinished_jobs(&self) -> bool {
+        self.download_manager
+            .jobs()
+            .iter()
+            .any(|job| job.state() != JobState::Finished)
+    }
+
+    fn selected_job(&self) -> Option<&JobHandle> {
+        self.queue_state
+            .selected()
+            .and_then(|idx| self.download_manager.jobs().get(idx))
+    }
+}
+
+/// Runs the dashboard until the user quits. Episodes still downloading then
+/// are cancelled and offered again on the next start.
+pub async fn run(
+    config: &Config,
+    gogo_anime: Arc<GogoAnime>,
+    download_manager: ConcurrentDownloadManager,
+    search_filters: SearchFilters,
+    shutdown: &Shutdown,
+    interrupted: &InterruptedDownloads,
+) -> Result<(), Box<dyn Error>> {
+    let mut app = App {
+        focus: Focus::Search,
+        query: String::new(),
+        results: Vec::new(),
+        results_state: ListState::default(),
+        next_page: None,
+        loading: None,
+        details: None,
+        episodes_input: String::new(),
+        queue_state: TableState::default(),
+        outcomes: HashMap::new(),
+        logs: VecDeque::new(),
+        download_manager: download_manager.with_hidden_progress(),
+        quitting: false,
+        started: Instant::now(),
+    };
+    shutdown.downloads_started(app.download_manager.cancellation_token());
+
+    match interrupted.load() {
+        Ok(episodes) if !episodes.is_empty() => {
+            interrupted.clear()?;
+            app.log(format!(
+                "Resuming {} episode(s) interrupted last time",
+                episodes.len()
+            ));
+            for episode in episodes {
+                app.download_manager.add_episode(
+                    gogo_anime.clone(),
+                    &config.preferred_res,
+                    episode,
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(report) => app.log(utils::report_summary(&report)),
+    }
+
+    let mut terminal = TerminalGuard::enter()?;
+    let (keys, mut key_events) = mpsc::unbounded_channel();
+    spawn_key_reader(keys);
+    let (messages, mut message_events) = mpsc::unbounded_channel();
+    let mut tick = tokio::time::interval(TICK);
+    let mut interrupted_episodes = Vec::new();
+    let mut forced_quit = false;
+
+    loop {
+        terminal.draw(|frame| ui::draw(frame, &mut app))?;
+        tokio::select! {
+            Some(key) = key_events.recv() => {
+                if handle_key(&mut app, key, config, &gogo_anime, &search_filters, &messages) {
+                    // A second quit doesn't wait for the downloads.
+                    forced_quit = true;
+                    break;
+                }
+            }
+            Some(message) = message_events.recv() => {
+                handle_message(&mut app, message, search_filters.sort)
+            }
+            _ = tick.tick() => {}
+        }
+
+        let finished = app.download_manager.take_finished().await;
+        if app.quitting {
+            interrupted_episodes.extend(app.download_manager.cancelled_episodes(&finished));
+        }
+        for (path, result) in finished.into_values() {
+            record_result(&mut app, path, result);
+        }
+        if shutdown.is_requested() && !app.quitting {
+            app.quitting = true;
+            app.log("Shutting down, waiting for the downloads to save their progress");
+        }
+        if app.quitting && !app.has_unfinished_jobs() {
+            break;
+        }
+    }
+
+    drop(terminal);
+    if !forced_quit {
+        // The last job may still be running the batch hook.
+        let finished = app.download_manager.await_results().await;
+        interrupted_episodes.extend(app.download_manager.cancelled_episodes(&finished));
+    }
+    shutdown.downloads_finished();
+    // After a forced quit some are still running, they're interrupted too.
+    interrupted_episodes.extend(app.download_manager.unfinished_episodes());
+    if !interrupted_episodes.is_empty() {
+        interrupted.save(&interrupted_episodes)?;
+        println!(
+            "{} interrupted episode(s) will be offered again next time",
+            interrupted_episodes.len()
+        );
+    }
+    Ok(())
+}
+
+fn record_result(
+    app: &mut App,
+    path: String,
+    result: Result<DownloadStatus, Report<DownloadError>>,
+) {
+    let (outcome, line) = match &result {
+        Ok(DownloadStatus::Downloaded { path: file, source }) => (
+            "done".to_string(),
+            format!("Downloaded {} ({})", file.display(), source),
+        ),
+        Ok(DownloadStatus::Skipped(existing)) => (
+            "skipped".to_string(),
+            format!("Skipped {}, already at {}", path, existing.display()),
+        ),
+        Ok(DownloadStatus::Cancelled(_)) => {
+            ("cancelled".to_string(), format!("Cancelled {}", path))
+        }
+        Err(report) => (
+            "failed".to_string(),
+            format!("Failed {}: {}", path, utils::report_summary(report)),
+        ),
+    };
+    app.log(line);
+    app.outcomes.insert(path, outcome);
+}
+
+/// Returns whether the dashboard should close right away.
+fn handle_key(
+    app: &mut App,
+    key: KeyEvent,
+    config: &Config,
+    gogo_anime: &Arc<GogoAnime>,
+    search_filters: &SearchFilters,
+    messages: &UnboundedSender<Message>,
+) -> bool {
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    if ctrl_c || (key.code == KeyCode::Char('q') && !app.focus.takes_text()) {
+        if app.quitting {
+            return true;
+        }
+        app.quitting = true;
+        app.download_manager.cancel_all();
+        app.log(
+            "Quitting, waiting for the downloads to save their progress (press again to quit now)",
+        );
+        return false;
+    }
+
+    match key.code {
+        KeyCode::Tab => {
+            app.focus = app.focus.next();
+            return false;
+        }
+        KeyCode::BackTab => {
+            app.focus = app.focus.previous();
+            return false;
+        }
+        _ => {}
+    }
+
+    match app.focus {
+        Focus::Search => match key.code {
+            KeyCode::Char(c) => app.query.push(c),
+            KeyCode::Backspace => {
+                app.query.pop();
+            }
+            KeyCode::Down => app.focus = Focus::Results,
+            KeyCode::Enter if !app.query.trim().is_empty() => {
+                app.results.clear();
+                app.results_state.select(None);
+                search(app, gogo_anime, search_filters, 1, messages);
+            }
+            _ => {}
+        },
+        Focus::Results => match key.code {
+            KeyCode::Up => app
+                .results_state
+                .select(previous(app.results_state.selected())),
+            KeyCode::Down => app
+                .results_state
+                .select(next(app.results_state.selected(), app.results.len())),
+            KeyCode::Char('n') => {
+                if let Some(page) = app.next_page {
+                    search(app, gogo_anime, search_filters, page, messages);
+                }
+            }
+            KeyCode::Esc => app.focus = Focus::Search,
+            KeyCode::Enter => {
+                if let Some(anime) = app
+                    .results_state
+                    .selected()
+                    .and_then(|idx| app.results.get(idx))
+                {
+                    fetch_details(app, anime.clone(), config, gogo_anime, messages);
+                }
+            }
+            _ => {}
+        },
+        Focus::Details => match key.code {
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '-' || c == ' ' => {
+                app.episodes_input.push(c)
+            }
+            KeyCode::Backspace => {
+                app.episodes_input.pop();
+            }
+            KeyCode::Esc => app.focus = Focus::Results,
+            KeyCode::Enter => queue_episodes(app, config, gogo_anime),
+            _ => {}
+        },
+        Focus::Queue => {
+            let job = app.selected_job().cloned();
+            match (key.code, job) {
+                (KeyCode::Up, _) => app.queue_state.select(previous(app.queue_state.selected())),
+                (KeyCode::Down, _) => app.queue_state.select(next(
+                    app.queue_state.selected(),
+                    app.download_manager.jobs().len(),
+                )),
+                (KeyCode::Char('p'), Some(job)) if job.is_paused() => {
+                    job.resume();
+                    app.log(format!("Resumed {}", job.name()));
+                }
+                (KeyCode::Char('p'), Some(job)) => {
+                    job.pause();
+                    app.log(format!("Paused {}", job.name()));
+                }
+                (KeyCode::Char('c'), Some(job)) => {
+                    job.cancel();
+                    app.log(format!("Cancelling {}", job.name()));
+                }
+                (KeyCode::Char('u'), Some(job)) => job.set_priority(job.priority() + 1),
+                (KeyCode::Char('d'), Some(job)) => job.set_priority(job.priority() - 1),
+                (KeyCode::Char('+'), _) => {
+                    let concurrency = app.download_manager.concurrency() + 1;
+                    app.download_manager.set_concurrency(concurrency);
+                    app.log(format!("Downloading {} episodes at once", concurrency));
+                }
+                (KeyCode::Char('-'), _) => {
+                    let concurrency = app.download_manager.concurrency().saturating_sub(1);
+                    app.download_manager.set_concurrency(concurrency);
+                    app.log(format!(
+                        "Downloading {} episodes at once",
+                        app.download_manager.concurrency()
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+fn handle_message(app: &mut App, message: Message, sort: SearchSort) {
+    app.loading = None;
+    match message {
+        Message::Search { page, result } => match result {
+            Ok((results, has_next_page)) => {
+                if results.is_empty() && page == 1 {
+                    app.log(format!("No results for \"{}\"", app.query));
+                }
+                // Sorting the pages together moves the selected anime, the
+                // selection follows it.
+                let selected = app
+                    .results_state
+                    .selected()
+                    .and_then(|index| app.results.get(index))
+                    .map(|anime| anime.url.clone());
+                app.results.extend(results);
+                sort.apply(&mut app.results);
+                if let Some(url) = selected {
+                    let index = app.results.iter().position(|anime| anime.url == url);
+                    app.results_state.select(index);
+                }
+                app.next_page = has_next_page.then_some(page + 1);
+                if app.results_state.selected().is_none() && !app.results.is_empty() {
+                    app.results_state.select(Some(0));
+                    app.focus = Focus::Results;
+                }
+            }
+            Err(err) => app.log(err),
+        },
+        Message::Details(result) => match result {
+            Ok(details) => {
+                app.episodes_input = format!("1-{}", details.episode_links.len());
+                app.details = Some(details);
+                app.focus = Focus::Details;
+            }
+            Err(err) => app.log(err),
+        },
+    }
+}
+
+fn search(
+    app: &mut App,
+    gogo_anime: &Arc<GogoAnime>,
+    search_filters: &SearchFilters,
+    page: usize,
+    messages: &UnboundedSender<Message>,
+) {
+    app.loading = Some(format!("Searching \"{}\"...", app.query));
+    let gogo_anime = gogo_anime.clone();
+    let query = app.query.trim().to_string();
+    let filters = search_filters.clone();
+    let messages = messages.clone();
+    tokio::spawn(async move {
+        let result = gogo_anime
+            .search_anime_page(&query, &filters, page)
+            .await
+            .map(|page| (page.results, page.has_next_page))
+            .map_err(|report| utils::report_summary(&report));
+        let _ = messages.send(Message::Search { page, result });
+    });
+}
+
+fn fetch_details(
+    app: &mut App,
+    anime: Anime,
+    config: &Config,
+    gogo_anime: &Arc<GogoAnime>,
+    messages: &UnboundedSender<Message>,
+) {
+    app.loading = Some(format!("Loading {}...", anime.name));
+    let gogo_anime = gogo_anime.clone();
+    let preferred_language = config.preferred_language;
+    let messages = messages.clone();
+    tokio::spawn(async move {
+        let anime = match preferred_language {
+            Some(language) if anime.language != language => gogo_anime
+                .find_variant(&anime, language)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or(anime),
+            _ => anime,
+        };
+        let result = gogo_anime
+            .fetch_detailed_anime_info(&anime.url)
+            .await
+            .map_err(|report| utils::report_summary(&report));
+        let _ = messages.send(Message::Details(result));
+    });
+}
+
+fn queue_episodes(app: &mut App, config: &Config, gogo_anime: &Arc<GogoAnime>) {
+    if app.quitting {
+        return;
+    }
+    let Some(details) = &app.details else {
+        return;
+    };
+    let Some((start, end)) =
+        utils::parse_episode_range(&app.episodes_input, details.episode_links.len())
+    else {
+        app.log(format!(
+            "Expected an episode or a range like 1-{}",
+            details.episode_links.len()
+        ));
+        return;
+    };
+
+    let mut queued = 0;
+    for link in &details.episode_links[start - 1..end] {
+        let ep_path = utils::episode_path(&config.download_folder, details, link);
+        let job = app.download_manager.add_gogo_download(
+            gogo_anime.clone(),
+            details,
+            &config.preferred_res,
+            &ep_path,
+            link,
+        );
+        queued += usize::from(job.is_some());
+    }
+    let name = details.name.clone();
+    app.log(format!("Queued {} episode(s) of {}", queued, name));
+    if app.queue_state.selected().is_none() && !app.download_manager.jobs().is_empty() {
+        app.queue_state.select(Some(0));
+    }
+}
+
+fn previous(selected: Option<usize>) -> Option<usize> {
+    Some(selected.map_or(0, |idx| idx.saturating_sub(1)))
+}
+
+fn next(selected: Option<usize>, len: usize) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some(selected.map_or(0, |idx| (idx + 1).min(len - 1)))
+}
+
+/// Reads key presses on a thread of its own, crossterm's reads block.
+fn spawn_key_reader(keys: UnboundedSender<KeyEvent>) {
+    tokio::task::spawn_blocking(move || {
+        while !keys.is_closed() {
+            match event::poll(TICK) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => {
+                    if keys.send(key).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => return,
+            }
+        }
+    });
+}
+
+/// Switches to the alternate screen in raw mode and back when dropped, so
+/// an error doesn't leave the terminal unusable.
+struct TerminalGuard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        Ok(Self { terminal })
+    }
+
+    fn draw(&mut self, render: impl FnOnce(&mut ratatui::Frame)) -> io::Result<()> {
+        self.terminal.draw(render).map(|_| ())
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
@@ -0,0 +1,251 @@
+use indicatif::{HumanBytes, HumanDuration};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table, Wrap};
+use ratatui::Frame;
+use std::path::Path;
+
+use super::{App, Focus};
+use crate::jobs::{JobHandle, JobState, ProgressUnit};
+
+pub(super) fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, help] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(main);
+    let [search, results, details] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(5),
+        Constraint::Length(10),
+    ])
+    .areas(left);
+    let [queue, logs] =
+        Layout::vertical([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(right);
+
+    draw_search(frame, app, search);
+    draw_results(frame, app, results);
+    draw_details(frame, app, details);
+    draw_queue(frame, app, queue);
+    draw_logs(frame, app, logs);
+    draw_help(frame, app, help);
+}
+
+fn pane(title: String, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(title);
+    if focused {
+        block.border_style(Style::new().fg(Color::Cyan))
+    } else {
+        block
+    }
+}
+
+fn highlight() -> Style {
+    Style::new().add_modifier(Modifier::REVERSED)
+}
+
+fn draw_search(frame: &mut Frame, app: &App, area: Rect) {
+    let focused = app.focus == Focus::Search;
+    let cursor = if focused { "_" } else { "" };
+    let input = Paragraph::new(format!("{}{}", app.query, cursor))
+        .block(pane("Search".to_string(), focused));
+    frame.render_widget(input, area);
+}
+
+fn draw_results(frame: &mut Frame, app: &mut App, area: Rect) {
+    let mut items: Vec<ListItem> = app
+        .results
+        .iter()
+        .map(|anime| ListItem::new(anime.to_string()))
+        .collect();
+    if app.next_page.is_some() {
+        items.push(ListItem::new("Press n to load more results...".dim()));
+    }
+    let list = List::new(items)
+        .block(pane(
+            format!("Results ({})", app.results.len()),
+            app.focus == Focus::Results,
+        ))
+        .highlight_style(highlight());
+    frame.render_stateful_widget(list, area, &mut app.results_state);
+}
+
+fn draw_details(frame: &mut Frame, app: &App, area: Rect) {
+    let focused = app.focus == Focus::Details;
+    let lines = match &app.details {
+        Some(details) => {
+            let mut lines = vec![
+                Line::from(vec!["Name: ".bold(), Span::raw(details.name.clone())]),
+                Line::from(vec![
+                    "Language: ".bold(),
+                    Span::raw(details.language.to_string()),
+                ]),
+            ];
+            let mut about: Vec<_> = details.about.iter().collect();
+            about.sort();
+            for (key, value) in about {
+                lines.push(Line::from(vec![
+                    format!("{}: ", key).bold(),
+                    Span::raw(value.clone()),
+                ]));
+            }
+            lines.push(Line::from(vec![
+                format!("Episodes (1-{}): ", details.episode_links.len()).bold(),
+                Span::raw(format!(
+                    "{}{}",
+                    app.episodes_input,
+                    if focused { "_" } else { "" }
+                )),
+            ]));
+            lines
+        }
+        None => vec![Line::from("Pick an anime from the results".dim())],
+    };
+    let paragraph = Paragraph::new(lines)
+        .wrap(Wrap { trim: true })
+        .block(pane("Details".to_string(), focused));
+    frame.render_widget(paragraph, area);
+}
+
+fn draw_queue(frame: &mut Frame, app: &mut App, area: Rect) {
+    let jobs = app.download_manager.jobs();
+    let count = |state| jobs.iter().filter(|job| job.state() == state).count();
+    let title = format!(
+        "Queue ({} running, {} queued, {} paused, {} at once)",
+        count(JobState::Running),
+        count(JobState::Queued),
+        count(JobState::Paused),
+        app.download_manager.concurrency()
+    );
+
+    let rows: Vec<Row> = jobs
+        .iter()
+        .map(|job| {
+            let status = match job.state() {
+                JobState::Finished => app
+                    .outcomes
+                    .get(job.name())
+                    .cloned()
+                    .unwrap_or_else(|| "finished".to_string()),
+                JobState::Running if job.unit() == ProgressUnit::Processing => {
+                    "processing".to_string()
+                }
+                state => state.to_string(),
+            };
+            let status_style = match status.as_str() {
+                "done" => Style::new().fg(Color::Green),
+                "failed" => Style::new().fg(Color::Red),
+                "paused" | "cancelled" | "skipped" => Style::new().fg(Color::Yellow),
+                _ => Style::new(),
+            };
+            let (speed, eta) = speed_and_eta(job);
+            Row::new(vec![
+                Span::raw(display_name(job.name())),
+                Span::styled(status, status_style),
+                Span::raw(progress(job)),
+                Span::raw(speed),
+                Span::raw(eta),
+                Span::raw(job.retries().to_string()),
+                Span::raw(job.priority().to_string()),
+            ])
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(20),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(8),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "Episode", "Status", "Progress", "Speed", "ETA", "Retries", "Priority",
+        ])
+        .bold(),
+    )
+    .block(pane(title, app.focus == Focus::Queue))
+    .highlight_style(highlight());
+    frame.render_stateful_widget(table, area, &mut app.queue_state);
+}
+
+/// "Show [Sub]/episode-1" of the episode's path.
+fn display_name(ep_path: &str) -> String {
+    let path = Path::new(ep_path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    match path.parent().and_then(|parent| parent.file_name()) {
+        Some(folder) => format!("{}/{}", folder.to_string_lossy(), stem),
+        None => stem.to_string(),
+    }
+}
+
+fn progress(job: &JobHandle) -> String {
+    let pb = job.progress();
+    match pb.length() {
+        Some(length) if length > 0 => {
+            format!("{:.1}%", pb.position() as f64 * 100.0 / length as f64)
+        }
+        _ => String::new(),
+    }
+}
+
+fn speed_and_eta(job: &JobHandle) -> (String, String) {
+    if job.state() != JobState::Running {
+        return (String::new(), String::new());
+    }
+    let pb = job.progress();
+    let speed = match job.unit() {
+        ProgressUnit::Bytes => format!("{}/s", HumanBytes(pb.per_sec() as u64)),
+        ProgressUnit::Segments => format!("{:.1} seg/s", pb.per_sec()),
+        // Milliseconds of video per second.
+        ProgressUnit::Processing => format!("{:.1}x", pb.per_sec() / 1000.0),
+    };
+    let eta = match pb.length() {
+        Some(length) if length > 0 && pb.position() > 0 => HumanDuration(pb.eta()).to_string(),
+        _ => String::new(),
+    };
+    (speed, eta)
+}
+
+fn draw_logs(frame: &mut Frame, app: &App, area: Rect) {
+    // Only the lines that fit, newest at the bottom.
+    let visible = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = app
+        .logs
+        .iter()
+        .skip(app.logs.len().saturating_sub(visible))
+        .map(|line| Line::from(line.as_str()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(pane("Logs".to_string(), false)),
+        area,
+    );
+}
+
+fn draw_help(frame: &mut Frame, app: &App, area: Rect) {
+    let keys = if app.quitting {
+        "Waiting for the downloads to stop, press q again to quit now"
+    } else {
+        match app.focus {
+            Focus::Search => "Enter search · ↓ results · Tab next pane · Ctrl-C quit",
+            Focus::Results => "↑↓ select · Enter details · n more results · Esc search · q quit",
+            Focus::Details => "Type the episodes, e.g. 1-12 · Enter queue them · Esc results",
+            Focus::Queue => {
+                "↑↓ select · p pause/resume · c cancel · u/d priority · +/- concurrency · q quit"
+            }
+        }
+    };
+    let mut spans = vec![Span::raw(keys).dim()];
+    if let Some(loading) = &app.loading {
+        spans.push(Span::raw("  "));
+        spans.push(Span::raw(loading.clone()).fg(Color::Yellow));
+    }
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
+}
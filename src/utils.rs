@@ -1,33 +1,63 @@
+use error_stack::{AttachmentKind, FrameKind, Report};
 use sanitize_filename::sanitize;
 
+use crate::anime_downloader::gogo::AnimeDetailedInfo;
+
 use std::path::Path;
 
 pub fn combine_path(path: &Path, folder: &str, url: &str) -> String {
-    let file_name = sanitize(url.split('/').last().unwrap_or(""));
+    let file_name = sanitize(url.split('/').next_back().unwrap_or(""));
     let combined_path = path.join(sanitize(folder)).join(file_name);
     combined_path.to_string_lossy().to_string()
 }
 
-fn parse_resolution(res: &str) -> (u32, u32) {
-    let parts: Vec<&str> = res.split('x').collect();
-    let width = parts[0].parse::<u32>().unwrap();
-    let height = parts[1].parse::<u32>().unwrap();
-    (width, height)
+/// Where the episode at `ep_url` of `anime` is downloaded to. Shows that were
+/// downloaded before the folders got a language suffix keep their old folder,
+/// named after the show as listed.
+pub fn episode_path(download_folder: &Path, anime: &AnimeDetailedInfo, ep_url: &str) -> String {
+    let folder = anime.folder_name();
+    let folder = if !download_folder.join(sanitize(&folder)).exists()
+        && download_folder.join(sanitize(&anime.name)).is_dir()
+    {
+        anime.name.clone()
+    } else {
+        folder
+    };
+    combine_path(download_folder, &folder, ep_url) + ".mp4"
+}
+
+pub fn parse_resolution(res: &str) -> Option<(u32, u32)> {
+    let (width, height) = res.trim().split_once('x')?;
+    Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?))
 }
 
 fn calculate_distance(res1: (u32, u32), res2: (u32, u32)) -> u32 {
-    let width_diff = (res1.0 as i32 - res2.0 as i32).abs() as u32;
-    let height_diff = (res1.1 as i32 - res2.1 as i32).abs() as u32;
+    let width_diff = (res1.0 as i32 - res2.0 as i32).unsigned_abs();
+    let height_diff = (res1.1 as i32 - res2.1 as i32).unsigned_abs();
     width_diff + height_diff
 }
 
+// Resolutions that can't be parsed are only picked if nothing else is left.
+fn distance_to(res: &str, target: (u32, u32)) -> u32 {
+    parse_resolution(res)
+        .map(|res| calculate_distance(res, target))
+        .unwrap_or(u32::MAX)
+}
+
+/// Sorts `items` by how close their resolution is to `target_res`, keeping
+/// the order of equally close ones.
+pub fn sort_by_resolution<T>(items: &mut [T], target_res: &str, resolution: impl Fn(&T) -> &str) {
+    let target = parse_resolution(target_res).unwrap_or((1280, 720));
+    items.sort_by_key(|item| distance_to(resolution(item), target));
+}
+
 pub fn closest_resolution(res_list: &[&String], target_res: &str) -> String {
-    let target = parse_resolution(target_res);
+    let target = parse_resolution(target_res).unwrap_or((1280, 720));
     let mut closest_res = res_list[0].to_owned();
-    let mut closest_distance = calculate_distance(parse_resolution(&closest_res), target);
+    let mut closest_distance = distance_to(&closest_res, target);
 
-    for res in res_list.iter().skip(1).into_iter() {
-        let current_distance = calculate_distance(parse_resolution(&(*res).clone()), target);
+    for res in res_list.iter().skip(1) {
+        let current_distance = distance_to(res, target);
         if current_distance < closest_distance {
             closest_res = res.to_string();
             closest_distance = current_distance;
@@ -36,3 +66,34 @@ pub fn closest_resolution(res_list: &[&String], target_res: &str) -> String {
 
     closest_res.to_string()
 }
+
+/// The contexts and printable attachments of `report` on a single line,
+/// without the locations and backtraces `{:?}` includes.
+pub fn report_summary<C>(report: &Report<C>) -> String {
+    report
+        .frames()
+        .filter_map(|frame| match frame.kind() {
+            FrameKind::Context(context) => Some(context.to_string()),
+            FrameKind::Attachment(AttachmentKind::Printable(attachment)) => {
+                Some(attachment.to_string())
+            }
+            _ => None,
+        })
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// "5" or "3-12", 1-based and inclusive. Empty means every episode.
+pub fn parse_episode_range(input: &str, episodes: usize) -> Option<(usize, usize)> {
+    let input = input.trim();
+    let (start, end) = if input.is_empty() {
+        (1, episodes)
+    } else if let Some((start, end)) = input.split_once('-') {
+        (start.trim().parse().ok()?, end.trim().parse().ok()?)
+    } else {
+        let episode = input.parse().ok()?;
+        (episode, episode)
+    };
+    (start >= 1 && start <= end && end <= episodes).then_some((start, end))
+}